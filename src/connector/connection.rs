@@ -1,96 +1,325 @@
 use std::fmt::{Debug, Formatter};
-use std::sync::{Arc};
-use std::sync::atomic::AtomicBool;
-use tokio::sync::Mutex;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::task::Id;
 use async_trait::async_trait;
 use once_cell::sync::Lazy;
-use quaint_forked::{pooled::Quaint};
+use quaint_forked::{pooled::{Quaint, PooledConnection}, prelude::Queryable};
 use quaint_forked::connector::start_owned_transaction;
+use quaint_forked::connector::owned_transaction::OwnedTransaction;
+use crate::connector::pool_stats::{PoolCounters, SQLPoolStats};
+use crate::connector::slow_query::SlowQueryOptions;
 use crate::connector::transaction::SQLTransaction;
 use crate::migration::migrate::SQLMigration;
 use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::SQLEscape;
 use crate::url::url_utils;
 use teo_runtime::connection::connection::Connection;
 use teo_result::{Error, Result};
 use teo_runtime::connection::transaction::Transaction;
+use teo_runtime::namespace::Namespace;
 
 pub struct SQLConnection {
     dialect: SQLDialect,
     pool: Quaint,
     memory_mode: bool,
+    schema: Option<String>,
+    comment: Option<String>,
+    slow_query: Option<SlowQueryOptions>,
+    migration_timeout: Option<Duration>,
+    upsert_on_save: bool,
+    namespace: Option<&'static Namespace>,
+    counters: Arc<PoolCounters>,
+    insert_returning: Arc<OnceCell<bool>>,
 }
 
 impl Debug for SQLConnection {
+    // the pool holds the url with its credentials, it's left out
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        f.debug_struct("SQLConnection")
+            .field("dialect", &self.dialect)
+            .field("memory_mode", &self.memory_mode)
+            .field("schema", &self.schema)
+            .finish()
     }
 }
 
 impl SQLConnection {
 
     pub async fn new(dialect: SQLDialect, url: &str, reset: bool) -> Self {
-        SQLMigration::create_database_if_needed(dialect, url, reset).await;
+        Self::new_with_options(dialect, url, reset, true).await
+    }
+
+    // restricted users may lack the privileges to create databases, assume it exists then
+    pub async fn new_with_options(dialect: SQLDialect, url: &str, reset: bool, create_database: bool) -> Self {
+        if create_database {
+            SQLMigration::create_database_if_needed(dialect, url, reset).await;
+        }
         let url = url_utils::normalized_url(dialect, url);
         let pool = Quaint::builder(url.as_str()).unwrap().build();
-        Self { dialect, pool, memory_mode: url.to_string().contains(":memory:") }
-    }
-
-    async fn sqlite_memory_transaction(&self) -> Result<Arc<dyn Transaction>> {
-        let mut connection = UNIQUE_TRANSACTION.lock().await;
-        if connection.is_none() {
-            let result = {
-                let pooled_connection = self.pool.check_out().await;
-                if pooled_connection.is_err() {
-                    Err(Error::new(format!("cannot create pooled connection: {}", pooled_connection.err().unwrap().to_string())))
-                } else {
-                    Ok(Arc::new(SQLTransaction::new(self.dialect, Arc::new(pooled_connection.unwrap()), None)))
-                }
-            }?;
-            *connection = Some(result.clone());
-            Ok(result)
-        } else {
-            Ok(connection.clone().unwrap())
+        let schema = if dialect.is_postgres() { Some(url_utils::psql_schema(&url)) } else { None };
+        Self { dialect, pool, memory_mode: url.to_string().contains(":memory:"), schema, comment: None, slow_query: None, migration_timeout: None, upsert_on_save: false, namespace: None, counters: Arc::new(PoolCounters::default()), insert_returning: Arc::new(OnceCell::new()) }
+    }
+
+    // prepended to generated statements so they can be traced in database logs
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    // statements slower than the threshold are logged at warn, with their plan if asked
+    pub fn set_slow_query_log(&mut self, slow_query: Option<SlowQueryOptions>) {
+        self.slow_query = slow_query;
+    }
+
+    // each migration statement fails with an error naming it once this is exceeded
+    pub fn set_migration_timeout(&mut self, migration_timeout: Option<Duration>) {
+        self.migration_timeout = migration_timeout;
+    }
+
+    // saving a new object updates the row with the same primary key instead of failing,
+    // so seeds can be run again
+    pub fn set_upsert_on_save(&mut self, upsert_on_save: bool) {
+        self.upsert_on_save = upsert_on_save;
+    }
+
+    // migrations check enum defaults against the enums declared here
+    pub fn set_namespace(&mut self, namespace: &'static Namespace) {
+        self.namespace = Some(namespace);
+    }
+
+    pub async fn pool_stats(&self) -> SQLPoolStats {
+        self.counters.stats(self.pool.capacity().await as usize)
+    }
+
+    fn sql_transaction(&self, conn: Arc<PooledConnection>, tran: Option<Arc<OwnedTransaction>>) -> SQLTransaction {
+        let mut transaction = SQLTransaction::new(self.dialect, conn, tran, self.comment.clone(), self.slow_query);
+        transaction.migration_timeout = self.migration_timeout;
+        transaction.upsert_on_save = self.upsert_on_save;
+        transaction.namespace = self.namespace;
+        transaction.insert_returning = self.insert_returning.clone();
+        transaction
+    }
+
+    // pooled connections may have been reset, bare table names must resolve to the configured schema
+    async fn check_out(&self) -> Result<PooledConnection> {
+        let waiting = self.counters.wait();
+        let pooled_connection = self.pool.check_out().await;
+        drop(waiting);
+        let pooled_connection = match pooled_connection {
+            Ok(pooled_connection) => pooled_connection,
+            Err(err) => return Err(Error::new(format!("cannot create pooled connection: {}", err.to_string()))),
+        };
+        if let Some(schema) = &self.schema {
+            let stmt = format!("SET search_path TO {}", schema.escape(self.dialect));
+            if let Err(err) = pooled_connection.raw_cmd(&stmt).await {
+                return Err(Error::new(format!("cannot set search path: {}", err.to_string())));
+            }
+        }
+        Ok(pooled_connection)
+    }
+
+    // every handle shares the single in-memory connection but owns its committed
+    // flag, committing or aborting one handle never marks the others. one connection
+    // has one transaction at a time, so transactions wait for the open one to finish.
+    // handles without a transaction still read what an open transaction wrote.
+    // a transaction opened by the task already holding the connection would wait for
+    // itself, it becomes a savepoint of the open one instead
+    async fn sqlite_memory_transaction(&self, transactional: bool) -> Result<Arc<dyn Transaction>> {
+        if transactional {
+            if let Some(open) = open_memory_transaction() {
+                return open.spawn().await;
+            }
+        }
+        let exclusive = if transactional { Some(MEMORY_TRANSACTION_LOCK.clone().lock_owned().await) } else { None };
+        let pooled_connection = {
+            let mut connection = UNIQUE_CONNECTION.lock().await;
+            if connection.is_none() {
+                *connection = Some(Arc::new(self.check_out().await?));
+            }
+            connection.clone().unwrap()
+        };
+        let Some(exclusive) = exclusive else {
+            return Ok(Arc::new(self.sql_transaction(pooled_connection, None)));
+        };
+        let transaction = match start_owned_transaction(pooled_connection.clone(), None).await {
+            Ok(transaction) => transaction,
+            Err(err) => return Err(Error::new(format!("cannot start transaction: {}", err.to_string()))),
+        };
+        let mut sql_transaction = self.sql_transaction(pooled_connection, Some(Arc::new(transaction)));
+        sql_transaction.exclusive = Some(Arc::new(std::sync::Mutex::new(Some(exclusive))));
+        let sql_transaction = Arc::new(sql_transaction);
+        if let Some(id) = tokio::task::try_id() {
+            *MEMORY_TRANSACTION_OWNER.lock().unwrap() = Some((id, Arc::downgrade(&sql_transaction)));
         }
+        Ok(sql_transaction)
     }
 }
 
-static UNIQUE_TRANSACTION: Lazy<Mutex<Option<Arc<dyn Transaction>>>> = Lazy::new(|| {
+// an in-memory sqlite database lives as long as its connection, keep one for the process
+static UNIQUE_CONNECTION: Lazy<Mutex<Option<Arc<PooledConnection>>>> = Lazy::new(|| {
     Mutex::new(None)
 });
 
+static MEMORY_TRANSACTION_LOCK: Lazy<Arc<Mutex<()>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(()))
+});
+
+// the task which opened the in-memory transaction, with the transaction itself
+static MEMORY_TRANSACTION_OWNER: Lazy<std::sync::Mutex<Option<(Id, Weak<SQLTransaction>)>>> = Lazy::new(|| {
+    std::sync::Mutex::new(None)
+});
+
+fn open_memory_transaction() -> Option<Arc<SQLTransaction>> {
+    let id = tokio::task::try_id()?;
+    let owner = MEMORY_TRANSACTION_OWNER.lock().unwrap();
+    let (owner_id, transaction) = owner.as_ref()?;
+    if *owner_id != id {
+        return None;
+    }
+    transaction.upgrade().filter(|transaction| transaction.holds_exclusive())
+}
+
 #[async_trait]
 impl Connection for SQLConnection {
 
     async fn transaction(&self) -> Result<Arc<dyn Transaction>> {
         if self.memory_mode && self.dialect.is_sqlite() {
-            return self.sqlite_memory_transaction().await;
-        }
-        match self.pool.check_out().await {
-            Ok(pooled_connection) => {
-                let pooled_connection = Arc::new(pooled_connection);
-                let transaction = start_owned_transaction(pooled_connection.clone(), None).await.unwrap();
-                Ok(Arc::new(SQLTransaction {
-                    dialect: self.dialect,
-                    conn: pooled_connection,
-                    tran: Some(Arc::new(transaction)),
-                    committed: Arc::new(AtomicBool::new(false)),
-                }))
-            }
-            Err(err) => {
-                Err(Error::new(format!("cannot create pooled connection: {}", err.to_string())))
-            }
+            return self.sqlite_memory_transaction(true).await;
         }
+        let pooled_connection = Arc::new(self.check_out().await?);
+        let checked_out = self.counters.check_out();
+        let transaction = start_owned_transaction(pooled_connection.clone(), None).await.unwrap();
+        let mut sql_transaction = self.sql_transaction(pooled_connection, Some(Arc::new(transaction)));
+        sql_transaction.checked_out = Some(Arc::new(checked_out));
+        Ok(Arc::new(sql_transaction))
     }
 
     async fn no_transaction(&self) -> Result<Arc<dyn Transaction>> {
         if self.memory_mode && self.dialect.is_sqlite() {
-            return self.sqlite_memory_transaction().await;
+            return self.sqlite_memory_transaction(false).await;
         }
-        let pooled_connection = self.pool.check_out().await;
-        if pooled_connection.is_err() {
-            Err(Error::new(format!("cannot create pooled connection: {}", pooled_connection.err().unwrap().to_string())))
-        } else {
-            Ok(Arc::new(SQLTransaction::new(self.dialect, Arc::new(pooled_connection.unwrap()), None)))
+        let pooled_connection = self.check_out().await?;
+        let mut sql_transaction = self.sql_transaction(Arc::new(pooled_connection), None);
+        sql_transaction.checked_out = Some(Arc::new(self.counters.check_out()));
+        Ok(Arc::new(sql_transaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::transaction::increment_statement;
+    use crate::query::params::execute_bound;
+    use crate::stmts::ToBoundSQL;
+
+    #[tokio::test]
+    async fn nested_memory_transactions_become_savepoints() {
+        let connection = SQLConnection::new(SQLDialect::SQLite, "sqlite::memory:", false).await;
+        let outer = connection.transaction().await.unwrap();
+        let inner = tokio::time::timeout(Duration::from_secs(1), connection.transaction()).await.expect("nested transaction waits for itself").unwrap();
+        inner.commit().await.unwrap();
+        outer.commit().await.unwrap();
+        // the lock is released, the next transaction starts a real one
+        let next = tokio::time::timeout(Duration::from_secs(1), connection.transaction()).await.unwrap().unwrap();
+        next.abort().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_transactions_do_not_see_uncommitted_writes() {
+        let connection = Arc::new(SQLConnection::new(SQLDialect::SQLite, "sqlite::memory:", false).await);
+        let (written, wait_written) = tokio::sync::oneshot::channel();
+        let (finish, wait_finish) = tokio::sync::oneshot::channel::<()>();
+        let writer = tokio::spawn({
+            let connection = connection.clone();
+            async move {
+                let transaction = connection.transaction().await.unwrap();
+                let sql_transaction = open_memory_transaction().unwrap();
+                sql_transaction.conn.raw_cmd("CREATE TABLE uncommitted (n INTEGER)").await.unwrap();
+                sql_transaction.conn.raw_cmd("INSERT INTO uncommitted VALUES (1)").await.unwrap();
+                written.send(()).unwrap();
+                wait_finish.await.unwrap();
+                transaction.abort().await.unwrap();
+            }
+        });
+        wait_written.await.unwrap();
+        // another logical transaction waits for the open one instead of reading its writes
+        let reader = tokio::spawn({
+            let connection = connection.clone();
+            async move {
+                let transaction = connection.transaction().await.unwrap();
+                let sql_transaction = open_memory_transaction().unwrap();
+                let result = sql_transaction.conn.query_raw("SELECT name FROM sqlite_master WHERE name = 'uncommitted'", &[]).await.unwrap();
+                transaction.abort().await.unwrap();
+                result.is_empty()
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!reader.is_finished());
+        finish.send(()).unwrap();
+        writer.await.unwrap();
+        assert!(reader.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pending_change_is_the_first_planned_operation() {
+        let path = std::env::temp_dir().join(format!("teo_pending_change_{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = SQLConnection::new(SQLDialect::SQLite, &format!("sqlite:{}", path.display()), false).await;
+        let transaction = connection.sql_transaction(Arc::new(connection.check_out().await.unwrap()), None);
+        transaction.migrate_with_hooks(vec![], &[], &[], false, true).await.unwrap();
+        assert_eq!(transaction.pending_schema_change(vec![]).await.unwrap(), None);
+        // the models no longer declare this table
+        transaction.conn.raw_cmd("CREATE TABLE stale (id INTEGER PRIMARY KEY)").await.unwrap();
+        assert_eq!(transaction.pending_schema_change(vec![]).await.unwrap(), Some("drop table `stale'".to_owned()));
+        transaction.migrate_with_hooks(vec![], &[], &[], false, true).await.unwrap();
+        assert_eq!(transaction.pending_schema_change(vec![]).await.unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn migration_hooks_run_in_order_and_roll_back_with_it() {
+        let path = std::env::temp_dir().join(format!("teo_migration_hooks_{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = SQLConnection::new(SQLDialect::SQLite, &format!("sqlite:{}", path.display()), false).await;
+        let conn = Arc::new(connection.check_out().await.unwrap());
+        let tran = start_owned_transaction(conn.clone(), None).await.unwrap();
+        let transaction = connection.sql_transaction(conn, Some(Arc::new(tran)));
+        let before = vec!["CREATE TABLE hooked (step TEXT)".to_owned(), "INSERT INTO hooked VALUES ('before')".to_owned()];
+        // no model declares the table, the migration drops it before this recreates it
+        let after = vec!["CREATE TABLE hooked (step TEXT)".to_owned()];
+        let plan = transaction.migrate_with_hooks(vec![], &before, &after, false, true).await.unwrap();
+        let statements = plan.statements();
+        assert_eq!(statements.len(), 4);
+        assert_eq!(&statements[..2], &before[..]);
+        assert_eq!(statements[3], after[0]);
+        transaction.abort().await.unwrap();
+        let result = connection.check_out().await.unwrap().query_raw("SELECT name FROM sqlite_master WHERE name = 'hooked'", &[]).await.unwrap();
+        assert!(result.is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn concurrent_increments_are_not_lost() {
+        let path = std::env::temp_dir().join(format!("teo_increment_{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = Arc::new(SQLConnection::new(SQLDialect::SQLite, &format!("sqlite:{}", path.display()), false).await);
+        let conn = connection.check_out().await.unwrap();
+        conn.raw_cmd("CREATE TABLE counters (name TEXT PRIMARY KEY, n INTEGER NOT NULL)").await.unwrap();
+        let tasks: Vec<_> = (0..8).map(|_| {
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                let conn = connection.check_out().await.unwrap();
+                conn.raw_cmd("PRAGMA busy_timeout = 5000").await.unwrap();
+                let (sql, params) = increment_statement("counters", vec![("name", "'a'")], vec!["name".to_owned()], "n", "2", SQLDialect::SQLite).to_bound_sql(SQLDialect::SQLite);
+                execute_bound(&conn, &sql, &params).await.unwrap();
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
         }
+        let result = conn.query_raw("SELECT n FROM counters", &[]).await.unwrap();
+        assert_eq!(result.into_iter().next().unwrap().get("n").unwrap().as_i64(), Some(16));
+        let _ = std::fs::remove_file(&path);
     }
 }
@@ -1,5 +1,7 @@
 pub mod transaction;
 pub mod connection;
+pub mod slow_query;
+pub mod pool_stats;
 
 pub use connection::SQLConnection;
 pub use transaction::SQLTransaction;
\ No newline at end of file
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+// available counts the connections the pool can still hand out, opened or not
+pub struct SQLPoolStats {
+    pub size: usize,
+    pub in_use: usize,
+    pub available: usize,
+    pub waiting: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PoolCounters {
+    in_use: AtomicUsize,
+    waiting: AtomicUsize,
+}
+
+impl PoolCounters {
+
+    pub(crate) fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stats(&self, size: usize) -> SQLPoolStats {
+        let in_use = self.in_use();
+        SQLPoolStats { size, in_use, available: size.saturating_sub(in_use), waiting: self.waiting() }
+    }
+
+    pub(crate) fn wait(self: &Arc<Self>) -> Waiting {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting { counters: self.clone() }
+    }
+
+    pub(crate) fn check_out(self: &Arc<Self>) -> CheckedOut {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        CheckedOut { counters: self.clone() }
+    }
+}
+
+// dropped when the check out returns, whether it succeeded or not
+pub(crate) struct Waiting {
+    counters: Arc<PoolCounters>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.counters.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// travels with the pooled connection, it's back in the pool once this is dropped
+#[derive(Debug)]
+pub(crate) struct CheckedOut {
+    counters: Arc<PoolCounters>,
+}
+
+impl Drop for CheckedOut {
+    fn drop(&mut self) {
+        self.counters.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_follow_check_out_and_check_in() {
+        let counters = Arc::new(PoolCounters::default());
+        assert_eq!(counters.stats(2), SQLPoolStats { size: 2, in_use: 0, available: 2, waiting: 0 });
+        let waiting = counters.wait();
+        assert_eq!(counters.stats(2).waiting, 1);
+        drop(waiting);
+        let first = counters.check_out();
+        let second = counters.check_out();
+        assert_eq!(counters.stats(2), SQLPoolStats { size: 2, in_use: 2, available: 0, waiting: 0 });
+        drop(first);
+        assert_eq!(counters.stats(2), SQLPoolStats { size: 2, in_use: 1, available: 1, waiting: 0 });
+        drop(second);
+        assert_eq!(counters.stats(2), SQLPoolStats { size: 2, in_use: 0, available: 2, waiting: 0 });
+    }
+}
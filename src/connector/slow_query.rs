@@ -0,0 +1,174 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use itertools::Itertools;
+use quaint_forked::{prelude::*, ast::Query as QuaintQuery};
+use quaint_forked::connector::IsolationLevel;
+use crate::schema::dialect::SQLDialect;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlowQueryOptions {
+    pub threshold: Duration,
+    pub explain: bool,
+}
+
+// times every statement sent through it, slower ones are logged at warn
+pub struct SlowQueryLog {
+    inner: Arc<dyn Queryable>,
+    dialect: SQLDialect,
+    options: SlowQueryOptions,
+}
+
+impl SlowQueryLog {
+
+    pub(crate) fn new(inner: Arc<dyn Queryable>, dialect: SQLDialect, options: SlowQueryOptions) -> Self {
+        Self { inner, dialect, options }
+    }
+
+    pub(crate) fn options(&self) -> SlowQueryOptions {
+        self.options
+    }
+
+    async fn report(&self, sql: &str, elapsed: Duration) {
+        if elapsed < self.options.threshold {
+            return;
+        }
+        log::warn!("slow query took {}ms: {}", elapsed.as_millis(), sql);
+        // explaining anything but a select could run it a second time on some databases
+        if self.options.explain && sql.trim_start().to_uppercase().starts_with("SELECT") {
+            let explain = if self.dialect.is_sqlite() { "EXPLAIN QUERY PLAN" } else { "EXPLAIN" };
+            if let Ok(result_set) = self.inner.query(QuaintQuery::from(format!("{} {}", explain, sql))).await {
+                let plan = result_set.into_iter().map(|row| {
+                    row.into_iter().map(|value| format!("{}", value)).join(" | ")
+                }).join("\n");
+                log::warn!("plan of the slow query:\n{}", plan);
+            }
+        }
+    }
+}
+
+fn query_text(q: &QuaintQuery<'_>) -> String {
+    match q {
+        QuaintQuery::Raw(sql) => sql.to_string(),
+        _ => format!("{:?}", q),
+    }
+}
+
+#[async_trait]
+impl Queryable for SlowQueryLog {
+
+    async fn query(&self, q: QuaintQuery<'_>) -> quaint_forked::Result<ResultSet> {
+        let sql = query_text(&q);
+        let start = Instant::now();
+        let result = self.inner.query(q).await;
+        self.report(&sql, start.elapsed()).await;
+        result
+    }
+
+    async fn query_raw(&self, sql: &str, params: &[Value<'_>]) -> quaint_forked::Result<ResultSet> {
+        let start = Instant::now();
+        let result = self.inner.query_raw(sql, params).await;
+        self.report(sql, start.elapsed()).await;
+        result
+    }
+
+    async fn query_raw_typed(&self, sql: &str, params: &[Value<'_>]) -> quaint_forked::Result<ResultSet> {
+        let start = Instant::now();
+        let result = self.inner.query_raw_typed(sql, params).await;
+        self.report(sql, start.elapsed()).await;
+        result
+    }
+
+    async fn execute(&self, q: QuaintQuery<'_>) -> quaint_forked::Result<u64> {
+        let sql = query_text(&q);
+        let start = Instant::now();
+        let result = self.inner.execute(q).await;
+        self.report(&sql, start.elapsed()).await;
+        result
+    }
+
+    async fn execute_raw(&self, sql: &str, params: &[Value<'_>]) -> quaint_forked::Result<u64> {
+        let start = Instant::now();
+        let result = self.inner.execute_raw(sql, params).await;
+        self.report(sql, start.elapsed()).await;
+        result
+    }
+
+    async fn execute_raw_typed(&self, sql: &str, params: &[Value<'_>]) -> quaint_forked::Result<u64> {
+        let start = Instant::now();
+        let result = self.inner.execute_raw_typed(sql, params).await;
+        self.report(sql, start.elapsed()).await;
+        result
+    }
+
+    async fn raw_cmd(&self, cmd: &str) -> quaint_forked::Result<()> {
+        let start = Instant::now();
+        let result = self.inner.raw_cmd(cmd).await;
+        self.report(cmd, start.elapsed()).await;
+        result
+    }
+
+    async fn version(&self) -> quaint_forked::Result<Option<String>> {
+        self.inner.version().await
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.inner.is_healthy()
+    }
+
+    async fn set_tx_isolation_level(&self, isolation_level: IsolationLevel) -> quaint_forked::Result<()> {
+        self.inner.set_tx_isolation_level(isolation_level).await
+    }
+
+    fn requires_isolation_first(&self) -> bool {
+        self.inner.requires_isolation_first()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use quaint_forked::single::Quaint;
+    use super::*;
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) { }
+    }
+
+    // needs a live database, the url is read from the given variable
+    async fn sleep_is_reported(variable: &str, dialect: SQLDialect, sleep: &str) {
+        let Ok(url) = std::env::var(variable) else {
+            return;
+        };
+        let _ = log::set_logger(&Capture);
+        log::set_max_level(log::LevelFilter::Warn);
+        let conn = Quaint::new(&url).await.unwrap();
+        let slow_query = SlowQueryLog::new(Arc::new(conn), dialect, SlowQueryOptions { threshold: Duration::from_millis(100), explain: true });
+        slow_query.query_raw("SELECT 1", &[]).await.unwrap();
+        slow_query.query_raw(sleep, &[]).await.unwrap();
+        let warnings = WARNINGS.lock().unwrap().drain(..).collect::<Vec<String>>();
+        assert!(warnings.iter().any(|w| w.starts_with("slow query took") && w.ends_with(sleep)));
+        assert!(warnings.iter().any(|w| w.starts_with("plan of the slow query")));
+        assert!(!warnings.iter().any(|w| w.ends_with("SELECT 1")));
+    }
+
+    #[tokio::test]
+    async fn slow_queries_are_logged_with_their_plan() {
+        sleep_is_reported("TEO_TEST_POSTGRES_URL", SQLDialect::PostgreSQL, "SELECT pg_sleep(0.2)").await;
+        sleep_is_reported("TEO_TEST_MYSQL_URL", SQLDialect::MySQL, "SELECT SLEEP(0.2)").await;
+    }
+}
@@ -1,24 +1,42 @@
+use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::ops::Range;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::sync::{OnceCell, OwnedMutexGuard};
 use async_trait::async_trait;
 use indexmap::indexmap;
 use itertools::Itertools;
 use quaint_forked::{prelude::*, ast::Query as QuaintQuery};
+use quaint_forked::Value as QuaintValue;
 use quaint_forked::error::DatabaseConstraint;
 use quaint_forked::error::ErrorKind::UniqueConstraintViolation;
 use quaint_forked::pooled::PooledConnection;
 use quaint_forked::connector::owned_transaction::OwnedTransaction;
 use quaint_forked::connector::start_owned_transaction;
-use teo_runtime::model::Model;
+use teo_parser::r#type::Type;
+use teo_runtime::model::{Field, Model};
+use teo_runtime::database::r#type::DatabaseType;
+use teo_runtime::database::mysql::r#type::MySQLType;
+use teo_runtime::database::postgres::r#type::PostgreSQLType;
+use teo_runtime::namespace::Namespace;
+use crate::connector::pool_stats::CheckedOut;
+use crate::connector::slow_query::{SlowQueryLog, SlowQueryOptions};
 use crate::execution::Execution;
 use crate::migration::migrate::SQLMigration;
-use crate::query::Query;
-use crate::stmts::SQL;
+use crate::migration::plan::SQLMigrationPlan;
+use crate::query::{Query, sql_comment};
+use crate::query::params::{bound, execute_bound, query_bound};
+use crate::stmts::{SQL, ToBoundSQL};
+use crate::stmts::insert_into::SQLInsertIntoStatement;
+use crate::stmts::select::SQLLock;
 use crate::schema::dialect::SQLDialect;
+use crate::schema::table::SQLTable;
 use crate::schema::value::decode::RowDecoder;
 use crate::schema::value::encode::ToSQLString;
-use crate::schema::value::encode::PSQLArrayToSQLString;
+use crate::schema::value::encode::{PSQLArrayToSQLString, bit_to_sql_input, check_finite, money_to_sql_input};
 use teo_runtime::action::Action;
 use teo_runtime::model::object::input::Input;
 use teo_runtime::model::Object;
@@ -32,6 +50,7 @@ use teo_runtime::model::field::typed::Typed;
 use teo_runtime::error_ext;
 use teo_runtime::request::Ctx;
 use key_path::{KeyPath, path};
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct SQLTransaction {
@@ -39,27 +58,105 @@ pub struct SQLTransaction {
     pub conn: Arc<PooledConnection>,
     pub tran: Option<Arc<OwnedTransaction>>,
     pub committed: Arc<AtomicBool>,
+    pub comment: Option<String>,
+    pub slow_query: Option<Arc<SlowQueryLog>>,
+    pub migration_timeout: Option<Duration>,
+    pub upsert_on_save: bool,
+    pub namespace: Option<&'static Namespace>,
+    pub exclusive: Option<Arc<std::sync::Mutex<Option<OwnedMutexGuard<()>>>>>,
+    pub(crate) checked_out: Option<Arc<CheckedOut>>,
+    pub(crate) insert_returning: Arc<OnceCell<bool>>,
+    pub savepoint: Option<String>,
 }
 
 impl Debug for SQLTransaction {
 
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        f.debug_struct("SQLTransaction")
+            .field("dialect", &self.dialect)
+            .field("transaction", &self.tran.is_some())
+            .field("committed", &self.committed.load(Ordering::SeqCst))
+            .field("savepoint", &self.savepoint)
+            .finish()
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SQLUpsertAction {
+    Created,
+    Updated,
+}
+
+// inserted and updated are only known on postgres
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SQLUpsertManyCount {
+    pub total: usize,
+    pub inserted: Option<usize>,
+    pub updated: Option<usize>,
+}
+
+pub enum SQLBatchOperation {
+    Save(Object),
+    Delete(Object),
+}
+
+impl SQLBatchOperation {
+    async fn run(&self, transaction: &dyn Transaction, path: KeyPath) -> Result<()> {
+        match self {
+            SQLBatchOperation::Save(object) => transaction.save_object(object, path).await,
+            SQLBatchOperation::Delete(object) => transaction.delete_object(object, path).await,
+        }
+    }
+}
+
+// splits the operations into runs of consecutive equal keys, operations without a key run alone
+fn batch_runs<K: PartialEq>(keys: &[Option<K>]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if key.is_some() && keys[run.start] == *key => run.end = index + 1,
+            _ => runs.push(index..index + 1),
+        }
+    }
+    runs
+}
+
 impl SQLTransaction {
-    pub(super) fn new(dialect: SQLDialect, conn: Arc<PooledConnection>, tran: Option<Arc<OwnedTransaction>>) -> Self {
+    pub(super) fn new(dialect: SQLDialect, conn: Arc<PooledConnection>, tran: Option<Arc<OwnedTransaction>>, comment: Option<String>, slow_query: Option<SlowQueryOptions>) -> Self {
+        let slow_query = slow_query.map(|options| {
+            let inner: Arc<dyn Queryable> = match &tran {
+                Some(tran) => tran.clone(),
+                None => conn.clone(),
+            };
+            Arc::new(SlowQueryLog::new(inner, dialect, options))
+        });
         Self {
-            dialect, conn, tran, committed: Arc::new(AtomicBool::new(false))
+            dialect, conn, tran, committed: Arc::new(AtomicBool::new(false)), comment, slow_query, migration_timeout: None, upsert_on_save: false, namespace: None, exclusive: None, checked_out: None, insert_returning: Arc::new(OnceCell::new()), savepoint: None
         }
     }
 }
 
 impl SQLTransaction {
 
+    pub(crate) fn holds_exclusive(&self) -> bool {
+        self.exclusive.as_ref().map(|exclusive| exclusive.lock().unwrap().is_some()).unwrap_or(false)
+    }
+
+    // lets the next transaction on a shared connection begin
+    fn release_exclusive(&self) {
+        if let Some(exclusive) = &self.exclusive {
+            exclusive.lock().unwrap().take();
+        }
+    }
+
+    async fn savepoint_cmd(&self, tran: &OwnedTransaction, stmt: String) -> Result<()> {
+        tran.raw_cmd(&stmt).await.map_err(|err| Error::new(format!("{}: {}", stmt, err.to_string())))
+    }
+
     fn queryable(&self) -> &dyn Queryable {
-        if let Some(tran) = &self.tran {
+        if let Some(slow_query) = &self.slow_query {
+            slow_query.as_ref()
+        } else if let Some(tran) = &self.tran {
             tran.as_ref()
         } else {
             self.conn()
@@ -78,62 +175,128 @@ impl SQLTransaction {
         self.tran.as_ref()
     }
 
-    async fn create_object(&self, object: &Object, path: KeyPath) -> teo_result::Result<()> {
+    fn with_comment(&self, stmt: String) -> String {
+        match &self.comment {
+            Some(comment) => sql_comment(comment) + &stmt,
+            None => stmt,
+        }
+    }
+
+    // a comment passed with the finder wins over the connection one
+    fn finder_with_comment<'a>(&self, finder: &'a Value) -> Cow<'a, Value> {
+        match &self.comment {
+            Some(comment) if finder.get("comment").is_none() => {
+                let mut map = finder.as_dictionary().unwrap().clone();
+                map.insert("comment".to_owned(), Value::String(comment.clone()));
+                Cow::Owned(Value::Dictionary(map))
+            }
+            _ => Cow::Borrowed(finder),
+        }
+    }
+
+    fn field_value_to_sql(&self, field: &Field, value: &Value) -> Result<String> {
+        check_finite(value, self.dialect())?;
+        match field.database_type() {
+            DatabaseType::MySQLType(MySQLType::Bit(_)) => Ok(bit_to_sql_input(value)),
+            DatabaseType::PostgreSQLType(PostgreSQLType::Money) => money_to_sql_input(value),
+            _ => self.value_to_sql(value, field.r#type()),
+        }
+    }
+
+    // arrays and values without a bound type are written as literals
+    fn value_to_sql(&self, value: &Value, r#type: &Type) -> Result<String> {
+        check_finite(value, self.dialect())?;
+        match bound(value, r#type, self.dialect()) {
+            Some(marker) => Ok(marker),
+            None => PSQLArrayToSQLString::to_string_with_ft(value, self.dialect(), r#type),
+        }
+    }
+
+    // the comment goes in front of the statement and holds no bound values
+    async fn run_query(&self, (stmt, params): (String, Vec<QuaintValue<'static>>)) -> quaint_forked::Result<ResultSet> {
+        query_bound(self.queryable(), &self.with_comment(stmt), &params).await
+    }
+
+    async fn run_execute(&self, (stmt, params): (String, Vec<QuaintValue<'static>>)) -> quaint_forked::Result<u64> {
+        execute_bound(self.queryable(), &self.with_comment(stmt), &params).await
+    }
+
+    async fn values_for_create<'a>(&self, object: &'a Object) -> teo_result::Result<Vec<(&'a str, String)>> {
         let model = object.model();
         let keys = object.keys_for_save();
-        let auto_keys = &model.cache().auto_keys;
         let mut values: Vec<(&str, String)> = vec![];
         for key in keys {
             if let Some(field) = model.field(key) {
                 let column_name = field.column_name();
                 let val = object.get_value(key).unwrap();
                 if !(field.auto_increment() && val.is_null()) {
-                    values.push((column_name, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), field.r#type())));
+                    values.push((column_name, self.field_value_to_sql(field, &val)?));
                 }
             } else if let Some(property) = model.property(key) {
                 let val: Value = object.get_property_value(key).await?;
-                values.push((key, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), property.r#type())));
+                values.push((key, self.value_to_sql(&val, property.r#type())?));
             }
         }
+        Ok(values)
+    }
+
+    // mysql can't return what it generated besides the last integer id, string keys
+    // are generated as uuids on the client before the insert there
+    async fn create_object(&self, object: &Object, path: KeyPath) -> teo_result::Result<()> {
+        let model = object.model();
+        let auto_keys = &model.cache().auto_keys;
+        let returning = !auto_keys.is_empty() && self.supports_insert_returning().await;
+        if !returning {
+            self.generate_string_keys(object)?;
+        }
+        let values = self.values_for_create(object).await?;
         let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
-        let stmt = SQL::insert_into(model.table_name()).values(value_refs).returning(auto_keys).to_string(self.dialect());
-        // println!("create stmt: {}", stmt);
-        if self.dialect() == SQLDialect::PostgreSQL {
-            match self.queryable().query(QuaintQuery::from(stmt)).await {
-                Ok(result_set) => {
-                    let columns = result_set.columns().clone();
-                    let result = result_set.into_iter().next();
-                    if result.is_some() {
-                        let value = Execution::row_to_value(object.namespace(), model, &result.unwrap(), &columns, self.dialect());
-                        for (k, v) in value.as_dictionary().unwrap() {
-                            object.set_value(k, v.clone())?;
-                        }
-                    }
-                    Ok(())
-                }
-                Err(err) => {
-                    Err(self.handle_err_result(err, path))
-                }
+        let mut insert = SQL::insert_into(model.table_name());
+        insert.values(value_refs);
+        if returning {
+            insert.returning(&auto_keys.iter().map(|k| self.dialect().quote(model.field(k).unwrap().column_name())).collect());
+        }
+        let result_set = self.run_query(insert.to_bound_sql(self.dialect())).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+        if returning {
+            let columns = result_set.columns().clone();
+            let Some(row) = result_set.into_iter().next() else {
+                return Err(error_ext::unknown_database_write_error(path, format!("the insert into `{}' returned no row", model.table_name())));
+            };
+            let value = Execution::row_to_value(object.namespace(), model, &row, &columns, self.dialect())?;
+            for (k, v) in value.as_dictionary().unwrap() {
+                object.set_value(k, v.clone())?;
             }
-        } else {
-            match self.conn().query(QuaintQuery::from(stmt)).await {
-                Ok(result) => {
-                    if let Some(id) = result.last_insert_id() {
-                        for key in auto_keys {
-                            if model.field(key).unwrap().r#type().is_int() {
-                                object.set_value(key, Value::Int(id as i32))?;
-                            } else {
-                                object.set_value(key, Value::Int64(id as i64))?;
-                            }
-                        }
-                    }
-                    Ok(())
-                }
-                Err(err) => {
-                    Err(self.handle_err_result(err, path))
-                }
+            return Ok(());
+        }
+        let missing: Vec<&String> = auto_keys.iter().filter(|k| object.get_value(k).map(|v| v.is_null()).unwrap_or(true)).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let r#type = model.field(missing[0]).unwrap().r#type();
+        match result_set.last_insert_id() {
+            Some(id) if missing.len() == 1 && r#type.is_int() => object.set_value(missing[0], Value::Int(id as i32)),
+            Some(id) if missing.len() == 1 && r#type.is_int64() => object.set_value(missing[0], Value::Int64(id as i64)),
+            _ => Err(error_ext::unknown_database_write_error(path, format!("cannot read the generated keys {} of `{}'", missing.iter().join(", "), model.table_name()))),
+        }
+    }
+
+    fn generate_string_keys(&self, object: &Object) -> teo_result::Result<()> {
+        let model = object.model();
+        for key in &model.cache().auto_keys {
+            let field = model.field(key).unwrap();
+            if !field.r#type().is_string() || !object.get_value(key).map(|v| v.is_null()).unwrap_or(true) {
+                continue;
             }
+            object.set_value(key, Value::String(Uuid::new_v4().to_string()))?;
         }
+        Ok(())
+    }
+
+    async fn supports_insert_returning(&self) -> bool {
+        *self.insert_returning.get_or_init(|| async {
+            let version = self.queryable().version().await.ok().flatten();
+            supports_insert_returning(self.dialect(), version.as_deref())
+        }).await
     }
 
     async fn update_object(&self, object: &Object, path: KeyPath) -> teo_result::Result<()> {
@@ -145,30 +308,29 @@ impl SQLTransaction {
                 let column_name = field.column_name();
                 if let Some(updator) = object.get_atomic_updator(key) {
                     let (key, val) = Input::key_value(updator.as_dictionary().unwrap());
+                    check_finite(val, self.dialect())?;
                     match key {
-                        "increment" => values.push((column_name, format!("{} + {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "decrement" => values.push((column_name, format!("{} - {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "multiply" => values.push((column_name, format!("{} * {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "divide" => values.push((column_name, format!("{} / {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "push" => values.push((column_name, format!("ARRAY_APPEND({}, {})", column_name, ToSQLString::to_string(&val, self.dialect())))),
+                        "increment" => values.push((column_name, format!("{} + {}", column_name, self.value_to_sql(val, field.r#type())?))),
+                        "decrement" => values.push((column_name, format!("{} - {}", column_name, self.value_to_sql(val, field.r#type())?))),
+                        "multiply" => values.push((column_name, format!("{} * {}", column_name, self.value_to_sql(val, field.r#type())?))),
+                        "divide" => values.push((column_name, format!("{} / {}", column_name, self.value_to_sql(val, field.r#type())?))),
+                        "push" => values.push((column_name, format!("ARRAY_APPEND({}, {})", column_name, ToSQLString::to_string(val, self.dialect())))),
                         _ => unreachable!(),
                     }
                 } else {
                     let val = object.get_value(key).unwrap();
-                    values.push((column_name, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), field.r#type())));
+                    values.push((column_name, self.field_value_to_sql(field, &val)?));
                 }
             } else if let Some(property) = model.property(key) {
                 let val: Value = object.get_property_value(key).await?;
-                values.push((key, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), property.r#type())));
+                values.push((key, self.value_to_sql(&val, property.r#type())?));
             }
         }
         let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
         let identifier = object.identifier();
-        let r#where = Query::where_from_previous_identifier(object, self.dialect());
+        let r#where = Query::where_from_previous_identifier(object, self.dialect())?;
         if !value_refs.is_empty() {
-            let stmt = SQL::update(model.table_name()).values(value_refs).r#where(&r#where).to_string(self.dialect());
-            // println!("update stmt: {}", stmt);
-            let result = self.conn().execute(QuaintQuery::from(stmt)).await;
+            let result = self.run_execute(SQL::update(model.table_name()).values(value_refs).r#where(&r#where).to_bound_sql(self.dialect())).await;
             if result.is_err() {
                 return Err(error_ext::unknown_database_write_error(path.clone(), format!("{:?}", result.err().unwrap())));
             }
@@ -182,6 +344,426 @@ impl SQLTransaction {
         }
     }
 
+    pub async fn find_many_with_total_count(&self, model: &'static Model, finder: &Value, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> Result<(Vec<Object>, usize)> {
+        Execution::query_objects_with_total_count(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), action, transaction_ctx, req_ctx, path).await
+    }
+
+    // pages through the matching rows by primary key so only one chunk is held in memory,
+    // the finder's own ordering and paging are replaced
+    pub async fn find_many_in_chunks<F, Fut>(&self, model: &'static Model, finder: &Value, chunk_size: usize, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath, mut f: F) -> Result<()> where F: FnMut(Vec<Object>) -> Fut, Fut: Future<Output = Result<()>> {
+        let mut finder = finder.as_dictionary().unwrap().clone();
+        for key in ["orderBy", "skip", "take", "cursor", "after"] {
+            finder.shift_remove(key);
+        }
+        finder.insert("orderBy".to_owned(), Value::Array(model.primary_index().unwrap().items().iter().map(|item| {
+            Value::Dictionary(indexmap!{item.field.clone() => Value::String("asc".to_owned())})
+        }).collect()));
+        finder.insert("take".to_owned(), Value::Int64(chunk_size as i64));
+        loop {
+            let objects = self.find_many(model, &Value::Dictionary(finder.clone()), false, action, transaction_ctx.clone(), req_ctx.clone(), path.clone()).await?;
+            let last = objects.last().map(|object| object.identifier());
+            let is_last_chunk = objects.len() < chunk_size;
+            if !objects.is_empty() {
+                f(objects).await?;
+            }
+            match last {
+                Some(identifier) if !is_last_chunk => { finder.insert("after".to_owned(), identifier); }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    pub async fn find_many_with_lock(&self, model: &'static Model, finder: &Value, lock: SQLLock, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> Result<Vec<Object>> {
+        if self.dialect() == SQLDialect::SQLite || self.dialect() == SQLDialect::MSSQL {
+            return Err(Error::new(format!("row locking is not supported on {:?}", self.dialect())));
+        }
+        let values = Execution::query_with_lock(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), lock, path).await?;
+        Execution::values_to_objects(values, model, finder, action, transaction_ctx, req_ctx)
+    }
+
+    pub async fn upsert_object(&self, object: &Object, conflict_keys: Vec<&str>, path: KeyPath) -> Result<SQLUpsertAction> {
+        self.upsert_object_with_update(object, conflict_keys, None, path).await
+    }
+
+    // without update values every inserted column is updated to the value the insert tried to write
+    pub async fn upsert_object_with_update(&self, object: &Object, conflict_keys: Vec<&str>, update: Option<&Value>, path: KeyPath) -> Result<SQLUpsertAction> {
+        check_upsert(self.dialect())?;
+        let model = object.model();
+        let auto_keys = &model.cache().auto_keys;
+        let values = self.values_for_create(object).await?;
+        let conflict_columns: Vec<String> = conflict_keys.iter().map(|k| match model.field(k) {
+            Some(field) => Ok(field.column_name().to_owned()),
+            None => Err(Error::new(format!("cannot upsert on `{}', it is not a field of `{}'", k, model.name()))),
+        }).collect::<Result<Vec<String>>>()?;
+        let updates: Vec<(String, String)> = match update {
+            Some(update) => {
+                let Some(update) = update.as_dictionary() else {
+                    return Err(Error::new("upsert update values should be a dictionary"));
+                };
+                update.iter().map(|(k, v)| {
+                    let Some(field) = model.field(k) else {
+                        return Err(Error::new(format!("cannot update `{}', it is not a field of `{}'", k, model.name())));
+                    };
+                    Ok((field.column_name().to_owned(), self.field_value_to_sql(field, v)?))
+                }).collect::<Result<Vec<(String, String)>>>()?
+            }
+            None => values.iter().filter(|(k, _)| !conflict_columns.iter().any(|c| c == k)).map(|(k, _)| {
+                (k.to_string(), SQLInsertIntoStatement::excluded(k, self.dialect()))
+            }).collect(),
+        };
+        let conflict_value = Value::Dictionary(conflict_keys.iter().map(|k| Ok((k.to_string(), object.get_value(k)?))).collect::<Result<_>>()?);
+        let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        if self.dialect() == SQLDialect::PostgreSQL {
+            // xmax is zero for freshly inserted rows
+            let mut returning: Vec<String> = auto_keys.iter().map(|k| self.dialect().quote(model.field(k).unwrap().column_name())).collect();
+            returning.push("(xmax = 0) AS \"_inserted\"".to_owned());
+            let stmt = SQL::insert_into(model.table_name()).values(value_refs).on_conflict(conflict_columns, updates).returning(&returning).to_bound_sql(self.dialect());
+            let result_set = self.run_query(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+            let Some((inserted, columns, row)) = returned_upsert_row(result_set, &path)? else {
+                // DO NOTHING returns no row on conflict, the stored row is read instead
+                let result = Execution::query(object.namespace(), self.queryable(), model, &teon!({"where": conflict_value, "take": 1i64}), self.dialect(), path.clone()).await?;
+                if let Some(value) = result.get(0) {
+                    object.set_from_database_result_value(value, None, None);
+                }
+                return Ok(SQLUpsertAction::Updated);
+            };
+            let value = Execution::row_to_value(object.namespace(), model, &row, &columns, self.dialect())?;
+            for (k, v) in value.as_dictionary().unwrap() {
+                object.set_value(k, v.clone())?;
+            }
+            Ok(if inserted { SQLUpsertAction::Created } else { SQLUpsertAction::Updated })
+        } else {
+            let inserted = if self.dialect() == SQLDialect::SQLite {
+                // sqlite reports one changed row for both branches of an upsert, the
+                // insert and the update run as separate statements to tell them apart
+                let direct_updates: Vec<(String, String)> = match update {
+                    Some(_) => updates,
+                    None => values.iter().filter(|(k, _)| !conflict_columns.iter().any(|c| c == k)).map(|(k, v)| (k.to_string(), v.clone())).collect(),
+                };
+                self.insert_or_update(model, value_refs, conflict_columns, direct_updates, &conflict_value, path.clone()).await?
+            } else {
+                // affected rows is 1 for insert and 2 for update
+                let stmt = SQL::insert_into(model.table_name()).values(value_refs).on_conflict(conflict_columns, updates).to_bound_sql(self.dialect());
+                self.run_execute(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))? == 1
+            };
+            let result = Execution::query(object.namespace(), self.queryable(), model, &teon!({"where": conflict_value, "take": 1i64}), self.dialect(), path.clone()).await?;
+            if let Some(value) = result.get(0) {
+                object.set_from_database_result_value(value, None, None);
+            }
+            Ok(if inserted { SQLUpsertAction::Created } else { SQLUpsertAction::Updated })
+        }
+    }
+
+    // a row removed between the two statements is inserted on the next round
+    async fn insert_or_update(&self, model: &Model, values: Vec<(&str, &str)>, conflict_columns: Vec<String>, updates: Vec<(String, String)>, conflict_value: &Value, path: KeyPath) -> Result<bool> {
+        let insert = SQL::insert_into(model.table_name()).values(values).on_conflict(conflict_columns, vec![]).to_bound_sql(self.dialect());
+        let r#where = Query::where_from_value(model, conflict_value, self.dialect())?;
+        let update = SQL::update(model.table_name()).values(updates.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()).r#where(&r#where).to_bound_sql(self.dialect());
+        for _ in 0..3 {
+            if self.run_execute(insert.clone()).await.map_err(|err| self.handle_err_result(err, path.clone()))? == 1 {
+                return Ok(true);
+            }
+            if updates.is_empty() {
+                return Ok(false);
+            }
+            if self.run_execute(update.clone()).await.map_err(|err| self.handle_err_result(err, path.clone()))? > 0 {
+                return Ok(false);
+            }
+        }
+        Err(error_ext::unknown_database_write_error(path, "the upserted row kept changing concurrently".to_owned()))
+    }
+
+    // one multi-row statement per chunk, objects writing different columns go
+    // into separate statements, written values are not read back into the objects
+    pub async fn upsert_many(&self, objects: &[Object], conflict_keys: Vec<&str>, chunk_size: usize, path: KeyPath) -> Result<SQLUpsertManyCount> {
+        check_upsert(self.dialect())?;
+        let mut count = SQLUpsertManyCount { total: 0, inserted: None, updated: None };
+        if objects.is_empty() {
+            return Ok(count);
+        }
+        let model = objects.first().unwrap().model();
+        let conflict_columns: Vec<String> = conflict_keys.iter().map(|k| model.field(k).unwrap().column_name().to_owned()).collect();
+        let mut rows: Vec<Vec<(&str, String)>> = vec![];
+        for object in objects {
+            rows.push(self.values_for_create(object).await?);
+        }
+        let mut start = 0;
+        while start < rows.len() {
+            let columns: Vec<&str> = rows[start].iter().map(|(k, _)| *k).collect();
+            let mut end = start + 1;
+            while end < rows.len() && end - start < chunk_size.max(1) && rows[end].iter().map(|(k, _)| *k).eq(columns.iter().cloned()) {
+                end += 1;
+            }
+            let updates: Vec<(String, String)> = columns.iter().filter(|k| !conflict_columns.iter().any(|c| c == *k)).map(|k| {
+                (k.to_string(), SQLInsertIntoStatement::excluded(k, self.dialect()))
+            }).collect();
+            let mut stmt = SQL::insert_into(model.table_name());
+            stmt.values(rows[start].iter().map(|(k, v)| (*k, v.as_str())).collect());
+            for row in &rows[start + 1..end] {
+                stmt.row(row.iter().map(|(_, v)| v.as_str()).collect());
+            }
+            stmt.on_conflict(conflict_columns.clone(), updates);
+            if self.dialect() == SQLDialect::PostgreSQL {
+                // xmax is zero for freshly inserted rows
+                let stmt = stmt.returning(&vec!["(xmax = 0) AS \"_inserted\""]).to_bound_sql(self.dialect());
+                let result_set = self.run_query(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+                let inserted = result_set.into_iter().filter(|row| row.get("_inserted").map(|v| v.as_bool()).flatten().unwrap_or(false)).count();
+                count.inserted = Some(count.inserted.unwrap_or(0) + inserted);
+                count.updated = Some(count.updated.unwrap_or(0) + end - start - inserted);
+            } else {
+                let stmt = stmt.to_bound_sql(self.dialect());
+                self.run_execute(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+            }
+            count.total += end - start;
+            start = end;
+        }
+        Ok(count)
+    }
+
+    // objects writing the same columns share a multi-row insert, the others are
+    // inserted one by one. outside postgres generated keys can only be read back
+    // row by row, so models with auto increment keys are inserted per row there
+    pub async fn create_many_objects(&self, objects: &[Object], chunk_size: usize, path: KeyPath) -> Result<()> {
+        if objects.is_empty() {
+            return Ok(());
+        }
+        let model = objects.first().unwrap().model();
+        let auto_keys = &model.cache().auto_keys;
+        if self.dialect() != SQLDialect::PostgreSQL && !auto_keys.is_empty() {
+            for object in objects {
+                self.create_object(object, path.clone()).await?;
+            }
+            return Ok(());
+        }
+        let mut rows: Vec<Vec<(&str, String)>> = vec![];
+        for object in objects {
+            rows.push(self.values_for_create(object).await?);
+        }
+        let mut start = 0;
+        while start < rows.len() {
+            let columns: Vec<&str> = rows[start].iter().map(|(k, _)| *k).collect();
+            let mut end = start + 1;
+            while end < rows.len() && end - start < chunk_size.max(1) && rows[end].iter().map(|(k, _)| *k).eq(columns.iter().cloned()) {
+                end += 1;
+            }
+            if end - start == 1 {
+                self.create_object(&objects[start], path.clone()).await?;
+                start = end;
+                continue;
+            }
+            let mut stmt = SQL::insert_into(model.table_name());
+            stmt.values(rows[start].iter().map(|(k, v)| (*k, v.as_str())).collect());
+            for row in &rows[start + 1..end] {
+                stmt.row(row.iter().map(|(_, v)| v.as_str()).collect());
+            }
+            if self.dialect() == SQLDialect::PostgreSQL && !auto_keys.is_empty() {
+                // returned rows follow the order of the values list
+                let stmt = stmt.returning(auto_keys).to_bound_sql(self.dialect());
+                let result_set = self.run_query(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+                let columns = result_set.columns().clone();
+                for (object, row) in objects[start..end].iter().zip(result_set.into_iter()) {
+                    let value = Execution::row_to_value(object.namespace(), model, &row, &columns, self.dialect())?;
+                    for (k, v) in value.as_dictionary().unwrap() {
+                        object.set_value(k, v.clone())?;
+                    }
+                }
+            } else {
+                let stmt = stmt.to_bound_sql(self.dialect());
+                self.run_execute(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    // stamps `field_name` with the current time instead of removing the row. rows
+    // of the cascaded relations are stamped as well when their model has a field
+    // with the same name, for many-to-many relations these are the join rows.
+    // cascading goes one relation deep
+    pub async fn soft_delete_object(&self, object: &Object, field_name: &str, cascade: Vec<&str>, path: KeyPath) -> Result<()> {
+        if object.is_new() {
+            return Err(error_ext::object_is_not_saved_thus_cant_be_deleted(path));
+        }
+        let model = object.model();
+        let namespace = object.namespace();
+        let now = Value::DateTime(chrono::Utc::now());
+        let r#where = Query::where_from_identifier(object, self.dialect())?;
+        self.soft_delete_rows(model, field_name, &now, r#where, path.clone()).await?;
+        for relation_name in cascade {
+            let Some(relation) = model.relation(relation_name) else {
+                return Err(Error::new(format!("cannot cascade soft delete, `{}' is not a relation", relation_name)));
+            };
+            let (related_model, pairs) = if relation.has_join_table() {
+                let (through_model, through_relation) = namespace.through_relation(relation);
+                (through_model, through_relation.iter().map(|(f, r)| (f.to_owned(), object.get_value(r).unwrap())).collect::<Vec<(String, Value)>>())
+            } else {
+                (namespace.model_at_path(&relation.model_path()).unwrap(), relation.iter().map(|(f, r)| (r.to_owned(), object.get_value(f).unwrap())).collect::<Vec<(String, Value)>>())
+            };
+            if related_model.field(field_name).is_none() {
+                continue;
+            }
+            let r#where = Query::where_from_value(related_model, &Value::Dictionary(pairs.into_iter().collect()), self.dialect())?;
+            self.soft_delete_rows(related_model, field_name, &now, r#where, path.clone()).await?;
+        }
+        Ok(())
+    }
+
+    // rows deleted before keep their original time
+    async fn soft_delete_rows(&self, model: &Model, field_name: &str, now: &Value, r#where: String, path: KeyPath) -> Result<()> {
+        let field = model.field(field_name).unwrap();
+        let value = self.field_value_to_sql(field, now)?;
+        let r#where = format!("{} AND {} IS NULL", r#where, self.dialect().quote(field.column_name()));
+        let stmt = SQL::update(model.table_name()).value((field.column_name(), value.as_str())).r#where(&r#where).to_bound_sql(self.dialect());
+        match self.run_execute(stmt).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(error_ext::unknown_database_write_error(path, format!("{:?}", err))),
+        }
+    }
+
+    // the counter starts at `by` when the row is absent, the conflict branch adds
+    // to the stored value so concurrent calls never lose an increment
+    pub async fn increment_or_create(&self, model: &'static Model, unique: &Value, field_name: &str, by: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> Result<Value> {
+        check_upsert(self.dialect())?;
+        let Some(field) = model.field(field_name) else {
+            return Err(Error::new(format!("cannot increment `{}', it is not a field of `{}'", field_name, model.name())));
+        };
+        let Some(pairs) = unique.as_dictionary() else {
+            return Err(Error::new("increment or create expects a dictionary of unique values"));
+        };
+        let column_name = field.column_name();
+        let by_sql = self.value_to_sql(by, field.r#type())?;
+        let mut values: Vec<(&str, String)> = vec![];
+        let mut conflict_columns: Vec<String> = vec![];
+        for (key, value) in pairs {
+            let Some(key_field) = model.field(key) else {
+                return Err(Error::new(format!("`{}' is not a field of `{}'", key, model.name())));
+            };
+            values.push((key_field.column_name(), self.value_to_sql(value, key_field.r#type())?));
+            conflict_columns.push(key_field.column_name().to_owned());
+        }
+        let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let mut stmt = increment_statement(model.table_name(), value_refs, conflict_columns, column_name, &by_sql, self.dialect());
+        if self.dialect() == SQLDialect::PostgreSQL {
+            let stmt = stmt.returning(&vec![self.dialect().quote(column_name)]).to_bound_sql(self.dialect());
+            let result_set = self.run_query(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+            let columns = result_set.columns().clone();
+            let Some(row) = result_set.into_iter().next() else {
+                return Err(error_ext::unknown_database_write_error(path, format!("the increment of `{}' returned no row", model.table_name())));
+            };
+            let value = Execution::row_to_value(transaction_ctx.namespace(), model, &row, &columns, self.dialect())?;
+            Ok(value.get(field_name).cloned().unwrap_or(Value::Null))
+        } else {
+            let stmt = stmt.to_bound_sql(self.dialect());
+            self.run_execute(stmt).await.map_err(|err| self.handle_err_result(err, path.clone()))?;
+            let result = Execution::query(transaction_ctx.namespace(), self.queryable(), model, &teon!({"where": unique.clone(), "take": 1i64}), self.dialect(), path.clone()).await?;
+            Ok(result.get(0).map(|v| v.get(field_name).cloned()).flatten().unwrap_or(Value::Null))
+        }
+    }
+
+    // consecutive deletes from one table go out as a single statement. with continue_on_error
+    // every operation runs in its own savepoint, so a failed one doesn't abort the transaction
+    pub async fn batch(&self, operations: Vec<SQLBatchOperation>, continue_on_error: bool, path: KeyPath) -> Result<Vec<Result<()>>> {
+        let transaction = self.spawn_transaction().await?;
+        let mut results = vec![];
+        if continue_on_error {
+            for (index, operation) in operations.iter().enumerate() {
+                let savepoint = transaction.spawn().await?;
+                let result = operation.run(savepoint.as_ref(), path.clone() + index).await;
+                if result.is_err() {
+                    savepoint.abort().await?;
+                } else {
+                    savepoint.commit().await?;
+                }
+                results.push(result);
+            }
+        } else {
+            let keys: Vec<Option<&str>> = operations.iter().map(|operation| match operation {
+                SQLBatchOperation::Delete(object) if !object.is_new() => Some(object.model().table_name()),
+                _ => None,
+            }).collect();
+            for run in batch_runs(&keys) {
+                let result = if run.len() > 1 {
+                    transaction.delete_objects(&operations[run.clone()], path.clone() + run.start).await
+                } else {
+                    operations[run.start].run(&transaction, path.clone() + run.start).await
+                };
+                if let Err(err) = result {
+                    transaction.abort().await?;
+                    return Err(err);
+                }
+                results.extend(run.map(|_| Ok(())));
+            }
+        }
+        transaction.commit().await?;
+        Ok(results)
+    }
+
+    async fn delete_objects(&self, operations: &[SQLBatchOperation], path: KeyPath) -> Result<()> {
+        let mut model = None;
+        let mut wheres = vec![];
+        for operation in operations {
+            if let SQLBatchOperation::Delete(object) = operation {
+                model = Some(object.model());
+                wheres.push(format!("({})", Query::where_from_identifier(object, self.dialect())?));
+            }
+        }
+        let Some(model) = model else {
+            return Ok(());
+        };
+        let stmt = SQL::delete_from(model.table_name()).r#where(wheres.join(" OR ")).to_bound_sql(self.dialect());
+        self.run_execute(stmt).await.map_err(|err| error_ext::unknown_database_write_error(path, format!("{:?}", err)))?;
+        Ok(())
+    }
+
+    pub async fn migration_plan(&self, models: Vec<&Model>) -> Result<SQLMigrationPlan> {
+        SQLMigration::migrate(self.dialect(), self.queryable(), self.namespace, models, self, true, true, &[], &[], None).await
+    }
+
+    // before runs ahead of any generated statement, after once everything is applied,
+    // both on this transaction so they roll back with the migration
+    pub async fn migrate_with_hooks(&self, models: Vec<&Model>, before: &[String], after: &[String], dry_run: bool, silent: bool) -> Result<SQLMigrationPlan> {
+        SQLMigration::migrate(self.dialect(), self.queryable(), self.namespace, models, self, dry_run, silent, before, after, self.migration_timeout).await
+    }
+
+    // none when the database matches the models, otherwise the first operation migrating would perform
+    pub async fn pending_schema_change(&self, models: Vec<&Model>) -> Result<Option<String>> {
+        SQLMigration::pending_change(self.dialect(), self.queryable(), self.namespace, models, self).await
+    }
+
+    pub async fn delete_object_returning(&self, object: &Object, path: KeyPath) -> Result<Value> {
+        if object.is_new() {
+            return Err(error_ext::object_is_not_saved_thus_cant_be_deleted(path));
+        }
+        let r#where = Query::where_from_identifier(object, self.dialect())?;
+        let values = self.delete_returning(object.namespace(), object.model(), r#where, path).await?;
+        Ok(values.into_iter().next().unwrap_or(Value::Null))
+    }
+
+    pub async fn delete_many_returning(&self, model: &'static Model, r#where: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> Result<Vec<Value>> {
+        let r#where = Query::r#where(transaction_ctx.namespace(), model, r#where, self.dialect(), None)?;
+        self.delete_returning(transaction_ctx.namespace(), model, r#where, path).await
+    }
+
+    // mysql has no DELETE ... RETURNING, the rows are read right before deleting them
+    async fn delete_returning(&self, namespace: &Namespace, model: &Model, r#where: String, path: KeyPath) -> Result<Vec<Value>> {
+        let result_set = if self.dialect().is_mysql() {
+            let select = SQL::select(None, model.table_name()).r#where(r#where.clone()).to_bound_sql(self.dialect());
+            let result_set = self.run_query(select).await.map_err(|err| error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)))?;
+            let delete = SQL::delete_from(model.table_name()).r#where(r#where).to_bound_sql(self.dialect());
+            self.run_execute(delete).await.map_err(|err| error_ext::unknown_database_write_error(path.clone(), format!("{:?}", err)))?;
+            result_set
+        } else {
+            let delete = SQL::delete_from(model.table_name()).r#where(r#where).returning(&vec!["*"]).to_bound_sql(self.dialect());
+            self.run_query(delete).await.map_err(|err| error_ext::unknown_database_write_error(path.clone(), format!("{:?}", err)))?
+        };
+        let columns = result_set.columns().clone();
+        result_set.into_iter().map(|row| Execution::row_to_value(namespace, model, &row, &columns, self.dialect())).collect()
+    }
+
+    pub async fn db_pull(&self) -> Result<Vec<SQLTable>> {
+        SQLMigration::db_pull(self.dialect(), self.queryable()).await
+    }
+
     fn handle_err_result(&self, err: quaint_forked::error::Error, path: KeyPath) -> teo_result::Error {
         match err.kind() {
             UniqueConstraintViolation { constraint } => {
@@ -213,13 +795,19 @@ impl SQLTransaction {
 impl Transaction for SQLTransaction {
 
     async fn migrate(&self, models: Vec<&Model>, dry_run: bool, reset_database: bool, silent: bool) -> Result<()> {
-        SQLMigration::migrate(self.dialect(), self.queryable(), models, self).await
+        let plan = SQLMigration::migrate(self.dialect(), self.queryable(), self.namespace, models, self, dry_run, silent, &[], &[], self.migration_timeout).await?;
+        // the trait can't return the plan, show what would have run instead
+        if dry_run && !silent {
+            for stmt in plan.statements() {
+                log::info!("dry run migration: {}", stmt);
+            }
+        }
+        Ok(())
     }
 
     async fn purge(&self, models: Vec<&Model>) -> Result<()> {
         for model in models {
-            let escape = self.dialect().escape();
-            self.conn().execute(QuaintQuery::from(format!("DELETE FROM {escape}{}{escape}", model.table_name()))).await.unwrap();
+            self.conn().execute(QuaintQuery::from(format!("DELETE FROM {}", self.dialect().quote(model.table_name())))).await.unwrap();
         }
         Ok(())
     }
@@ -242,6 +830,13 @@ impl Transaction for SQLTransaction {
 
     async fn save_object(&self, object: &Object, path: KeyPath) -> teo_result::Result<()> {
         if object.is_new() {
+            if self.upsert_on_save {
+                // generated keys aren't known before the insert, nothing can conflict then
+                let keys: Vec<&str> = object.model().primary_index().unwrap().items().iter().map(|item| item.field.as_str()).collect();
+                if keys.iter().all(|k| object.get_value(k).map(|v| !v.is_null()).unwrap_or(false)) {
+                    return self.upsert_object(object, keys, path).await.map(|_| ());
+                }
+            }
             self.create_object(object, path).await
         } else {
             self.update_object(object, path).await
@@ -253,10 +848,8 @@ impl Transaction for SQLTransaction {
             return Err(error_ext::object_is_not_saved_thus_cant_be_deleted(path));
         }
         let model = object.model();
-        let r#where = Query::where_from_identifier(object, self.dialect());
-        let stmt = SQL::delete_from(model.table_name()).r#where(r#where).to_string(self.dialect());
-        // println!("see delete stmt: {}", stmt);
-        let result = self.queryable().execute(QuaintQuery::from(stmt)).await;
+        let r#where = Query::where_from_identifier(object, self.dialect())?;
+        let result = self.run_execute(SQL::delete_from(model.table_name()).r#where(r#where).to_bound_sql(self.dialect())).await;
         if result.is_err() {
             return Err(error_ext::unknown_database_write_error(path, format!("{:?}", result.err().unwrap())));
         } else {
@@ -265,7 +858,7 @@ impl Transaction for SQLTransaction {
     }
 
     async fn find_unique(&self, model: &'static Model, finder: &Value, ignore_select_and_include: bool, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> teo_result::Result<Option<Object>> {
-        let objects = Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await?;
+        let objects = Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), action, transaction_ctx, req_ctx, path).await?;
         if objects.is_empty() {
             Ok(None)
         } else {
@@ -274,27 +867,27 @@ impl Transaction for SQLTransaction {
     }
 
     async fn find_many(&self, model: &'static Model, finder: &Value, ignore_select_and_include: bool, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> teo_result::Result<Vec<Object>> {
-        Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await
+        Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), action, transaction_ctx, req_ctx, path).await
     }
 
     async fn count(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_result::Result<Value> {
-        Execution::query_count(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), path).await
     }
 
     async fn count_objects(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_result::Result<usize> {
-        Execution::query_count_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count_objects(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), path).await
     }
 
     async fn count_fields(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_result::Result<Value> {
-        Execution::query_count_fields(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count_fields(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), path).await
     }
 
     async fn aggregate(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_result::Result<Value> {
-        Execution::query_aggregate(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_aggregate(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), path).await
     }
 
     async fn group_by(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_result::Result<Vec<Value>> {
-        Execution::query_group_by(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_group_by(transaction_ctx.namespace(), self.queryable(), model, &self.finder_with_comment(finder), self.dialect(), path).await
     }
 
     async fn sql(&self, model: &'static Model, sql: &str, transaction_ctx: transaction::Ctx) -> Result<Vec<Value>> {
@@ -325,33 +918,99 @@ impl Transaction for SQLTransaction {
     }
 
     async fn commit(&self) -> Result<()> {
-        if let Some(tran) = &self.tran {
+        if let (Some(tran), Some(savepoint)) = (&self.tran, &self.savepoint) {
+            // sql server keeps savepoints until the transaction ends
+            if !self.dialect.is_mssql() {
+                self.savepoint_cmd(tran, format!("RELEASE SAVEPOINT {}", savepoint)).await?;
+            }
+        } else if let Some(tran) = &self.tran {
             match tran.commit().await {
                 Ok(()) => (),
                 Err(err) => return Err(Error::new(err.to_string()))
             }
         }
         self.committed.store(true, Ordering::SeqCst);
+        self.release_exclusive();
         Ok(())
     }
 
     async fn abort(&self) -> Result<()> {
-        if let Some(tran) = &self.tran {
-            match tran.rollback().await {
-                Ok(()) => (),
-                Err(err) => return Err(Error::new(err.to_string()))
-            };
-        }
-        Ok(())
+        let result = if let (Some(tran), Some(savepoint)) = (&self.tran, &self.savepoint) {
+            let stmt = if self.dialect.is_mssql() { format!("ROLLBACK TRANSACTION {}", savepoint) } else { format!("ROLLBACK TO SAVEPOINT {}", savepoint) };
+            self.savepoint_cmd(tran, stmt).await
+        } else if let Some(tran) = &self.tran {
+            tran.rollback().await.map_err(|err| Error::new(err.to_string()))
+        } else {
+            Ok(())
+        };
+        self.release_exclusive();
+        result
     }
 
     async fn spawn(&self) -> Result<Arc<dyn Transaction>> {
-        Ok(Arc::new(SQLTransaction {
-            dialect: self.dialect,
-            conn: self.conn.clone(),
-            tran: Some(Arc::new(start_owned_transaction(self.conn.clone(), None).await.unwrap())),
-            committed: Arc::new(AtomicBool::new(false)),
-        }))
+        Ok(Arc::new(self.spawn_transaction().await?))
+    }
+}
+
+impl SQLTransaction {
+
+    // inside a transaction the spawned one is a savepoint of it, so the outer rollback covers it
+    async fn spawn_transaction(&self) -> Result<SQLTransaction> {
+        let (tran, savepoint) = if let Some(tran) = &self.tran {
+            let savepoint = format!("teo_savepoint_{}", SAVEPOINT_ID.fetch_add(1, Ordering::SeqCst));
+            let stmt = if self.dialect.is_mssql() { format!("SAVE TRANSACTION {}", savepoint) } else { format!("SAVEPOINT {}", savepoint) };
+            self.savepoint_cmd(tran, stmt).await?;
+            (tran.clone(), Some(savepoint))
+        } else {
+            (Arc::new(start_owned_transaction(self.conn.clone(), None).await.unwrap()), None)
+        };
+        let mut transaction = SQLTransaction::new(self.dialect, self.conn.clone(), Some(tran), self.comment.clone(), self.slow_query.as_ref().map(|s| s.options()));
+        transaction.savepoint = savepoint;
+        transaction.migration_timeout = self.migration_timeout;
+        transaction.upsert_on_save = self.upsert_on_save;
+        transaction.namespace = self.namespace;
+        transaction.checked_out = self.checked_out.clone();
+        transaction.insert_returning = self.insert_returning.clone();
+        Ok(transaction)
+    }
+}
+
+// sql server upserts through MERGE, which isn't generated yet
+fn check_upsert(dialect: SQLDialect) -> Result<()> {
+    if dialect.is_mssql() {
+        Err(Error::new("upserts are not supported on SQL Server yet"))
+    } else {
+        Ok(())
+    }
+}
+
+static SAVEPOINT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// sqlite has RETURNING since 3.35 and mariadb since 10.5, mysql and sql server lack it
+// inserts `by` as the first value, a conflicting row gets `by` added to what it stores
+pub(crate) fn increment_statement<'a>(table: &'a str, mut values: Vec<(&'a str, &'a str)>, conflict_columns: Vec<String>, column: &'a str, by: &'a str, dialect: SQLDialect) -> SQLInsertIntoStatement<'a> {
+    let current = if dialect.is_mysql() {
+        dialect.quote(column)
+    } else {
+        format!("{}.{}", dialect.quote(table), dialect.quote(column))
+    };
+    values.push((column, by));
+    let mut stmt = SQL::insert_into(table);
+    stmt.values(values).on_conflict(conflict_columns, vec![(column.to_owned(), format!("{} + {}", current, by))]);
+    stmt
+}
+
+fn supports_insert_returning(dialect: SQLDialect, version: Option<&str>) -> bool {
+    let at_least = |min: (u32, u32)| {
+        let Some(version) = version else { return false };
+        let mut parts = version.split(|c: char| !c.is_ascii_digit()).filter(|p| !p.is_empty()).map(|p| p.parse::<u32>().unwrap_or(0));
+        (parts.next().unwrap_or(0), parts.next().unwrap_or(0)) >= min
+    };
+    match dialect {
+        SQLDialect::PostgreSQL => true,
+        SQLDialect::SQLite => at_least((3, 35)),
+        SQLDialect::MySQL => version.map(|v| v.contains("MariaDB")).unwrap_or(false) && at_least((10, 5)),
+        SQLDialect::MSSQL => false,
     }
 }
 
@@ -385,4 +1044,73 @@ fn sql_value_to_teon_value(value: &quaint_forked::Value) -> Value {
         return Value::from(value.as_json().unwrap());
     }
     return Value::Null
-}
\ No newline at end of file
+}
+// whether the row was inserted and what it returned, none when a conflicting
+// insert did nothing
+fn returned_upsert_row(result_set: ResultSet, path: &KeyPath) -> Result<Option<(bool, Vec<String>, ResultRow)>> {
+    let columns: Vec<String> = result_set.columns().iter().filter(|c| c.as_str() != "_inserted").cloned().collect();
+    let Some(row) = result_set.into_iter().next() else {
+        return Ok(None);
+    };
+    match row.get("_inserted").and_then(|v| v.as_bool()) {
+        Some(inserted) => Ok(Some((inserted, columns, row))),
+        None => Err(error_ext::unknown_database_write_error(path.clone(), "the upsert didn't report whether it inserted".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upsert_result(rows: Vec<Vec<QuaintValue<'static>>>) -> ResultSet {
+        ResultSet::new(vec!["id".to_owned(), "_inserted".to_owned()], rows)
+    }
+
+    #[test]
+    fn increments_add_to_the_stored_value() {
+        let stmt = increment_statement("counters", vec![("name", "'a'")], vec!["name".to_owned()], "n", "2", SQLDialect::PostgreSQL).returning(&vec!["\"n\""]).to_string(SQLDialect::PostgreSQL);
+        assert_eq!(stmt, "INSERT INTO \"counters\"(\"name\",\"n\") VALUES('a',2) ON CONFLICT (\"name\") DO UPDATE SET \"n\" = \"counters\".\"n\" + 2 RETURNING \"n\";");
+        let stmt = increment_statement("counters", vec![("name", "'a'")], vec!["name".to_owned()], "n", "2", SQLDialect::MySQL).to_string(SQLDialect::MySQL);
+        assert_eq!(stmt, "INSERT INTO `counters`(`name`,`n`) VALUES('a',2) ON DUPLICATE KEY UPDATE `n` = `n` + 2;");
+    }
+
+    #[test]
+    fn batch_groups_consecutive_deletes_of_a_table() {
+        let keys = [Some("users"), Some("users"), None, Some("users"), Some("posts"), Some("posts"), None, None];
+        assert_eq!(batch_runs(&keys), vec![0..2, 2..3, 3..4, 4..6, 6..7, 7..8]);
+        assert!(batch_runs::<&str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn upserts_are_rejected_on_sql_server() {
+        assert!(check_upsert(SQLDialect::MSSQL).is_err());
+        assert!(check_upsert(SQLDialect::PostgreSQL).is_ok());
+    }
+
+    #[test]
+    fn upsert_reports_inserted_and_updated_rows() {
+        let (inserted, columns, _) = returned_upsert_row(upsert_result(vec![vec![QuaintValue::int32(1), QuaintValue::boolean(true)]]), &path![]).unwrap().unwrap();
+        assert!(inserted);
+        assert_eq!(columns, vec!["id".to_owned()]);
+        let (inserted, _, _) = returned_upsert_row(upsert_result(vec![vec![QuaintValue::int32(1), QuaintValue::boolean(false)]]), &path![]).unwrap().unwrap();
+        assert!(!inserted);
+    }
+
+    #[test]
+    fn upsert_doing_nothing_returns_no_row() {
+        assert!(returned_upsert_row(upsert_result(vec![]), &path![]).unwrap().is_none());
+        let without_flag = ResultSet::new(vec!["id".to_owned()], vec![vec![QuaintValue::int32(1)]]);
+        assert!(returned_upsert_row(without_flag, &path![]).is_err());
+    }
+
+    #[test]
+    fn insert_returning_by_version() {
+        assert!(supports_insert_returning(SQLDialect::PostgreSQL, None));
+        assert!(supports_insert_returning(SQLDialect::SQLite, Some("3.45.0")));
+        assert!(!supports_insert_returning(SQLDialect::SQLite, Some("3.34.1")));
+        assert!(supports_insert_returning(SQLDialect::MySQL, Some("10.6.12-MariaDB-1:10.6.12+maria~ubu2004")));
+        assert!(!supports_insert_returning(SQLDialect::MySQL, Some("10.4.28-MariaDB")));
+        assert!(!supports_insert_returning(SQLDialect::MySQL, Some("8.0.36")));
+        assert!(!supports_insert_returning(SQLDialect::MSSQL, Some("16.0.1000.6")));
+    }
+}
@@ -1,17 +1,17 @@
 use std::borrow::Cow;
 use array_tool::vec::Uniq;
 use std::backtrace::Backtrace;
-use std::collections::HashMap;
 use async_recursion::async_recursion;
 use indexmap::IndexMap;
 use key_path::KeyPath;
 use quaint_forked::prelude::{Queryable, ResultRow};
-use quaint_forked::ast::{Query as QuaintQuery};
 use teo_parser::r#type::Type;
 use crate::query::Query;
+use crate::query::params::{bind, query_bound};
 use crate::schema::dialect::SQLDialect;
 use crate::schema::value::decode::RowDecoder;
-use crate::schema::value::encode::{SQLEscape, ToSQLString, ToWrapped};
+use crate::schema::value::encode::{SQLEscape, ToWrapped};
+use crate::stmts::select::SQLLock;
 use teo_runtime::action::Action;
 use teo_runtime::connection::transaction;
 use teo_runtime::model::field::column_named::ColumnNamed;
@@ -31,9 +31,11 @@ pub(crate) struct Execution { }
 
 impl Execution {
 
-    pub(crate) fn row_to_value(namespace: &Namespace, model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Value {
-        Value::Dictionary(columns.iter().filter_map(|column_name| {
-            if let Some(field) = model.field_with_column_name(column_name) {
+    pub(crate) fn row_to_value(namespace: &Namespace, model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> teo_result::Result<Value> {
+        Ok(Value::Dictionary(columns.iter().map(|column_name| {
+            Ok(if column_name == "_total_count" { // window count, read separately
+                None
+            } else if let Some(field) = model.field_with_column_name(column_name) {
                 if field.auto_increment() && dialect == SQLDialect::PostgreSQL {
                     Some((field.name().to_owned(), RowDecoder::decode_serial(field.is_optional(), row, column_name)))
                 } else {
@@ -47,16 +49,22 @@ impl Execution {
                 let field_name = names[1];
                 if relation_name == "c" { // cursor fetch, should remove
                     None
+                } else if relation_name == "_count" { // relation count
+                    Some((column_name.to_owned(), RowDecoder::decode(&Type::Int64, false, row, column_name, dialect)))
                 } else {
-                    let relation = model.relation(relation_name).unwrap();
+                    let Some(relation) = model.relation(relation_name) else {
+                        return Err(teo_result::Error::new(format!("unknown result column `{}'", column_name)));
+                    };
                     let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
-                    let field = opposite_model.field(field_name).unwrap();
+                    let Some(field) = opposite_model.field(field_name) else {
+                        return Err(teo_result::Error::new(format!("unknown result column `{}'", column_name)));
+                    };
                     Some((column_name.to_owned(), RowDecoder::decode(field.r#type(), field.is_optional(), row, column_name, dialect)))
                 }
             } else {
-                panic!("Unhandled key {}.", column_name);
-            }
-        }).collect())
+                return Err(teo_result::Error::new(format!("unknown result column `{}'", column_name)));
+            })
+        }).collect::<teo_result::Result<Vec<Option<(String, Value)>>>>()?.into_iter().flatten().collect()))
     }
 
     fn row_to_aggregate_value(model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Value {
@@ -70,8 +78,9 @@ impl Execution {
                 if !retval.contains_key(group) {
                     retval.insert(group.to_string(), Value::Dictionary(IndexMap::new()));
                 }
-                if group == "_count" { // force i64
-                    let count: i64 = row.get(result_key).unwrap().as_i64().unwrap();
+                if group == "_count" { // force i64, `_all` and field counts share the group
+                    let count_value = row.get(result_key).unwrap();
+                    let count: i64 = count_value.as_i64().or(count_value.as_i32().map(|c| c as i64)).unwrap();
                     retval.get_mut(group).unwrap().as_dictionary_mut().unwrap().insert(field_name.to_string(), teon!(count));
                 } else if group == "_avg" || group == "_sum" { // force f64
                     let v = RowDecoder::decode(&Type::Float, true, &row, result_key, dialect);
@@ -92,6 +101,24 @@ impl Execution {
 
     pub(crate) async fn query_objects<'a>(namespace: &Namespace, conn: &'a dyn Queryable, model: &'static Model, finder: &'a Value, dialect: SQLDialect, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<request::Ctx>, path: KeyPath) -> teo_result::Result<Vec<Object>> {
         let values = Self::query(namespace, conn, model, finder, dialect, path).await?;
+        Self::values_to_objects(values, model, finder, action, transaction_ctx, req_ctx)
+    }
+
+    pub(crate) async fn query_objects_with_total_count<'a>(namespace: &Namespace, conn: &'a dyn Queryable, model: &'static Model, finder: &'a Value, dialect: SQLDialect, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<request::Ctx>, path: KeyPath) -> teo_result::Result<(Vec<Object>, usize)> {
+        let mut total_count: Option<usize> = None;
+        let values = if dialect.is_postgres() || dialect.is_mysql() {
+            Self::query_internal(namespace, conn, model, finder, dialect, None, None, None, false, None, Some(&mut total_count), None, path.clone()).await?
+        } else {
+            Self::query(namespace, conn, model, finder, dialect, path.clone()).await?
+        };
+        let total_count = match total_count {
+            Some(total_count) => total_count,
+            None => Self::query_count_objects(namespace, conn, model, Self::without_paging_and_skip_take(finder).as_ref(), dialect, path).await?,
+        };
+        Ok((Self::values_to_objects(values, model, finder, action, transaction_ctx, req_ctx)?, total_count))
+    }
+
+    pub(crate) fn values_to_objects(values: Vec<Value>, model: &'static Model, finder: &Value, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<request::Ctx>) -> teo_result::Result<Vec<Object>> {
         let select = finder.as_dictionary().unwrap().get("select");
         let include = finder.as_dictionary().unwrap().get("include");
         let mut results = vec![];
@@ -104,10 +131,13 @@ impl Execution {
     }
 
     #[async_recursion]
-    async fn query_internal(namespace: &Namespace, conn: &dyn Queryable, model: &Model, value: &Value, dialect: SQLDialect, additional_where: Option<String>, additional_left_join: Option<String>, join_table_results: Option<Vec<String>>, force_negative_take: bool, additional_distinct: Option<Vec<String>>, path: KeyPath) -> teo_result::Result<Vec<Value>> {
+    async fn query_internal(namespace: &Namespace, conn: &dyn Queryable, model: &Model, value: &Value, dialect: SQLDialect, additional_where: Option<String>, additional_left_join: Option<String>, join_table_results: Option<Vec<String>>, force_negative_take: bool, additional_distinct: Option<Vec<String>>, total_count: Option<&mut Option<usize>>, lock: Option<SQLLock>, path: KeyPath) -> teo_result::Result<Vec<Value>> {
         let _select = value.get("select");
         let include = value.get("include");
         let original_distinct = value.get("distinct").map(|v| if v.as_array().unwrap().is_empty() { None } else { Some(v.as_array().unwrap()) }).flatten();
+        if let Some(original_distinct) = original_distinct {
+            Self::validate_distinct(model, original_distinct, path.clone())?;
+        }
         let distinct = Self::merge_distinct(original_distinct, additional_distinct);
         let skip = value.get("skip");
         let take = value.get("take");
@@ -117,10 +147,13 @@ impl Execution {
         } else {
             Cow::Borrowed(value)
         };
-        let stmt = Query::build(namespace, model, value_for_build.as_ref(), dialect, additional_where, additional_left_join, join_table_results, force_negative_take)?;
+        // the window count doesn't know about distinct rows, callers count separately then
+        let with_total_count = total_count.is_some() && distinct.is_none();
+        let (stmt, params) = Query::build(namespace, model, value_for_build.as_ref(), dialect, additional_where, additional_left_join, join_table_results, force_negative_take, with_total_count, lock)?;
         // println!("see sql query stmt: {}", &stmt);
         let reverse = Input::has_negative_take(value);
-        let rows = match conn.query(QuaintQuery::from(stmt)).await {
+        let result = query_bound(conn, &stmt, &params).await;
+        let rows = match result {
             Ok(rows) => rows,
             Err(err) => {
                 return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
@@ -129,22 +162,26 @@ impl Execution {
         if rows.is_empty() {
             return Ok(vec![])
         }
+        if with_total_count {
+            let count = rows.first().unwrap().get("_total_count").unwrap().as_i64().unwrap();
+            *total_count.unwrap() = Some(count as usize);
+        }
         let columns = rows.columns().clone();
-        let mut results = rows.into_iter().map(|row| Self::row_to_value(namespace, model, &row, &columns, dialect)).collect::<Vec<Value>>();
+        let mut results = rows.into_iter().map(|row| Self::row_to_value(namespace, model, &row, &columns, dialect)).collect::<teo_result::Result<Vec<Value>>>()?;
         if reverse {
             results.reverse();
         }
         if let Some(distinct) = distinct {
             let distinct_keys = distinct.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             results = results.unique_via(|a, b| {
-                Self::sub_hashmap(a, &distinct_keys) == Self::sub_hashmap(b, &distinct_keys)
+                Self::distinct_values(a, &distinct_keys) == Self::distinct_values(b, &distinct_keys)
             });
         }
         if should_in_memory_take_skip {
-            let skip = skip.map(|s| s.as_int64().unwrap()).unwrap_or(0) as usize;
-            let take = take.map(|s| s.as_int64().unwrap().abs() as u64).unwrap_or(0) as usize;
+            let skip = skip.map(|s| s.as_int64().unwrap().max(0)).unwrap_or(0) as usize;
+            let take = take.map(|s| s.as_int64().unwrap().unsigned_abs() as usize).unwrap_or(usize::MAX);
             results = results.into_iter().enumerate().filter(|(i, _r)| {
-                *i >= skip && *i < (skip + take)
+                *i >= skip && *i < skip.saturating_add(take)
             }).map(|(_i, r)| r.clone()).collect();
             if reverse {
                 results.reverse();
@@ -154,11 +191,14 @@ impl Execution {
             for (key, value) in include {
                 let skip = value.as_dictionary().map(|m| m.get("skip")).flatten().map(|v| v.as_int64().unwrap());
                 let take = value.as_dictionary().map(|m| m.get("take")).flatten().map(|v| v.as_int64().unwrap());
-                let take_abs = take.map(|t| t.abs() as u64);
+                let take_abs = take.map(|t| t.unsigned_abs());
                 let negative_take = take.map(|v| v.is_negative()).unwrap_or(false);
                 let inner_distinct = value.as_dictionary().map(|m| m.get("distinct")).flatten().map(|v| if v.as_array().unwrap().is_empty() { None } else { Some(v.as_array().unwrap()) }).flatten();
                 let relation = model.relation(key).unwrap();
                 let (opposite_model, _) = namespace.opposite_relation(relation);
+                if let Some(r#where) = value.get("where") {
+                    Self::validate_include_where(namespace, opposite_model, key, r#where, path.clone())?;
+                }
                 if !relation.has_join_table() {
                     let fields = relation.fields();
                     let opposite_fields = relation.references();
@@ -167,17 +207,21 @@ impl Execution {
                     } else {
                         opposite_fields.iter().map(|f| opposite_model.field(f).unwrap().column_name().escape(dialect)).collect::<Vec<String>>().join(",").to_wrapped()
                     };
+                    // a null foreign key has no related rows, keep it out of the IN list
+                    let keyed: Vec<&Value> = results.iter().filter(|r| {
+                        fields.iter().all(|f| r.get(f).map(|v| !v.is_null()).unwrap_or(false))
+                    }).collect();
                     let values = if opposite_fields.len() == 1 {
                         // in a (?,?,?,?,?) format
                         let field_name = fields.get(0).unwrap();
-                        results.iter().map(|v| {
-                            ToSQLString::to_string(&v.as_dictionary().unwrap().get(field_name).unwrap(), dialect)
-                        }).collect::<Vec<String>>().join(",").to_wrapped()
+                        keyed.iter().map(|v| {
+                            bind(v.as_dictionary().unwrap().get(field_name).unwrap(), model.field(field_name).unwrap().r#type(), true, dialect)
+                        }).collect::<teo_result::Result<Vec<String>>>()?.join(",").to_wrapped()
                     } else {
                         // in a (VALUES (?,?),(?,?)) format
-                        format!("(VALUES {})", results.iter().map(|o| {
-                            fields.iter().map(|f| ToSQLString::to_string(&o.as_dictionary().unwrap().get(f).unwrap(), dialect)).collect::<Vec<String>>().join(",").to_wrapped()
-                        }).collect::<Vec<String>>().join(","))
+                        format!("(VALUES {})", keyed.iter().map(|o| {
+                            Ok(fields.iter().map(|f| bind(o.as_dictionary().unwrap().get(f).unwrap(), model.field(f).unwrap().r#type(), true, dialect)).collect::<teo_result::Result<Vec<String>>>()?.join(",").to_wrapped())
+                        }).collect::<teo_result::Result<Vec<String>>>()?.join(","))
                     };
                     let where_addition = Query::where_item(&names, "IN", &values);
                     let nested_query = if value.is_dictionary() {
@@ -185,7 +229,11 @@ impl Execution {
                     } else {
                         Cow::Owned(teon!({}))
                     };
-                    let included_values = Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), None, None, negative_take, None, path.clone()).await?;
+                    let included_values = if keyed.is_empty() {
+                        vec![]
+                    } else {
+                        Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), None, None, negative_take, None, None, None, path.clone()).await?
+                    };
                     // println!("see included: {:?}", included_values);
                     for result in results.iter_mut() {
                         let mut skipped = 0;
@@ -196,13 +244,13 @@ impl Execution {
                         for included_value in included_values.iter() {
                             let mut matched = true;
                             for (field, reference) in relation.iter() {
-                                if included_value.get(reference).is_none() && result.get(field).is_none() {
-                                    matched = false;
-                                    break;
-                                }
-                                if included_value.get(reference) != result.get(field) {
-                                    matched = false;
-                                    break;
+                                // NULL never equals NULL
+                                match (included_value.get(reference), result.get(field)) {
+                                    (Some(a), Some(b)) if !a.is_null() && a == b => (),
+                                    _ => {
+                                        matched = false;
+                                        break;
+                                    }
                                 }
                             }
                             if matched {
@@ -246,12 +294,12 @@ impl Execution {
                         let references = through_relation.references();
                         let field_name = references.get(0).unwrap();
                         results.iter().map(|v| {
-                            ToSQLString::to_string(&v.as_dictionary().unwrap().get(field_name).unwrap(), dialect)
-                        }).collect::<Vec<String>>().join(",").to_wrapped()
+                            bind(v.as_dictionary().unwrap().get(field_name).unwrap(), model.field(field_name).unwrap().r#type(), true, dialect)
+                        }).collect::<teo_result::Result<Vec<String>>>()?.join(",").to_wrapped()
                     } else { // (VALUES (?,?),(?,?)) format
                         let pairs = results.iter().map(|o| {
-                            through_relation.references().iter().map(|f| ToSQLString::to_string(&o.as_dictionary().unwrap().get(f).unwrap(), dialect)).collect::<Vec<String>>().join(",").to_wrapped()
-                        }).collect::<Vec<String>>().join(",");
+                            Ok(through_relation.references().iter().map(|f| bind(o.as_dictionary().unwrap().get(f).unwrap(), model.field(f).unwrap().r#type(), true, dialect)).collect::<teo_result::Result<Vec<String>>>()?.join(",").to_wrapped())
+                        }).collect::<teo_result::Result<Vec<String>>>()?.join(",");
                         format!("(VALUES {})", pairs)
                     };
                     let where_addition = Query::where_item(&names, "IN", &values);
@@ -275,7 +323,7 @@ impl Execution {
                     } else {
                         None
                     };
-                    let included_values = Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), Some(left_join), Some(join_table_results), negative_take, additional_inner_distinct, path.clone()).await?;
+                    let included_values = Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), Some(left_join), Some(join_table_results), negative_take, additional_inner_distinct, None, None, path.clone()).await?;
                     // println!("see included {:?}", included_values);
                     for result in results.iter_mut() {
                         result.as_dictionary_mut().unwrap().insert(relation.name().to_owned(), Value::Array(vec![]));
@@ -285,13 +333,12 @@ impl Execution {
                             let mut matched = true;
                             for (_field, reference) in through_relation.iter() {
                                 let key = format!("{}.{}", opposite_relation.unwrap().name(), reference);
-                                if result.get(reference).is_none() && included_value.get(&key).is_none() {
-                                    matched = false;
-                                    break;
-                                }
-                                if result.get(reference) != included_value.get(&key) {
-                                    matched = false;
-                                    break;
+                                match (result.get(reference), included_value.get(&key)) {
+                                    (Some(a), Some(b)) if !a.is_null() && a == b => (),
+                                    _ => {
+                                        matched = false;
+                                        break;
+                                    }
                                 }
                             }
                             if matched {
@@ -318,12 +365,20 @@ impl Execution {
     }
 
     pub(crate) async fn query(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<Vec<Value>> {
-       Self::query_internal(namespace, conn, model, finder, dialect, None, None, None, false, None, path).await
+       Self::query_internal(namespace, conn, model, finder, dialect, None, None, None, false, None, None, None, path).await
+    }
+
+    // only the queried rows are locked, included relations are read plainly
+    pub(crate) async fn query_with_lock(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, lock: SQLLock, path: KeyPath) -> teo_result::Result<Vec<Value>> {
+        Self::query_internal(namespace, conn, model, finder, dialect, None, None, None, false, None, None, Some(lock), path).await
     }
 
     pub(crate) async fn query_aggregate(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<Value> {
-        let stmt = Query::build_for_aggregate(namespace, model, finder, dialect)?;
-        match conn.query(QuaintQuery::from(&*stmt)).await {
+        if let Some(distinct) = finder.get("distinct").map(|v| v.as_array().unwrap()) {
+            Self::validate_distinct(model, distinct, path.clone())?;
+        }
+        let (stmt, params) = Query::build_for_aggregate(namespace, model, finder, dialect)?;
+        match query_bound(conn, &stmt, &params).await {
             Ok(result_set) => {
                 let columns = result_set.columns().clone();
                 let result = result_set.into_iter().next().unwrap();
@@ -336,8 +391,8 @@ impl Execution {
     }
 
     pub(crate) async fn query_group_by(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<Vec<Value>> {
-        let stmt = Query::build_for_group_by(namespace, model, finder, dialect)?;
-        let rows = match conn.query(QuaintQuery::from(stmt)).await {
+        let (stmt, params) = Query::build_for_group_by(namespace, model, finder, dialect)?;
+        let rows = match query_bound(conn, &stmt, &params).await {
             Ok(rows) => rows,
             Err(err) => {
                 return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
@@ -359,8 +414,13 @@ impl Execution {
     }
 
     pub(crate) async fn query_count_objects(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<usize> {
-        let stmt = Query::build_for_count(namespace, model, finder, dialect, None, None, None, false)?;
-        match conn.query(QuaintQuery::from(stmt)).await {
+        if let Some(distinct) = finder.get("distinct").map(|v| v.as_array().unwrap()).filter(|d| !d.is_empty()) {
+            Self::validate_distinct(model, distinct, path.clone())?;
+            return Self::query_count_distinct_objects(namespace, conn, model, finder, distinct, dialect, path).await;
+        }
+        // a count is the size of the whole matched set, paging arguments don't narrow it
+        let (stmt, params) = Query::build_for_count(namespace, model, Self::without_pagination(finder).as_ref(), dialect, None, None, None, false)?;
+        match query_bound(conn, &stmt, &params).await {
             Ok(result) => {
                 let result = result.into_iter().next().unwrap();
                 let count: i64 = result.into_iter().next().unwrap().as_i64().unwrap();
@@ -372,6 +432,19 @@ impl Execution {
         }
     }
 
+    async fn query_count_distinct_objects(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, distinct: &Vec<Value>, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<usize> {
+        let (stmt, params) = Query::build_for_count_distinct(namespace, model, Self::without_pagination(finder).as_ref(), dialect, distinct)?;
+        match query_bound(conn, &stmt, &params).await {
+            Ok(result) => {
+                let result = result.into_iter().next().unwrap();
+                Ok(result.into_iter().next().unwrap().as_i64().unwrap() as usize)
+            },
+            Err(err) => {
+                return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
+            }
+        }
+    }
+
     pub(crate) async fn query_count_fields(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_result::Result<Value> {
         let new_finder = Value::Dictionary(finder.as_dictionary().unwrap().iter().map(|(k, v)| {
             if k.as_str() == "select" {
@@ -398,6 +471,20 @@ impl Execution {
         }
     }
 
+    // cursors narrow the window too
+    fn without_pagination(value: &Value) -> Cow<Value> {
+        let value = Self::without_paging_and_skip_take(value);
+        let map = value.as_dictionary().unwrap();
+        if map.contains_key("cursor") || map.contains_key("after") {
+            let mut map = map.clone();
+            map.remove("cursor");
+            map.remove("after");
+            Cow::Owned(Value::Dictionary(map))
+        } else {
+            value
+        }
+    }
+
     fn without_paging_and_skip_take_distinct(value: &Value) -> Cow<Value> {
         let map = value.as_dictionary().unwrap();
         if map.contains_key("take") || map.contains_key("skip") || map.contains_key("pageSize") || map.contains_key("pageNumber") {
@@ -413,13 +500,44 @@ impl Execution {
         }
     }
 
-    fn sub_hashmap(value: &Value, keys: &Vec<&str>) -> HashMap<String, Value> {
+    // like SQL DISTINCT, nulls equal each other. a key missing from the row
+    // reads as null, so absent and null values dedup together
+    fn distinct_values<'a>(value: &'a Value, keys: &Vec<&str>) -> Vec<&'a Value> {
         let map = value.as_dictionary().unwrap();
-        let mut retval = HashMap::new();
-        for key in keys {
-            retval.insert(key.to_string(), map.get(*key).cloned().unwrap_or(Value::Null));
+        keys.iter().map(|key| map.get(*key).unwrap_or(&Value::Null)).collect()
+    }
+
+    // distinct dedups the parent rows before includes are attached, so it
+    // can only name the model's own columns
+    fn validate_distinct(model: &Model, distinct: &Vec<Value>, path: KeyPath) -> teo_result::Result<()> {
+        for key in distinct {
+            let Some(key) = key.as_str() else {
+                return Err(teo_result::Error::new(format!("distinct keys should be field names: {}", path)));
+            };
+            if model.field(key).is_some() || model.property(key).is_some() {
+                continue;
+            }
+            let reason = if model.relation(key).is_some() { "is a relation" } else { "is not a field" };
+            return Err(teo_result::Error::new(format!("distinct key '{}' {}, only the model's own fields can be distinct: {}", key, reason, path)));
         }
-        retval
+        Ok(())
+    }
+
+    // the where of an include applies to the related model, unknown keys would be left out of the query
+    fn validate_include_where<'a>(namespace: &'a Namespace, model: &'a Model, relation_name: &str, r#where: &Value, path: KeyPath) -> teo_result::Result<()> {
+        let resolve = |model: &'a Model, key: &str| if model.field(key).is_some() || model.property(key).is_some() {
+            WhereKey::Column
+        } else if let Some(relation) = model.relation(key) {
+            match namespace.model_at_path(&relation.model_path()) {
+                Some(related_model) => WhereKey::Relation(related_model, relation.is_vec()),
+                None => WhereKey::Unknown,
+            }
+        } else {
+            WhereKey::Unknown
+        };
+        check_where_keys(model, r#where, &resolve, "").map_err(|reason| {
+            teo_result::Error::new(format!("where of include '{}' is invalid, {}: {}", relation_name, reason, path))
+        })
     }
 
     fn merge_distinct(value1: Option<&Vec<Value>>, value2: Option<Vec<String>>) -> Option<Vec<String>> {
@@ -441,3 +559,77 @@ impl Execution {
         }
     }
 }
+
+// what a key of a where names on a model
+enum WhereKey<M> {
+    Column,
+    Relation(M, bool),
+    Unknown,
+}
+
+// walks AND, OR, NOT and relation filters, the error names the first key which doesn't resolve
+fn check_where_keys<M: Copy>(model: M, r#where: &Value, resolve: &dyn Fn(M, &str) -> WhereKey<M>, prefix: &str) -> std::result::Result<(), String> {
+    let Some(map) = r#where.as_dictionary() else {
+        return Err(format!("'{}' should be a dictionary", if prefix.is_empty() { "where" } else { prefix }));
+    };
+    for (key, value) in map {
+        let key_path = if prefix.is_empty() { key.to_owned() } else { format!("{prefix}.{key}") };
+        match key.as_str() {
+            "AND" | "OR" => {
+                let Some(items) = value.as_array() else {
+                    return Err(format!("'{}' should be a list", key_path));
+                };
+                for item in items {
+                    check_where_keys(model, item, resolve, prefix)?;
+                }
+            }
+            "NOT" => check_where_keys(model, value, resolve, prefix)?,
+            _ => match resolve(model, key) {
+                WhereKey::Column => (),
+                WhereKey::Unknown => return Err(format!("'{}' is not a field", key_path)),
+                WhereKey::Relation(related_model, is_vec) => {
+                    let Some(filters) = value.as_dictionary() else {
+                        return Err(format!("'{}' should be a dictionary", key_path));
+                    };
+                    let operators = if is_vec { vec!["some", "none", "every"] } else { vec!["is", "isNot"] };
+                    if filters.keys().all(|k| operators.contains(&k.as_str())) {
+                        for (operator, filter) in filters {
+                            check_where_keys(related_model, filter, resolve, &format!("{key_path}.{operator}"))?;
+                        }
+                    } else if is_vec {
+                        return Err(format!("'{}' should use some, none or every", key_path));
+                    } else {
+                        // a to-one relation may be filtered by its fields directly
+                        check_where_keys(related_model, value, resolve, &key_path)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // models are stood in for by their names
+    fn resolve(model: &'static str, key: &str) -> WhereKey<&'static str> {
+        match (model, key) {
+            ("Post", "title") | ("User", "name") => WhereKey::Column,
+            ("Post", "author") => WhereKey::Relation("User", false),
+            ("User", "posts") => WhereKey::Relation("Post", true),
+            _ => WhereKey::Unknown,
+        }
+    }
+
+    #[test]
+    fn include_where_keys_are_checked_at_every_level() {
+        assert_eq!(check_where_keys("Post", &teon!({"OR": [{"title": "a"}, {"NOT": {"author": {"is": {"name": "b"}}}}]}), &resolve, ""), Ok(()));
+        assert_eq!(check_where_keys("Post", &teon!({"author": {"name": "b"}}), &resolve, ""), Ok(()));
+        assert_eq!(check_where_keys("Post", &teon!({"AND": [{"NOT": {"titel": "a"}}]}), &resolve, ""), Err("'titel' is not a field".to_owned()));
+        assert_eq!(check_where_keys("Post", &teon!({"author": {"is": {"posts": {"some": {"body": "c"}}}}}), &resolve, ""), Err("'author.is.posts.some.body' is not a field".to_owned()));
+        assert_eq!(check_where_keys("User", &teon!({"posts": {"title": "a"}}), &resolve, ""), Err("'posts' should use some, none or every".to_owned()));
+        assert_eq!(check_where_keys("Post", &teon!({"OR": {"title": "a"}}), &resolve, ""), Err("'OR' should be a list".to_owned()));
+    }
+}
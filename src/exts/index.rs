@@ -4,6 +4,21 @@ use teo_runtime::model::index::Item;
 use crate::exts::sort::SortExt;
 use crate::schema::dialect::SQLDialect;
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexOptions {
+    pub nulls_not_distinct: bool,
+    pub method: Option<String>,
+}
+
+impl IndexOptions {
+
+    // btree is the default everywhere, keep it out of comparisons
+    pub(crate) fn normalized_method(method: &str) -> Option<String> {
+        let method = method.to_lowercase();
+        if method == "btree" { None } else { Some(method) }
+    }
+}
+
 pub trait IndexExt {
 
     fn psql_primary_to_unique(&self, table_name: &str) -> Self;
@@ -24,6 +39,10 @@ pub trait IndexExt {
 
     fn to_sql_create(&self, dialect: SQLDialect, table_name: &str) -> String;
 
+    fn sql_options(&self, dialect: SQLDialect, supports_nulls_not_distinct: bool) -> IndexOptions;
+
+    fn to_sql_create_with_options(&self, dialect: SQLDialect, table_name: &str, options: &IndexOptions) -> String;
+
     fn sql_format_item(dialect: SQLDialect, item: &Item, table_create_mode: bool) -> String;
 }
 
@@ -34,7 +53,7 @@ impl IndexExt for teo_runtime::model::Index {
     }
 
     fn sql_name(&self, table_name: &str, dialect: SQLDialect) -> Cow<str> {
-        if self.r#type().is_primary() {
+        let name = if self.r#type().is_primary() {
             Cow::Owned(self.normalize_name(table_name, dialect))
         } else {
             if dialect.is_sqlite() || (dialect.is_postgres() && !self.name().ends_with("pkey")) {
@@ -42,6 +61,11 @@ impl IndexExt for teo_runtime::model::Index {
             } else {
                 Cow::Borrowed(self.name())
             }
+        };
+        if name.len() > dialect.max_identifier_length() {
+            Cow::Owned(truncate_identifier(name.as_ref(), dialect.max_identifier_length()))
+        } else {
+            name
         }
     }
 
@@ -86,32 +110,54 @@ impl IndexExt for teo_runtime::model::Index {
     }
 
     fn to_sql_drop(&self, dialect: SQLDialect, table_name: &str) -> String {
-        let escape = dialect.escape();
-        let index_name_cow = self.sql_name(table_name, dialect);
-        let index_name = index_name_cow.as_ref();
+        let index_name = dialect.quote(self.sql_name(table_name, dialect).as_ref());
+        let table_name = dialect.quote(table_name);
         if dialect == SQLDialect::PostgreSQL {
-            format!("DROP INDEX {escape}{index_name}{escape}")
+            format!("DROP INDEX {index_name}")
         } else if dialect == SQLDialect::SQLite {
-            format!("DROP INDEX IF EXISTS {escape}{index_name}{escape}")
+            format!("DROP INDEX IF EXISTS {index_name}")
         } else {
-            format!("DROP INDEX {escape}{index_name}{escape} ON {escape}{table_name}{escape}")
+            format!("DROP INDEX {index_name} ON {table_name}")
         }
     }
 
     fn to_sql_create(&self, dialect: SQLDialect, table_name: &str) -> String {
-        let escape = dialect.escape();
-        let index_name_cow = self.sql_name(table_name, dialect);
-        let index_name = index_name_cow.as_ref();
-        let unique = if self.r#type().is_unique() { "UNIQUE " } else { "" };
+        self.to_sql_create_with_options(dialect, table_name, &IndexOptions::default())
+    }
+
+    fn sql_options(&self, dialect: SQLDialect, supports_nulls_not_distinct: bool) -> IndexOptions {
+        IndexOptions {
+            nulls_not_distinct: supports_nulls_not_distinct && dialect.is_postgres() && self.nulls_not_distinct(),
+            method: if dialect.is_sqlite() { None } else { self.method().map(|m| IndexOptions::normalized_method(m)).flatten() },
+        }
+    }
+
+    fn to_sql_create_with_options(&self, dialect: SQLDialect, table_name: &str, options: &IndexOptions) -> String {
+        let index_name = dialect.quote(self.sql_name(table_name, dialect).as_ref());
+        let table_name = dialect.quote(table_name);
+        let kind = match options.method.as_deref() {
+            Some("fulltext") if dialect.is_mysql() => "FULLTEXT ",
+            Some("spatial") if dialect.is_mysql() => "SPATIAL ",
+            _ => if self.r#type().is_unique() { "UNIQUE " } else { "" },
+        };
+        let using = match options.method.as_deref() {
+            Some(method) if dialect.is_postgres() => Cow::Owned(format!(" USING {method} ")),
+            _ => Cow::Borrowed(""),
+        };
         let fields: Vec<String> = self.items().iter().map(|item| {
-            Self::sql_format_item(dialect, item, false)
+            if options.method.is_some() {
+                // only btree accepts a sort order
+                dialect.quote(&item.field)
+            } else {
+                Self::sql_format_item(dialect, item, false)
+            }
         }).collect();
-        format!("CREATE {unique}INDEX {escape}{index_name}{escape} ON {escape}{table_name}{escape}({})", fields.join(","))
+        let nulls_not_distinct = if options.nulls_not_distinct && dialect.is_postgres() && self.r#type().is_unique() { " NULLS NOT DISTINCT" } else { "" };
+        format!("CREATE {kind}INDEX {index_name} ON {table_name}{using}({}){nulls_not_distinct}", fields.join(","))
     }
 
     fn sql_format_item(dialect: SQLDialect, item: &Item, table_create_mode: bool) -> String {
-        let escape = dialect.escape();
-        let name = &item.field;
+        let name = dialect.quote(&item.field);
         let sort = item.sort.to_str();
         let len = if let Some(len) = item.len {
             if dialect == SQLDialect::MySQL {
@@ -123,9 +169,24 @@ impl IndexExt for teo_runtime::model::Index {
             Cow::Borrowed("")
         };
         if table_create_mode && dialect == SQLDialect::PostgreSQL {
-            format!("{escape}{name}{escape}")
+            name
         } else {
-            format!("{escape}{name}{escape}{len} {sort}")
+            format!("{name}{len} {sort}")
         }
     }
-}
\ No newline at end of file
+}
+
+// keeps generated names equal to what the database stores, the hash of the full
+// name keeps long names sharing a prefix apart
+fn truncate_identifier(name: &str, max_length: usize) -> String {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in name.bytes() {
+        hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+    }
+    let suffix = format!("_{:08x}", hash);
+    let mut end = max_length - suffix.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], suffix)
+}
@@ -1,27 +1,36 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::time::Duration;
 use itertools::Itertools;
-use maplit::hashset;
+use maplit::{hashmap, hashset};
 use quaint_forked::pooled::{Quaint};
-use quaint_forked::prelude::Queryable;
+use quaint_forked::prelude::{Queryable, ResultSet};
 use quaint_forked::ast::Query;
 use crate::migration::sql::{sqlite_auto_increment_query, sqlite_list_indices_query};
 use super::super::url::url_utils;
+use crate::migration::plan::{SQLMigrationOperation, SQLMigrationPlan};
 use crate::schema::column::decoder::{ColumnDecoder, ColumnManipulation};
 use crate::stmts::create::table::SQLCreateTableStatement;
 use crate::stmts::SQL;
+use crate::query::params::unescape_marks;
 use crate::schema::column::SQLColumn;
+use crate::schema::table::SQLTable;
 use crate::schema::dialect::SQLDialect;
 use teo_runtime::model::Model;
-use crate::schema::value::encode::ToSQLString;
+use teo_runtime::namespace::Namespace;
+use teo_parser::r#type::Type as FieldType;
+use crate::schema::value::encode::{ToSQLString, ToSQLInputDialect};
 use teo_runtime::connection::connection::Connection;
 use teo_runtime::connection::transaction::Transaction;
 use teo_runtime::sort::Sort;
 use teo_runtime::model::{Index, index::Item};
 use teo_runtime::model::index::Type;
-use teo_result::{Result};
+use teo_result::{Error, Result};
+use teo_runtime::value::Value;
+use teo_runtime::database::r#type::DatabaseType;
+use teo_runtime::database::mysql::r#type::MySQLType;
 use crate::exts::database_type::DatabaseTypeToSQLString;
-use crate::exts::index::IndexExt;
+use crate::exts::index::{IndexExt, IndexOptions};
 use crate::exts::sort::SortExt;
 
 pub(crate) struct SQLMigration { }
@@ -78,7 +87,7 @@ impl SQLMigration {
         }
         // use database
         if dialect == SQLDialect::PostgreSQL {
-            let stmt = format!("SET search_path TO {db_name}");
+            let stmt = format!("SET search_path TO \"{db_name}\"");
             conn.execute(Query::from(stmt)).await.unwrap();
         } else {
             let stmt = SQL::r#use().database(db_name).to_string(dialect);
@@ -88,82 +97,136 @@ impl SQLMigration {
 
     // Migrate
 
-    pub(crate) async fn db_columns(conn: &dyn Queryable, dialect: SQLDialect, table_name: &str) -> HashSet<SQLColumn> {
+    pub(crate) async fn db_columns(conn: &dyn Queryable, dialect: SQLDialect, table_name: &str) -> Result<HashSet<SQLColumn>> {
         match dialect {
             SQLDialect::SQLite => {
-                let columns_result = conn.query(Query::from(format!("pragma table_info('{}')", table_name))).await.unwrap();
-                let indices_result = conn.query(Query::from(sqlite_list_indices_query(table_name))).await.unwrap();
-                let auto_increment_result = conn.query(Query::from(sqlite_auto_increment_query(table_name))).await.unwrap();
-                let db_columns = ColumnDecoder::decode_sqlite_columns(columns_result, indices_result, auto_increment_result);
-                db_columns
+                let columns_result = Self::query(conn, &format!("pragma table_info('{}')", table_name)).await?;
+                let indices_result = Self::query(conn, &sqlite_list_indices_query(table_name)).await?;
+                let auto_increment_result = Self::query(conn, &sqlite_auto_increment_query(table_name)).await?;
+                let db_columns = ColumnDecoder::decode_sqlite_columns(columns_result, indices_result, auto_increment_result)?;
+                Ok(db_columns)
             }
             _ => {
                 let mut results = hashset! {};
-                let db_table_columns = conn.query(if dialect == SQLDialect::PostgreSQL {
-                    let desc = format!("SELECT * FROM information_schema.columns where table_name = '{}'", table_name);
-                    Query::from(desc)
+                let desc = if dialect == SQLDialect::PostgreSQL {
+                    format!("SELECT * FROM information_schema.columns where table_schema = current_schema() and table_name = '{}'", table_name)
                 } else {
-                    let desc = SQL::describe(table_name).to_string(dialect);
-                    Query::from(desc)
-                }).await.unwrap();
+                    SQL::describe(table_name).to_string(dialect)
+                };
+                let db_table_columns = Self::query(conn, &desc).await?;
                 for db_table_column in db_table_columns {
-                    let db_column = ColumnDecoder::decode(db_table_column, dialect, conn, table_name).await;
+                    let db_column = ColumnDecoder::decode(db_table_column, dialect, conn, table_name).await?;
                     results.insert(db_column);
                 }
-                results
+                Ok(results)
             }
         }
     }
 
-    pub(crate) async fn get_db_user_tables(dialect: SQLDialect, conn: &dyn Queryable) -> Vec<String> {
+    pub(crate) async fn get_db_user_tables(dialect: SQLDialect, conn: &dyn Queryable) -> Result<Vec<String>> {
         match dialect {
             SQLDialect::MySQL => {
                 let sql = "SHOW TABLES";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+                let db_result = Self::query(conn, &sql).await?;
+                Ok(db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect())
             }
             SQLDialect::PostgreSQL => {
                 let sql = "SELECT tablename FROM pg_catalog.pg_tables WHERE schemaname != 'pg_catalog' AND schemaname != 'information_schema'";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+                let db_result = Self::query(conn, &sql).await?;
+                Ok(db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect())
             }
             SQLDialect::SQLite => {
                 let sql = "SELECT name FROM sqlite_master WHERE type in ('table') AND name not like 'sqlite?_%' escape '?'";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+                let db_result = Self::query(conn, &sql).await?;
+                Ok(db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect())
             }
             _ => unreachable!()
         }
     }
 
-    pub(crate) async fn rename_table(dialect: SQLDialect, conn: &dyn Queryable, old_name: &str, new_name: &str) {
-        let escape = dialect.escape();
-        let sql = format!("ALTER TABLE {escape}{old_name}{escape} RENAME TO {escape}{new_name}{escape}");
-        conn.execute(Query::from(sql)).await.unwrap();
+    pub(crate) fn rename_table_stmt(dialect: SQLDialect, old_name: &str, new_name: &str) -> String {
+        format!("ALTER TABLE {} RENAME TO {}", dialect.quote(old_name), dialect.quote(new_name))
     }
 
-    pub(crate) async fn table_has_records(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> bool {
-        let escape = dialect.escape();
-        let sql = format!("select * from {escape}{table_name}{escape} limit 1");
-        !conn.query(Query::from(sql)).await.unwrap().is_empty()
+    // the first listed old name that exists, exact matches before case insensitive ones,
+    // tables still claimed by another model are never taken
+    fn rename_candidate(db_tables: &Vec<String>, old_names: &Vec<String>, model_table_names: &Vec<String>) -> Option<String> {
+        let available = db_tables.iter().filter(|t| !model_table_names.contains(t)).collect::<Vec<&String>>();
+        if let Some(old_name) = old_names.iter().find(|n| available.contains(n)) {
+            return Some(old_name.clone());
+        }
+        old_names.iter().find_map(|n| available.iter().find(|t| t.eq_ignore_ascii_case(n)).map(|t| t.to_string()))
+    }
+
+    pub(crate) async fn table_has_records(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<bool> {
+        let sql = format!("select * from {} limit 1", dialect.quote(table_name));
+        Ok(!Self::query(conn, &sql).await?.is_empty())
     }
 
-    pub(crate) async fn migrate(dialect: SQLDialect, conn: &dyn Queryable, models: Vec<&Model>, pconn: &dyn Transaction) -> Result<()> {
-        let mut db_tables = Self::get_db_user_tables(dialect, conn).await;
+    // the client timeout can't stop a statement the server is running, the server is
+    // given the same limit for the migration and its own setting afterwards
+    pub(crate) async fn migrate(dialect: SQLDialect, conn: &dyn Queryable, namespace: Option<&Namespace>, models: Vec<&Model>, pconn: &dyn Transaction, dry_run: bool, silent: bool, before: &[String], after: &[String], statement_timeout: Option<Duration>) -> Result<SQLMigrationPlan> {
+        let Some(duration) = statement_timeout.filter(|_| !dry_run) else {
+            return Self::migrate_models(dialect, conn, namespace, models, pconn, dry_run, silent, before, after, statement_timeout, false).await;
+        };
+        let reset = match dialect {
+            SQLDialect::SQLite => {
+                let previous = Self::query(conn, "PRAGMA busy_timeout").await?.into_iter().next().and_then(|row| row.get("timeout").and_then(|v| v.as_integer())).unwrap_or(0);
+                Self::server_timeout_stmt(dialect, Some(Duration::from_millis(previous as u64)))
+            }
+            _ => Self::server_timeout_stmt(dialect, None),
+        };
+        let set = Self::server_timeout_stmt(dialect, Some(duration));
+        if let Err(err) = conn.raw_cmd(&set).await {
+            return Err(Error::new(format!("migration statement `{}' failed: {}", set, err.to_string())));
+        }
+        let result = Self::migrate_models(dialect, conn, namespace, models, pconn, dry_run, silent, before, after, statement_timeout, false).await;
+        // a failed postgres transaction rejects this, rolling back restores the setting anyway
+        let _ = conn.raw_cmd(&reset).await;
+        result
+    }
+
+    // mysql only limits reads with max_execution_time, ddl mostly waits on metadata locks.
+    // none restores the default
+    fn server_timeout_stmt(dialect: SQLDialect, duration: Option<Duration>) -> String {
+        match (dialect, duration) {
+            (SQLDialect::PostgreSQL, Some(duration)) => format!("SET statement_timeout = {}", duration.as_millis()),
+            (SQLDialect::PostgreSQL, None) => "RESET statement_timeout".to_owned(),
+            (SQLDialect::MySQL, Some(duration)) => format!("SET SESSION lock_wait_timeout = {}", duration.as_secs().max(1)),
+            (SQLDialect::MySQL, None) => "SET SESSION lock_wait_timeout = DEFAULT".to_owned(),
+            (SQLDialect::SQLite, duration) => format!("PRAGMA busy_timeout = {}", duration.map(|d| d.as_millis()).unwrap_or(0)),
+            (SQLDialect::MSSQL, Some(duration)) => format!("SET LOCK_TIMEOUT {}", duration.as_millis()),
+            (SQLDialect::MSSQL, None) => "SET LOCK_TIMEOUT -1".to_owned(),
+        }
+    }
+
+    // with first_only the tables after the first changed one aren't compared
+    async fn migrate_models(dialect: SQLDialect, conn: &dyn Queryable, namespace: Option<&Namespace>, models: Vec<&Model>, pconn: &dyn Transaction, dry_run: bool, silent: bool, before: &[String], after: &[String], statement_timeout: Option<Duration>, first_only: bool) -> Result<SQLMigrationPlan> {
+        let mut plan = SQLMigrationPlan::default();
+        Self::apply_hooks(conn, &mut plan, dry_run, before).await?;
+        let mut db_tables = Self::get_db_user_tables(dialect, conn).await?;
+        let supports_nulls_not_distinct = dialect.is_postgres() && Self::psql_server_version_num(conn).await? >= 150000;
+        let model_table_names: Vec<String> = models.iter().map(|m| m.table_name().to_string()).collect();
         // compare each table and do migration
         for model in models {
+            if first_only && !plan.is_empty() {
+                return Ok(plan);
+            }
             let table_name = model.table_name();
+            // a dry run doesn't rename, the database still knows the table by its old name
+            let mut db_table_name = table_name.to_string();
             if let Some(migration) = Some(model.migration()) {
                 if !db_tables.iter().any(|x| x == table_name) {
-                    if let Some(old_name) = &migration.renamed {
-                        for old_name in old_name {
-                            if db_tables.contains(old_name) {
-                                // rename
-                                Self::rename_table(dialect, conn, old_name.as_str(), table_name).await;
-                                let index = db_tables.clone().iter().find_position(|v| *v == old_name).unwrap().0;
-                                db_tables.remove(index);
-                                db_tables.push(table_name.to_string());
-                                break;
+                    if let Some(old_names) = &migration.renamed {
+                        if let Some(old_name) = Self::rename_candidate(&db_tables, old_names, &model_table_names) {
+                            // rename
+                            Self::operation(&mut plan, silent, SQLMigrationOperation::RenameTable { old: old_name.clone(), new: table_name.to_string() });
+                            Self::apply(conn, &mut plan, dry_run, statement_timeout, Self::rename_table_stmt(dialect, old_name.as_str(), table_name)).await?;
+                            let index = db_tables.iter().find_position(|v| **v == old_name).unwrap().0;
+                            db_tables.remove(index);
+                            db_tables.push(table_name.to_string());
+                            if dry_run {
+                                db_table_name = old_name;
                             }
                         }
                     }
@@ -173,125 +236,238 @@ impl SQLMigration {
             let is_table_exist = db_tables.iter().any(|x| x == table_name);
             if !is_table_exist {
                 // table not exist, create table
-                Self::create_table(dialect, conn, model).await;
+                Self::operation(&mut plan, silent, SQLMigrationOperation::CreateTable(table_name.to_string()));
+                for stmt in Self::create_table_stmts(dialect, model, supports_nulls_not_distinct) {
+                    Self::apply(conn, &mut plan, dry_run, statement_timeout, stmt).await?;
+                }
             } else {
                 // remove from list
                 let index = db_tables.clone().iter().find_position(|x| *x == table_name).unwrap().0;
                 db_tables.remove(index);
                 // start migrate for this table
                 let model_columns = ColumnDecoder::decode_model_columns(model);
-                let db_columns = Self::db_columns(conn, dialect, table_name).await;
+                let db_columns = Self::db_columns(conn, dialect, &db_table_name).await?;
                 let need_to_alter_any_column = ColumnDecoder::need_to_alter_any_columns(&db_columns, &model_columns);
                 if need_to_alter_any_column && dialect == SQLDialect::SQLite {
-                    panic!("SQLite doesn't support column altering");
+                    return Err(Error::new(format!("cannot migrate table `{}', SQLite doesn't support column altering", table_name)));
                 }
-                let table_has_records = Self::table_has_records(dialect, conn, table_name).await;
-                let db_indices = Self::db_indices(dialect, conn, model).await;
+                let table_has_records = Self::table_has_records(dialect, conn, &db_table_name).await?;
+                let db_indices = Self::db_indices(dialect, conn, &db_table_name).await?;
                 let model_indices = Self::normalized_model_indices(model.indexes().values().collect(), dialect, table_name);
+                let model_index_options: HashMap<String, IndexOptions> = model.indexes().values().map(|i| {
+                    (i.sql_name(table_name, dialect).into_owned(), i.sql_options(dialect, supports_nulls_not_distinct))
+                }).collect();
+                let db_index_options = Self::db_index_options(dialect, conn, &db_table_name, supports_nulls_not_distinct).await?;
+                let index_options_changed = model_index_options.iter().filter(|(name, options)| {
+                    db_index_options.get(*name).cloned().unwrap_or_default() != **options
+                }).map(|(name, _)| name.clone()).collect();
                 // here update columns and indices
-                let manipulations = ColumnDecoder::manipulations(&db_columns, &model_columns, &db_indices, &model_indices, model);
+                let manipulations = ColumnDecoder::manipulations(&db_columns, &model_columns, &db_indices, &model_indices, &index_options_changed, model);
                 if table_has_records && manipulations.iter().find(|m| m.is_add_column_non_null()).is_some() && model.allows_drop_when_migrate() {
-                    Self::drop_table(dialect, conn, table_name).await;
-                    Self::create_table(dialect, conn, model).await;
+                    Self::operation(&mut plan, silent, SQLMigrationOperation::DropTable(table_name.to_string()));
+                    Self::apply(conn, &mut plan, dry_run, statement_timeout, Self::drop_table_stmt(dialect, table_name)).await?;
+                    Self::operation(&mut plan, silent, SQLMigrationOperation::CreateTable(table_name.to_string()));
+                    for stmt in Self::create_table_stmts(dialect, model, supports_nulls_not_distinct) {
+                        Self::apply(conn, &mut plan, dry_run, statement_timeout, stmt).await?;
+                    }
                 } else {
                     for m in manipulations.iter() {
                         match m {
                             ColumnManipulation::CreateIndex(index) => {
                                 if !index.name().starts_with("teo_primary_sqlite_index") {
-                                    let create = index.to_sql_create(dialect, table_name);
-                                    conn.execute(Query::from(create)).await.unwrap();
+                                    Self::operation(&mut plan, silent, m.to_operation(table_name));
+                                    let create = index.to_sql_create_with_options(dialect, table_name, &model_index_options.get(index.name()).cloned().unwrap_or_default());
+                                    Self::apply(conn, &mut plan, dry_run, statement_timeout, create).await?;
                                 }
                             }
                             ColumnManipulation::DropIndex(index) => {
                                 if !index.name().starts_with("teo_primary_sqlite_index") {
+                                    Self::operation(&mut plan, silent, m.to_operation(table_name));
                                     let drop = index.to_sql_drop(dialect, table_name);
-                                    conn.execute(Query::from(drop)).await.unwrap();
+                                    Self::apply(conn, &mut plan, dry_run, statement_timeout, drop).await?;
                                 }
                             }
                             ColumnManipulation::AddColumn(column, default) => {
                                 if column.not_null() && default.is_none() {
                                     // if any records, just raise here
-                                    let has_records = Self::table_has_records(dialect, conn, table_name).await;
-                                    if has_records {
-                                        panic!("Cannot add new non null column `{}', table `{}' has records. Consider add a default value or drop the table.", column.name(), table_name)
+                                    if table_has_records {
+                                        return Err(Error::new(format!("Cannot add new non null column `{}', table `{}' has records. Consider add a default value or drop the table.", column.name(), table_name)));
                                     }
                                 }
+                                Self::operation(&mut plan, silent, m.to_operation(table_name));
                                 let mut c = column.clone().clone();
-                                if default.is_some() {
-                                    c.set_default(Some(ToSQLString::to_string(&default.as_ref().unwrap(), dialect)));
+                                if let Some(default) = default {
+                                    let r#enum = model.field(column.name()).map(|f| f.r#type().unwrap_optional()).filter(|t| t.is_enum_variant());
+                                    c.set_default(Some(if let Some(r#enum) = r#enum {
+                                        let members = Self::enum_members(namespace, column, r#enum)?;
+                                        Self::enum_default(column, members.as_deref(), default, dialect)?
+                                    } else {
+                                        ToSQLString::to_string(&default, dialect)
+                                    }));
                                 }
                                 let stmt = SQL::alter_table(table_name).add(c).to_string(dialect);
-                                conn.execute(Query::from(stmt)).await.unwrap();
+                                Self::apply(conn, &mut plan, dry_run, statement_timeout, stmt).await?;
                             }
                             ColumnManipulation::AlterColumn(old_column, new_column) => {
+                                Self::operation(&mut plan, silent, m.to_operation(table_name));
                                 if dialect != SQLDialect::PostgreSQL {
                                     let alter = SQL::alter_table(table_name).modify(new_column.clone().clone()).to_string(dialect);
-                                    conn.execute(Query::from(alter)).await.unwrap();
+                                    Self::apply(conn, &mut plan, dry_run, statement_timeout, alter).await?;
                                 } else {
                                     let clauses = Self::psql_alter_clauses(table_name, *old_column, *new_column);
                                     for clause in clauses {
-                                        conn.execute(Query::from(clause)).await.unwrap();
+                                        Self::apply(conn, &mut plan, dry_run, statement_timeout, clause).await?;
                                     }
                                 }
                             }
                             ColumnManipulation::RemoveColumn(name) => {
+                                Self::operation(&mut plan, silent, m.to_operation(table_name));
                                 let stmt = SQL::alter_table(table_name).drop_column(name).to_string(dialect);
-                                conn.execute(Query::from(stmt)).await.unwrap();
+                                Self::apply(conn, &mut plan, dry_run, statement_timeout, stmt).await?;
                             }
                             ColumnManipulation::RenameColumn { old, new } => {
-                                let stmt = if dialect == SQLDialect::PostgreSQL {
-                                    format!("ALTER TABLE {} RENAME COLUMN '{}' TO '{}'", table_name, old, new)
-                                } else {
-                                    format!("ALTER TABLE {} RENAME COLUMN `{}` TO `{}`", table_name, old, new)
-                                };
-                                conn.execute(Query::from(stmt)).await.unwrap();
+                                Self::operation(&mut plan, silent, m.to_operation(table_name));
+                                let stmt = format!("ALTER TABLE {} RENAME COLUMN {} TO {}", dialect.quote(table_name), dialect.quote(old), dialect.quote(new));
+                                Self::apply(conn, &mut plan, dry_run, statement_timeout, stmt).await?;
                             }
                         }
                     }
                 }
             }
         }
+        if first_only && !plan.is_empty() {
+            return Ok(plan);
+        }
         // drop tables
         for table in db_tables {
-            Self::drop_table(dialect, conn, &table).await;
+            Self::operation(&mut plan, silent, SQLMigrationOperation::DropTable(table.clone()));
+            Self::apply(conn, &mut plan, dry_run, statement_timeout, Self::drop_table_stmt(dialect, &table)).await?;
         }
+        Self::apply_hooks(conn, &mut plan, dry_run, after).await?;
+        Ok(plan)
+    }
+
+    // user statements, unlike generated ones their failures are reported back
+    async fn apply_hooks(conn: &dyn Queryable, plan: &mut SQLMigrationPlan, dry_run: bool, stmts: &[String]) -> Result<()> {
+        for stmt in stmts {
+            if !dry_run {
+                if let Err(err) = conn.raw_cmd(stmt).await {
+                    return Err(Error::new(format!("migration hook `{}' failed: {}", stmt, err.to_string())));
+                }
+            }
+            plan.statement(stmt.clone());
+        }
+        Ok(())
+    }
+
+    // the same diff as migrate, stops at the first difference and describes it
+    // the first operation a dry run of the migration would perform
+    pub(crate) async fn pending_change(dialect: SQLDialect, conn: &dyn Queryable, namespace: Option<&Namespace>, models: Vec<&Model>, pconn: &dyn Transaction) -> Result<Option<String>> {
+        let plan = Self::migrate_models(dialect, conn, namespace, models, pconn, true, true, &[], &[], None, true).await?;
+        Ok(plan.operations().first().map(|operation| operation.to_string()))
+    }
+
+    // mysql's inline ENUM carries its variants, other databases store the variant as
+    // text and only the namespace knows the members. without a namespace the default
+    // isn't checked here, the schema parser already validated it
+    fn enum_members(namespace: Option<&Namespace>, column: &SQLColumn, r#type: &FieldType) -> Result<Option<Vec<String>>> {
+        if let DatabaseType::MySQLType(MySQLType::Enum(e)) = column.r#type() {
+            return Ok(Some(e.variants.clone()));
+        }
+        let FieldType::EnumVariant(reference) = r#type else {
+            return Err(Error::new(format!("column `{}' is not an enum column", column.name())));
+        };
+        let Some(namespace) = namespace else {
+            return Ok(None);
+        };
+        match namespace.enum_at_path(&reference.str_path()) {
+            Some(e) => Ok(Some(e.members().iter().map(|m| m.name().to_owned()).collect())),
+            None => Err(Error::new(format!("enum of column `{}' is not found", column.name()))),
+        }
+    }
+
+    fn enum_default(column: &SQLColumn, members: Option<&[String]>, default: &Value, dialect: SQLDialect) -> Result<String> {
+        let variant = match default.as_str() {
+            Some(variant) => variant,
+            None => return Err(Error::new(format!("default of enum column `{}' is not a variant", column.name()))),
+        };
+        if let Some(members) = members.filter(|members| !members.iter().any(|m| m == variant)) {
+            return Err(Error::new(format!("default `{}' of enum column `{}' is not one of {}", variant, column.name(), members.join(", "))));
+        }
+        Ok(variant.to_sql_input(dialect))
+    }
+
+    fn operation(plan: &mut SQLMigrationPlan, silent: bool, operation: SQLMigrationOperation) {
+        if !silent {
+            log::info!("migration: {}", operation);
+        }
+        plan.operation(operation);
+    }
+
+    // statements are recorded either way, a dry run only skips executing them
+    async fn apply(conn: &dyn Queryable, plan: &mut SQLMigrationPlan, dry_run: bool, statement_timeout: Option<Duration>, stmt: String) -> Result<()> {
+        let stmt = unescape_marks(&stmt);
+        if !dry_run {
+            let execution = conn.execute(Query::from(stmt.as_str()));
+            let result = match statement_timeout {
+                Some(duration) => match tokio::time::timeout(duration, execution).await {
+                    Ok(result) => result,
+                    Err(_) => return Err(Error::new(format!("migration statement timed out after {}ms: {}", duration.as_millis(), stmt))),
+                },
+                None => execution.await,
+            };
+            if let Err(err) = result {
+                return Err(Error::new(format!("migration statement `{}' failed: {}", stmt, err.to_string())));
+            }
+        }
+        plan.statement(stmt);
         Ok(())
     }
 
-    async fn drop_table(dialect: SQLDialect, conn: &dyn Queryable, table: &str) {
-        let escape = dialect.escape();
-        let sql = format!("DROP TABLE {escape}{table}{escape}");
-        conn.execute(Query::from(sql)).await.unwrap();
+    pub(crate) async fn db_pull(dialect: SQLDialect, conn: &dyn Queryable) -> Result<Vec<SQLTable>> {
+        let mut result = vec![];
+        for table_name in Self::get_db_user_tables(dialect, conn).await? {
+            let columns = Self::db_columns(conn, dialect, &table_name).await?.into_iter().sorted_by(|a, b| a.name().cmp(b.name())).collect();
+            let indices = Self::db_indices(dialect, conn, &table_name).await?.into_iter().sorted_by(|a, b| a.name().cmp(b.name())).collect();
+            result.push(SQLTable::new(table_name, columns, indices));
+        }
+        Ok(result)
+    }
+
+    // failed introspection queries name the statement they ran
+    async fn query(conn: &dyn Queryable, sql: &str) -> Result<ResultSet> {
+        conn.query(Query::from(sql)).await.map_err(|err| Error::new(format!("migration statement `{}' failed: {}", sql, err.to_string())))
+    }
+
+    fn drop_table_stmt(dialect: SQLDialect, table: &str) -> String {
+        format!("DROP TABLE {}", dialect.quote(table))
     }
 
-    async fn create_table(dialect: SQLDialect, conn: &dyn Queryable, model: &Model) {
+    fn create_table_stmts(dialect: SQLDialect, model: &Model, supports_nulls_not_distinct: bool) -> Vec<String> {
         // create table
-        let stmt = SQLCreateTableStatement::from(model).to_string(dialect);
-        conn.execute(Query::from(stmt)).await.unwrap();
+        let create_table = SQLCreateTableStatement::from(model);
+        let mut result = vec![create_table.to_string(dialect)];
+        result.extend(create_table.auto_increment_start_stmts(dialect));
         // create indices
         for (_name, index) in model.indexes() {
             // primary is created when creating table
             if index.r#type().is_primary() { continue }
-            let stmt = index.to_sql_create(dialect, model.table_name());
-            conn.execute(Query::from(stmt)).await.unwrap();
+            result.push(index.to_sql_create_with_options(dialect, model.table_name(), &index.sql_options(dialect, supports_nulls_not_distinct)));
         }
+        result
     }
 
     fn psql_alter_clauses(table: &str, old_column: &SQLColumn, new_column: &SQLColumn) -> Vec<String> {
         let mut result = vec![];
-        let name = new_column.name();
-        let escape = SQLDialect::PostgreSQL.escape();
+        let table = SQLDialect::PostgreSQL.quote(table);
+        let name = SQLDialect::PostgreSQL.quote(new_column.name());
         if old_column.r#type() != new_column.r#type() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} TYPE {}", new_column.r#type().to_sql_string()));
-        }
-        if old_column.default().is_none() && new_column.default().is_some() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} SET DEFAULT {}", new_column.default().unwrap()));
-        } else if old_column.default().is_some() && new_column.default().is_none() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} DROP DEFAULT"));
-        } else if old_column.default().is_some() && new_column.default().is_some() {
-            if old_column.default() == new_column.default() {
-                result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} SET DEFAULT {}", new_column.default().unwrap()));
-            }
+            result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} TYPE {}", new_column.r#type().to_sql_string()));
+        }
+        match (old_column.normalized_default(), new_column.normalized_default()) {
+            (Some(_), None) => result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} DROP DEFAULT")),
+            (old, Some(new)) if old.as_ref() != Some(&new) => result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} SET DEFAULT {}", new_column.default().unwrap())),
+            _ => (),
         }
         result
     }
@@ -311,19 +487,65 @@ impl SQLMigration {
         results.into_iter().collect()
     }
 
-    async fn db_indices(dialect: SQLDialect, conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
+    pub(crate) async fn db_indices(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         match dialect {
-            SQLDialect::PostgreSQL => Self::psql_db_indices(conn, model).await,
-            SQLDialect::MySQL => Self::mysql_db_indices(conn, model).await,
-            SQLDialect::SQLite => Self::sqlite_db_indices(conn, model).await,
+            SQLDialect::PostgreSQL => Self::psql_db_indices(conn, table_name).await,
+            SQLDialect::MySQL => Self::mysql_db_indices(conn, table_name).await,
+            SQLDialect::SQLite => Self::sqlite_db_indices(conn, table_name).await,
             _ => unreachable!(),
         }
     }
 
-    async fn mysql_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = model.table_name();
+    async fn psql_server_version_num(conn: &dyn Queryable) -> Result<i64> {
+        let result_set = Self::query(conn, "SHOW server_version_num").await?;
+        let row = result_set.into_single().unwrap();
+        Ok(row.get("server_version_num").unwrap().to_string().unwrap().parse().unwrap())
+    }
+
+    async fn db_index_options(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str, supports_nulls_not_distinct: bool) -> Result<HashMap<String, IndexOptions>> {
+        match dialect {
+            SQLDialect::PostgreSQL => Self::psql_db_index_options(conn, table_name, supports_nulls_not_distinct).await,
+            SQLDialect::MySQL => Self::mysql_db_index_options(conn, table_name).await,
+            _ => Ok(hashmap!{}),
+        }
+    }
+
+    async fn psql_db_index_options(conn: &dyn Queryable, table_name: &str, supports_nulls_not_distinct: bool) -> Result<HashMap<String, IndexOptions>> {
+        let nulls_not_distinct = if supports_nulls_not_distinct { "i.indnullsnotdistinct" } else { "false" };
+        let sql = format!(r#"SELECT irel.relname AS index_name,
+       am.amname AS method,
+       {nulls_not_distinct} AS nulls_not_distinct
+FROM   pg_index AS i
+JOIN   pg_class AS trel ON trel.oid = i.indrelid
+JOIN   pg_namespace AS tnsp ON trel.relnamespace = tnsp.oid
+JOIN   pg_class AS irel ON irel.oid = i.indexrelid
+JOIN   pg_am AS am ON am.oid = irel.relam
+WHERE  tnsp.nspname = current_schema()
+AND    trel.relname = '{table_name}'"#);
+        let result_set = Self::query(conn, &sql).await?;
+        Ok(result_set.into_iter().map(|row| {
+            let index_name = row.get("index_name").unwrap().to_string().unwrap();
+            let method = row.get("method").unwrap().to_string().unwrap();
+            (index_name, IndexOptions {
+                nulls_not_distinct: row.get("nulls_not_distinct").unwrap().as_bool().unwrap(),
+                method: IndexOptions::normalized_method(&method),
+            })
+        }).collect())
+    }
+
+    async fn mysql_db_index_options(conn: &dyn Queryable, table_name: &str) -> Result<HashMap<String, IndexOptions>> {
+        let sql = format!("SHOW INDEX FROM `{}`", table_name);
+        let result_set = Self::query(conn, &sql).await?;
+        Ok(result_set.into_iter().map(|row| {
+            let index_name = row.get("Key_name").unwrap().to_string().unwrap();
+            let method = row.get("Index_type").unwrap().to_string().unwrap();
+            (index_name, IndexOptions { nulls_not_distinct: false, method: IndexOptions::normalized_method(&method) })
+        }).collect())
+    }
+
+    async fn mysql_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!("SHOW INDEX FROM `{}`", table_name);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(conn, &sql).await?;
         let mut indices = vec![];
         for row in result_set {
             let index_name = row.get("Key_name").unwrap().to_string().unwrap();
@@ -349,11 +571,10 @@ impl SQLMigration {
                 ))
             }
         }
-        indices.into_iter().collect()
+        Ok(indices.into_iter().collect())
     }
 
-    async fn psql_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = model.table_name();
+    async fn psql_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!(r#"SELECT     irel.relname                           AS index_name,
            a.attname                              AS column_name,
            i.indisunique                          AS is_unique,
@@ -377,7 +598,7 @@ ON         c.ordinality = o.ordinality
 join       pg_attribute AS a
 ON         trel.oid = a.attrelid
 AND        a.attnum = c.colnum
-WHERE      tnsp.nspname='public'
+WHERE      tnsp.nspname=current_schema()
 AND        trel.relname='{table_name}'
 GROUP BY   tnsp.nspname,
            trel.relname,
@@ -388,7 +609,7 @@ GROUP BY   tnsp.nspname,
            array_position(i.indkey, a.attnum),
            o.OPTION ORDER BY column_position
 "#);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(conn, &sql).await?;
         let mut indices = vec![];
         for row in result_set {
             let index_name = row.get("index_name").unwrap().to_string().unwrap();
@@ -415,11 +636,10 @@ GROUP BY   tnsp.nspname,
                 ))
             }
         }
-        indices.into_iter().collect()
+        Ok(indices.into_iter().collect())
     }
 
-    async fn sqlite_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = model.table_name();
+    async fn sqlite_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!(r#"SELECT
     il.name as index_name,
     ii.name as column_name,
@@ -445,7 +665,7 @@ GROUP BY
     il.seq,
     ii.`desc`
 ORDER BY 1,6"#, table_name);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(conn, &sql).await?;
         let mut indices = vec![];
         for row in result_set {
             let index_name = row.get("index_name").unwrap().to_string().unwrap();
@@ -487,6 +707,79 @@ ORDER BY 1,6"#, table_name);
         //     ]);
         //     results.push(index);
         // }
-        results.into_iter().collect()
+        Ok(results.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teo_runtime::database::mysql::r#type::MySQLEnum;
+    use teo_runtime::database::postgres::r#type::PostgreSQLType;
+
+    fn column() -> SQLColumn {
+        SQLColumn::builder("status", DatabaseType::PostgreSQLType(PostgreSQLType::Text)).build()
+    }
+
+    #[test]
+    fn psql_alter_follows_the_normalized_defaults() {
+        let with_default = |default: &str| SQLColumn::builder("name", DatabaseType::PostgreSQLType(PostgreSQLType::Text)).default(default).build();
+        let without_default = SQLColumn::builder("name", DatabaseType::PostgreSQLType(PostgreSQLType::Text)).build();
+        assert!(SQLMigration::psql_alter_clauses("users", &with_default("'x'::text"), &with_default("'x'")).is_empty());
+        assert_eq!(SQLMigration::psql_alter_clauses("users", &with_default("'x'::text"), &without_default), vec!["ALTER TABLE \"users\" ALTER COLUMN \"name\" DROP DEFAULT"]);
+        assert_eq!(SQLMigration::psql_alter_clauses("users", &without_default, &with_default("'y'")), vec!["ALTER TABLE \"users\" ALTER COLUMN \"name\" SET DEFAULT 'y'"]);
+        assert_eq!(SQLMigration::psql_alter_clauses("users", &with_default("'x'::text"), &with_default("'y'")), vec!["ALTER TABLE \"users\" ALTER COLUMN \"name\" SET DEFAULT 'y'"]);
+    }
+
+    #[test]
+    fn server_timeout_per_dialect() {
+        let duration = Some(Duration::from_millis(1500));
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::PostgreSQL, duration), "SET statement_timeout = 1500");
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::PostgreSQL, None), "RESET statement_timeout");
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::MySQL, duration), "SET SESSION lock_wait_timeout = 1");
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::MySQL, Some(Duration::from_millis(100))), "SET SESSION lock_wait_timeout = 1");
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::SQLite, duration), "PRAGMA busy_timeout = 1500");
+        assert_eq!(SQLMigration::server_timeout_stmt(SQLDialect::MSSQL, None), "SET LOCK_TIMEOUT -1");
+    }
+
+    #[test]
+    fn enum_default_accepts_a_member() {
+        let members = vec!["draft".to_owned(), "published".to_owned()];
+        let default = SQLMigration::enum_default(&column(), Some(&members), &Value::String("draft".to_owned()), SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(default, "'draft'");
+    }
+
+    #[test]
+    fn enum_default_rejects_other_variants_on_every_dialect() {
+        let members = vec!["draft".to_owned(), "published".to_owned()];
+        for dialect in [SQLDialect::PostgreSQL, SQLDialect::SQLite, SQLDialect::MySQL] {
+            assert!(SQLMigration::enum_default(&column(), Some(&members), &Value::String("archived".to_owned()), dialect).is_err());
+        }
+        assert!(SQLMigration::enum_default(&column(), Some(&members), &Value::Int(1), SQLDialect::PostgreSQL).is_err());
+    }
+
+    #[test]
+    fn mysql_enum_members_come_from_the_column() {
+        let column = SQLColumn::builder("status", DatabaseType::MySQLType(MySQLType::Enum(MySQLEnum { variants: vec!["draft".to_owned()] }))).build();
+        let members = SQLMigration::enum_members(None, &column, &FieldType::String).unwrap();
+        assert_eq!(members, Some(vec!["draft".to_owned()]));
+    }
+
+    #[test]
+    fn text_enum_defaults_without_namespace_are_quoted() {
+        let default = SQLMigration::enum_default(&column(), None, &Value::String("draft".to_owned()), SQLDialect::SQLite).unwrap();
+        assert_eq!(default, "'draft'");
+        assert!(SQLMigration::enum_members(None, &column(), &FieldType::String).is_err());
+    }
+
+    #[test]
+    fn rename_takes_the_first_existing_old_name() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+        let old_names = names(&["people", "persons", "humans"]);
+        assert_eq!(SQLMigration::rename_candidate(&names(&["persons", "humans"]), &old_names, &names(&["users"])), Some("persons".to_owned()));
+        assert_eq!(SQLMigration::rename_candidate(&names(&["Humans", "persons"]), &old_names, &names(&["users"])), Some("persons".to_owned()));
+        assert_eq!(SQLMigration::rename_candidate(&names(&["Persons"]), &old_names, &names(&["users"])), Some("Persons".to_owned()));
+        // still used by another model
+        assert_eq!(SQLMigration::rename_candidate(&names(&["persons"]), &old_names, &names(&["users", "persons"])), None);
     }
 }
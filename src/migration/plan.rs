@@ -0,0 +1,60 @@
+use std::fmt::{Display, Formatter};
+use crate::schema::column::SQLColumn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SQLMigrationOperation {
+    CreateTable(String),
+    DropTable(String),
+    RenameTable { old: String, new: String },
+    AddColumn { table: String, column: SQLColumn },
+    RemoveColumn { table: String, column: String },
+    RenameColumn { table: String, old: String, new: String },
+    AlterColumn { table: String, old: SQLColumn, new: SQLColumn },
+    CreateIndex { table: String, index: String },
+    DropIndex { table: String, index: String },
+}
+
+impl Display for SQLMigrationOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SQLMigrationOperation::CreateTable(table) => write!(f, "create table `{}'", table),
+            SQLMigrationOperation::DropTable(table) => write!(f, "drop table `{}'", table),
+            SQLMigrationOperation::RenameTable { old, new } => write!(f, "rename table `{}' to `{}'", old, new),
+            SQLMigrationOperation::AddColumn { table, column } => write!(f, "add column `{}' to `{}'", column.name(), table),
+            SQLMigrationOperation::RemoveColumn { table, column } => write!(f, "remove column `{}' from `{}'", column, table),
+            SQLMigrationOperation::RenameColumn { table, old, new } => write!(f, "rename column `{}' to `{}' on `{}'", old, new, table),
+            SQLMigrationOperation::AlterColumn { table, new, .. } => write!(f, "alter column `{}' on `{}'", new.name(), table),
+            SQLMigrationOperation::CreateIndex { table, index } => write!(f, "create index `{}' on `{}'", index, table),
+            SQLMigrationOperation::DropIndex { table, index } => write!(f, "drop index `{}' on `{}'", index, table),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SQLMigrationPlan {
+    operations: Vec<SQLMigrationOperation>,
+    statements: Vec<String>,
+}
+
+impl SQLMigrationPlan {
+
+    pub fn operations(&self) -> &Vec<SQLMigrationOperation> {
+        &self.operations
+    }
+
+    pub fn statements(&self) -> &Vec<String> {
+        &self.statements
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub(crate) fn operation(&mut self, operation: SQLMigrationOperation) {
+        self.operations.push(operation);
+    }
+
+    pub(crate) fn statement(&mut self, statement: String) {
+        self.statements.push(statement);
+    }
+}
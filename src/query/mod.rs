@@ -1,13 +1,15 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
-use indexmap::indexmap;
+use indexmap::{indexmap, IndexMap};
 use itertools::Itertools;
 use maplit::{btreemap};
 use once_cell::sync::Lazy;
+use chrono::{Duration, Utc};
 use teo_parser::r#type::Type;
 use teo_result::{Error, Result};
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::{IfIMode, SQLEscape, ToLike, ToSQLString, ToWrapped, ValueToSQLString, WrapInArray, ToSQLInputWithoutQuotes};
+use crate::schema::value::encode::{check_finite, IfIMode, SQLEscape, like_escape, ToSQLString, ToWrapped, ValueToSQLString, WrapInArray, ToSQLInputDialect};
+use crate::stmts::select::SQLLock;
 use crate::stmts::select::r#where::{ToWrappedSQLString, WhereClause};
 use crate::stmts::select::r#where::WhereClause::{And, Not};
 use crate::stmts::SQL;
@@ -15,7 +17,12 @@ use teo_runtime::model::{Model, object::Object, object::input::Input};
 use teo_runtime::model::field::column_named::ColumnNamed;
 use teo_runtime::model::field::typed::Typed;
 use teo_runtime::namespace::Namespace;
+use teo_runtime::traits::named::Named;
 use teo_runtime::value::Value;
+use quaint_forked::Value as QuaintValue;
+use crate::query::params::{bind, bind_like, bind_params, bind_text, MARK};
+
+pub(crate) mod params;
 
 pub(crate) struct Query { }
 
@@ -25,11 +32,11 @@ impl Query {
         format!("{} {} {}", lhs.as_ref(), op, rhs)
     }
 
-    pub(crate) fn where_from_identifier(object: &Object, dialect: SQLDialect) -> String {
+    pub(crate) fn where_from_identifier(object: &Object, dialect: SQLDialect) -> Result<String> {
         Self::where_from_value(object.model(), &object.identifier(), dialect)
     }
 
-    pub(crate) fn where_from_previous_identifier(object: &Object, dialect: SQLDialect) -> String {
+    pub(crate) fn where_from_previous_identifier(object: &Object, dialect: SQLDialect) -> Result<String> {
         Self::where_from_value(object.model(), &object.previous_identifier(), dialect)
     }
 
@@ -40,13 +47,206 @@ impl Query {
         value: &Value,
         op: &str,
         dialect: SQLDialect,
-    ) -> String {
+    ) -> Result<String> {
         let arr_val = value.as_array().unwrap();
         let mut arr: Vec<String> = Vec::new();
         for val in arr_val {
-            arr.push(val.to_sql_string(r#type, optional, dialect));
+            arr.push(bind(val, r#type, optional, dialect)?);
+        }
+        Ok(Query::where_item(column_name, op, &arr.join(", ").to_wrapped()))
+    }
+
+    fn without_nulls(value: &Value) -> (Value, bool) {
+        let array = value.as_array().unwrap();
+        let has_null = array.iter().any(|v| v.is_null());
+        (Value::Array(array.iter().filter(|v| !v.is_null()).cloned().collect()), has_null)
+    }
+
+    fn where_truncated_datetime(column_name: &str, hour: bool, value: &Value, dialect: SQLDialect) -> Result<String> {
+        let bucket = match (value, hour) {
+            (Value::DateTime(datetime), true) => datetime.format("%Y-%m-%d %H:00:00").to_string(),
+            (Value::DateTime(datetime), false) => datetime.format("%Y-%m-%d").to_string(),
+            (Value::Date(date), false) => date.format("%Y-%m-%d").to_string(),
+            _ => return Err(Error::new(format!("{} takes a {}", if hour { "onHour" } else { "onDate" }, if hour { "datetime" } else { "date or datetime" }))),
+        };
+        let lhs = match (dialect, hour) {
+            (SQLDialect::PostgreSQL, false) => format!("date_trunc('day', {column_name})"),
+            (SQLDialect::PostgreSQL, true) => format!("date_trunc('hour', {column_name})"),
+            (SQLDialect::MySQL, true) => format!("DATE_FORMAT({column_name}, '%Y-%m-%d %H:00:00')"),
+            (SQLDialect::SQLite, true) => format!("strftime('%Y-%m-%d %H:00:00', {column_name})"),
+            _ => format!("DATE({column_name})"),
+        };
+        let rhs = if dialect.is_postgres() {
+            bucket.to_sql_input(dialect) + if hour { "::timestamp" } else { "::date" }
+        } else {
+            bucket.to_sql_input(dialect)
+        };
+        Ok(Self::where_item(lhs, "=", &rhs))
+    }
+
+    // range comparisons on text follow the given collation, e.g. "C" for byte order
+    fn collated<'a>(column_name: &'a str, map: &IndexMap<String, Value>, dialect: SQLDialect) -> Result<Cow<'a, str>> {
+        Ok(match map.get("collation").map(|c| c.as_str()).flatten() {
+            Some(collation) => {
+                if collation.is_empty() || !collation.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+                    return Err(Error::new(format!("invalid collation `{}'", collation)));
+                }
+                if dialect.is_postgres() {
+                    Cow::Owned(format!("{} COLLATE \"{}\"", column_name, collation))
+                } else {
+                    Cow::Owned(format!("{} COLLATE {}", column_name, collation))
+                }
+            }
+            None => Cow::Borrowed(column_name),
+        })
+    }
+
+    // `{ "_now_minus": "7d" }` compares against the database clock, sqlite keeps
+    // datetimes as text so the cutoff is computed here instead
+    fn comparand(value: &Value, r#type: &Type, dialect: SQLDialect) -> Result<String> {
+        let relative = match value.as_dictionary() {
+            Some(map) => match (map.get("_now_minus"), map.get("_now_plus")) {
+                (Some(spec), None) => Some(-relative_seconds(spec)?),
+                (None, Some(spec)) => Some(relative_seconds(spec)?),
+                _ => return Err(Error::new("relative time takes one of _now_minus and _now_plus")),
+            },
+            None => None,
+        };
+        let Some(seconds) = relative else {
+            return bind(value, r#type, false, dialect);
+        };
+        let sign = if seconds < 0 { "-" } else { "+" };
+        match dialect {
+            SQLDialect::PostgreSQL => Ok(format!("(NOW() {sign} INTERVAL '{} seconds')", seconds.abs())),
+            SQLDialect::MySQL => Ok(format!("(NOW(3) {sign} INTERVAL {} SECOND)", seconds.abs())),
+            _ => {
+                let at = Utc::now() + Duration::seconds(seconds);
+                match r#type.unwrap_optional() {
+                    Type::Date => bind(&Value::Date(at.date_naive()), r#type, false, dialect),
+                    _ => bind(&Value::DateTime(at), r#type, false, dialect),
+                }
+            }
+        }
+    }
+
+    fn where_i_like(column_name: &str, pattern: &str, dialect: SQLDialect) -> String {
+        if dialect.is_postgres() {
+            Self::where_item(column_name, "ILIKE", &format!("{} ESCAPE {}", pattern, like_escape(dialect)))
+        } else {
+            Self::where_like(column_name, pattern, true, dialect)
+        }
+    }
+
+    // patterns come from `bind_like`, which escapes wildcards with a backslash,
+    // postgres matches case insensitively with ILIKE so indexes still apply
+    fn where_like(column_name: &str, pattern: &str, i_mode: bool, dialect: SQLDialect) -> String {
+        if i_mode && dialect.is_postgres() {
+            return Self::where_i_like(column_name, pattern, dialect);
+        }
+        Self::where_item(column_name.to_i_mode(i_mode), "LIKE", &format!("{} ESCAPE {}", pattern.to_i_mode(i_mode), like_escape(dialect)))
+    }
+
+    // the extracted scalar is cast after the compared value, text when it's a string
+    fn where_json_path(column_name: &str, path: &Value, map: &IndexMap<String, Value>, dialect: SQLDialect) -> Result<String> {
+        let Some(path) = path.as_array().filter(|p| !p.is_empty()) else {
+            return Err(Error::new("json path should be a non empty array of keys and indices"));
+        };
+        if path.iter().any(|segment| segment.as_str().is_none() && segment.as_int64().is_none()) {
+            return Err(Error::new("json path segments are keys or indices"));
+        }
+        let mut result: Vec<String> = vec![];
+        for (key, value) in map {
+            let op = match key.as_str() {
+                "path" => continue,
+                "equals" => "=",
+                "not" => "<>",
+                "gt" => ">",
+                "gte" => ">=",
+                "lt" => "<",
+                "lte" => "<=",
+                "in" => "IN",
+                "notIn" => "NOT IN",
+                _ => return Err(Error::new(format!("{} is not supported on json paths", key))),
+            };
+            if (op == "IN" || op == "NOT IN") && value.as_array().is_none() {
+                return Err(Error::new(format!("{} on a json path takes an array", key)));
+            }
+            let sample = if let Some(values) = value.as_array() {
+                match values.first() {
+                    Some(sample) => sample,
+                    // nothing is in an empty list
+                    None => {
+                        result.push(if op == "IN" { "1 = 0" } else { "1 = 1" }.to_owned());
+                        continue
+                    }
+                }
+            } else {
+                value
+            };
+            let r#type = Self::json_path_type(sample)?;
+            if let Some(values) = value.as_array() {
+                for v in values {
+                    if Self::json_path_type(v)? != r#type {
+                        return Err(Error::new(format!("values of {} on a json path should share one type", key)));
+                    }
+                }
+            }
+            let extracted = Self::json_path_extract(column_name, path, &r#type, dialect);
+            let rhs = if let Some(values) = value.as_array() {
+                values.iter().map(|v| bind(v, &r#type, false, dialect)).collect::<Result<Vec<String>>>()?.join(",").to_wrapped()
+            } else {
+                bind(value, &r#type, false, dialect)?
+            };
+            result.push(Self::where_item(extracted, op, &rhs));
+        }
+        Ok(And(result).to_wrapped_string(dialect))
+    }
+
+    fn json_path_type(value: &Value) -> Result<Type> {
+        Ok(match value {
+            Value::Int(_) | Value::Int64(_) => Type::Int64,
+            Value::Float32(_) | Value::Float(_) => Type::Float,
+            Value::Decimal(_) => Type::Decimal,
+            Value::Bool(_) => Type::Bool,
+            Value::String(_) => Type::String,
+            _ => return Err(Error::new("json paths only compare with numbers, strings and bools")),
+        })
+    }
+
+    fn json_path_extract(column_name: &str, path: &Vec<Value>, r#type: &Type, dialect: SQLDialect) -> String {
+        if dialect.is_postgres() {
+            let mut segments: Vec<String> = path.iter().map(|segment| match segment.as_str() {
+                Some(key) => bind_text(key, dialect),
+                None => segment.as_int64().unwrap().to_string(),
+            }).collect();
+            let last = segments.pop().unwrap();
+            let mut extracted = column_name.to_owned();
+            for segment in segments {
+                extracted = format!("{} -> {}", extracted, segment);
+            }
+            let extracted = format!("({} ->> {})", extracted, last);
+            match r#type {
+                Type::Int64 => format!("{}::bigint", extracted),
+                Type::Float | Type::Decimal => format!("{}::numeric", extracted),
+                Type::Bool => format!("{}::boolean", extracted),
+                _ => extracted,
+            }
+        } else {
+            let json_path = path.iter().fold("$".to_owned(), |json_path, segment| match segment.as_str() {
+                Some(key) => format!("{}.\"{}\"", json_path, key.replace('"', "\\\"")),
+                None => format!("{}[{}]", json_path, segment.as_int64().unwrap()),
+            });
+            let extracted = format!("JSON_EXTRACT({}, {})", column_name, bind_text(&json_path, dialect));
+            if !dialect.is_mysql() {
+                // sqlite returns the native sql value already
+                return extracted;
+            }
+            match r#type {
+                Type::Int64 | Type::Bool => format!("CAST({} AS SIGNED)", extracted),
+                Type::Float | Type::Decimal => format!("CAST({} AS DECIMAL(65,30))", extracted),
+                _ => format!("JSON_UNQUOTE({})", extracted),
+            }
         }
-        Query::where_item(column_name, op, &arr.join(", ").to_wrapped())
     }
 
     fn where_entry_item(
@@ -55,8 +255,12 @@ impl Query {
         optional: bool,
         value: &Value,
         dialect: SQLDialect,
-    ) -> String {
+    ) -> Result<String> {
+        check_finite(value, dialect)?;
         let column_name = escape_wisdom(column_name, dialect);
+        if let Some(path) = value.get("path") {
+            return Self::where_json_path(&column_name, path, value.as_dictionary().unwrap(), dialect);
+        }
         if let Some(map) = value.as_dictionary() {
             let mut result: Vec<String> = vec![];
             for (key, value) in map {
@@ -64,56 +268,140 @@ impl Query {
                     "equals" => if value.is_null() {
                         result.push(Self::where_item(&column_name, "IS", "NULL"));
                     } else {
-                        result.push(Self::where_item(&column_name, "=", &value.to_sql_string(r#type, optional, dialect)));
+                        result.push(Self::where_item(&column_name, "=", &bind(value, r#type, optional, dialect)?));
                     }
                     "not" => if value.is_null() {
                         result.push(Self::where_item(&column_name, "IS NOT", "NULL"));
+                    } else if optional {
+                        // null rows are not equal to any value, keep them in the result
+                        let rhs = bind(value, r#type, optional, dialect)?;
+                        if dialect.is_mysql() {
+                            result.push(Not(Self::where_item(&column_name, "<=>", &rhs).to_wrapped()).to_string(dialect));
+                        } else {
+                            result.push(Self::where_item(&column_name, "IS DISTINCT FROM", &rhs));
+                        }
                     } else {
-                        result.push(Self::where_item(&column_name, "<>", &value.to_sql_string(r#type, optional, dialect)));
+                        result.push(Self::where_item(&column_name, "<>", &bind(value, r#type, optional, dialect)?));
                     }
                     "gt" => {
-                        result.push(Self::where_item(&column_name, ">", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(Self::collated(&column_name, map, dialect)?, ">", &Self::comparand(value, r#type, dialect)?));
                     }
                     "gte" => {
-                        result.push(Self::where_item(&column_name, ">=", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(Self::collated(&column_name, map, dialect)?, ">=", &Self::comparand(value, r#type, dialect)?));
                     }
                     "lt" => {
-                        result.push(Self::where_item(&column_name, "<", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(Self::collated(&column_name, map, dialect)?, "<", &Self::comparand(value, r#type, dialect)?));
                     }
                     "lte" => {
-                        result.push(Self::where_item(&column_name, "<=", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(Self::collated(&column_name, map, dialect)?, "<=", &Self::comparand(value, r#type, dialect)?));
                     }
                     "in" => {
-                        if !value.as_array().unwrap().is_empty() {
-                            result.push(Self::where_entry_array(&column_name, r#type, optional, value, "IN", dialect));
+                        let (values, has_null) = Self::without_nulls(value);
+                        let is_null = Self::where_item(&column_name, "IS", "NULL");
+                        if !values.as_array().unwrap().is_empty() {
+                            let item = Self::where_entry_array(&column_name, r#type, optional, &values, "IN", dialect)?;
+                            if has_null {
+                                result.push(WhereClause::Or(vec![item, is_null]).to_wrapped_string(dialect));
+                            } else {
+                                result.push(item);
+                            }
+                        } else if has_null {
+                            result.push(is_null);
                         } else {
                             result.push("FALSE".to_owned())
                         }
                     }
                     "notIn" => {
-                        if !value.as_array().unwrap().is_empty() {
-                            result.push(Self::where_entry_array(&column_name, r#type, optional, value, "NOT IN", dialect));
+                        let (values, has_null) = Self::without_nulls(value);
+                        let is_not_null = Self::where_item(&column_name, "IS NOT", "NULL");
+                        if !values.as_array().unwrap().is_empty() {
+                            let item = Self::where_entry_array(&column_name, r#type, optional, &values, "NOT IN", dialect)?;
+                            if has_null {
+                                result.push(And(vec![item, is_not_null]).to_wrapped_string(dialect));
+                            } else {
+                                result.push(item);
+                            }
+                        } else if has_null {
+                            result.push(is_not_null);
                         } else {
                             result.push("TRUE".to_owned())
                         }
                     }
+                    "contains" if r#type.unwrap_optional().is_range() => {
+                        if !dialect.is_postgres() {
+                            return Err(Error::new("contains on ranges is only supported on PostgreSQL"));
+                        }
+                        // the value is either an element or a range
+                        result.push(Self::where_item(&column_name, "@>", &ToSQLString::to_string(&value, dialect)));
+                    }
+                    "overlaps" => {
+                        if !dialect.is_postgres() {
+                            return Err(Error::new("overlaps is only supported on PostgreSQL ranges"));
+                        }
+                        result.push(Self::where_item(&column_name, "&&", &ToSQLString::to_string(&value, dialect)));
+                    }
+                    "between" => {
+                        // plain bounds keep scalar columns index friendly on every dialect
+                        let Some(range) = value.as_range() else {
+                            return Err(Error::new("between takes a range"));
+                        };
+                        result.push(And(vec![
+                            Self::where_item(&column_name, ">=", &bind(&range.start, r#type, false, dialect)?),
+                            Self::where_item(&column_name, if range.closed { "<=" } else { "<" }, &bind(&range.end, r#type, false, dialect)?),
+                        ]).to_string(dialect));
+                    }
                     "contains" => {
+                        let Some(term) = value.as_str() else {
+                            return Err(Error::new(format!("contains takes a string, not {:?}", value)));
+                        };
                         let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.as_str().unwrap().to_sql_input_without_quotes(dialect).to_like(true, true).to_i_mode(i_mode)));
+                        result.push(Self::where_like(&column_name, &bind_like(term, true, true, dialect), i_mode, dialect));
                     }
                     "startsWith" => {
                         let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.as_str().unwrap().to_sql_input_without_quotes(dialect).to_like(false, true).to_i_mode(i_mode)));
+                        let prefix = value.as_str().unwrap();
+                        let prefix_range = map.get("prefixRange").map(|v| v.as_bool().unwrap()).unwrap_or(false);
+                        if let (false, true, Some(upper_bound)) = (i_mode, prefix_range, prefix_upper_bound(prefix)) {
+                            // index friendly form, col >= 'abc' AND col < 'abd'
+                            result.push(And(vec![
+                                Self::where_item(&column_name, ">=", &bind_text(prefix, dialect)),
+                                Self::where_item(&column_name, "<", &bind_text(&upper_bound, dialect)),
+                            ]).to_string(dialect));
+                        } else {
+                            result.push(Self::where_like(&column_name, &bind_like(prefix, false, true, dialect), i_mode, dialect));
+                        }
                     }
                     "endsWith" => {
                         let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.as_str().unwrap().to_sql_input_without_quotes(dialect).to_like(true, false).to_i_mode(i_mode)));
+                        result.push(Self::where_like(&column_name, &bind_like(value.as_str().unwrap(), true, false, dialect), i_mode, dialect));
+                    }
+                    "iContains" | "iStartsWith" | "iEndsWith" => {
+                        let (left, right) = match key.as_str() {
+                            "iContains" => (true, true),
+                            "iStartsWith" => (false, true),
+                            _ => (true, false),
+                        };
+                        let pattern = bind_like(value.as_str().unwrap(), left, right, dialect);
+                        result.push(Self::where_i_like(&column_name, &pattern, dialect));
                     }
                     "matches" => {
                         let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "REGEXP", &value.to_sql_string(r#type, false, dialect).to_i_mode(i_mode)));
+                        let pattern = bind(value, r#type, false, dialect)?;
+                        match dialect {
+                            SQLDialect::PostgreSQL => result.push(Self::where_item(&column_name, if i_mode { "~*" } else { "~" }, &pattern)),
+                            // sqlite only parses REGEXP, the connection has to register a regexp() function
+                            _ => result.push(Self::where_item(&column_name.to_i_mode(i_mode), "REGEXP", &pattern.to_i_mode(i_mode))),
+                        }
+                    }
+                    "onDate" | "onHour" => {
+                        if !r#type.unwrap_optional().is_date() && !r#type.unwrap_optional().is_datetime() {
+                            return Err(Error::new(format!("{} is only valid on date and datetime fields", key)));
+                        }
+                        result.push(Self::where_truncated_datetime(&column_name, key == "onHour", value, dialect)?);
                     }
                     "mode" => { }
+                    "prefixRange" => { }
+                    "collation" => { }
                     "has" => {
                         let element_type = r#type.as_array().unwrap();
                         result.push(Self::where_item(&column_name, "@>", &value.to_sql_string_array_arg(element_type.unwrap_optional(), element_type.is_optional(), dialect).wrap_in_array()));
@@ -128,23 +416,23 @@ impl Query {
                         result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", "0"));
                     }
                     "length" => {
-                        result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", &value.to_sql_string(&Type::Int64, false, dialect)));
+                        result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", &bind(value, &Type::Int64, false, dialect)?));
                     }
                     "_count" => {
-                        result.push(Self::where_entry_item(&format!("COUNT({})", &column_name), &Type::Int64, false, value, dialect));
+                        result.push(Self::where_entry_item(&format!("COUNT({})", &column_name), &Type::Int64, false, value, dialect)?);
                     }
                     "_avg" | "_sum" => {
-                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), &Type::Float, true, value, dialect));
+                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), &Type::Float, true, value, dialect)?);
                     }
                     "_min" | "_max" => {
-                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), r#type, optional, value, dialect));
+                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), r#type, optional, value, dialect)?);
                     }
-                    _ => panic!("Unhandled key."),
+                    _ => return Err(Error::new(format!("unknown filter `{}' on {}", key, column_name))),
                 }
             }
-            And(result).to_wrapped_string(dialect)
+            Ok(And(result).to_wrapped_string(dialect))
         } else {
-            Query::where_item(column_name, "=", &value.to_sql_string(r#type, optional, dialect))
+            Ok(Query::where_item(column_name, "=", &bind(value, r#type, optional, dialect)?))
         }
     }
 
@@ -154,35 +442,46 @@ impl Query {
         optional: bool,
         value: &Value,
         dialect: SQLDialect,
-    ) -> String {
+    ) -> Result<String> {
         Self::where_entry_item(column_name, field_type, optional, value, dialect)
     }
 
-    pub(crate) fn where_from_value(model: &Model, identifier: &Value, dialect: SQLDialect) -> String {
+    pub(crate) fn where_from_value(model: &Model, identifier: &Value, dialect: SQLDialect) -> Result<String> {
         let mut retval: Vec<String> = vec![];
         for (key, value) in identifier.as_dictionary().unwrap() {
-            let field = model.field(key).unwrap();
-            let column_name = field.column_name();
-            let escape = dialect.escape();
-            retval.push(format!("{}{}{} = {}", escape, column_name, escape, ToSQLString::to_string(&value, dialect)));
+            if let Some(field) = model.field(key) {
+                let column_name = field.column_name();
+                retval.push(format!("{} = {}", dialect.quote(column_name), bind(value, field.r#type(), true, dialect)?));
+            } else if let Some(relation) = model.relation(key) {
+                // relation inside a compound unique, resolve into its foreign key columns
+                for (f, r) in relation.iter() {
+                    let fk_field = model.field(f).unwrap();
+                    let Some(fk_value) = value.get(r).or_else(|| value.get(f)) else {
+                        return Err(Error::new(format!("value of `{}' is missing `{}'", key, r)));
+                    };
+                    retval.push(format!("{} = {}", dialect.quote(fk_field.column_name()), bind(fk_value, fk_field.r#type(), true, dialect)?));
+                }
+            } else {
+                return Err(Error::new(format!("`{}' is not a field or relation of model `{}'", key, model.name())));
+            }
         }
-        And(retval).to_string(dialect)
+        Ok(And(retval).to_string(dialect))
     }
 
-    pub(crate) fn r#where(namespace: &Namespace, model: &Model, r#where: &Value, dialect: SQLDialect, table_alias: Option<&str>) -> String {
+    pub(crate) fn r#where(namespace: &Namespace, model: &Model, r#where: &Value, dialect: SQLDialect, table_alias: Option<&str>) -> Result<String> {
         let r#where = r#where.as_dictionary().unwrap();
         let mut retval: Vec<String> = vec![];
         for (key, value) in r#where.iter() {
             if key == "AND" {
-                let inner = WhereClause::And(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect()).to_string(dialect);
+                let inner = WhereClause::And(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect::<Result<Vec<String>>>()?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else if key == "OR" {
-                let inner = WhereClause::Or(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect()).to_string(dialect);
+                let inner = WhereClause::Or(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect::<Result<Vec<String>>>()?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else if key == "NOT" {
-                let inner = WhereClause::Not(Self::r#where(namespace, model, value, dialect, table_alias)).to_string(dialect);
+                let inner = WhereClause::Not(Self::r#where(namespace, model, value, dialect, table_alias)?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else {
@@ -195,116 +494,181 @@ impl Query {
                     } else {
                         Cow::Borrowed(column_name)
                     };
-                    let where_entry = Query::where_entry(&entry_column_name, field.r#type(), optional, value, dialect);
+                    let where_entry = Query::where_entry(&entry_column_name, field.r#type(), optional, value, dialect)?;
                     retval.push(where_entry);
                 } else if let Some(relation) = model.relation(key) {
                     let has_join_table = relation.has_join_table();
-                    let id_columns: Vec<&str> = model.primary_index().unwrap().keys().iter().map(|k| model.field(k).unwrap().column_name()).collect();
-                    let id_columns_string = id_columns.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(",").to_wrapped();
-                    let id_columns_prefixed_string = id_columns.iter().map(|s| format!("t.{}", s)).collect::<Vec<String>>();
-                    let id_columns_prefixed = id_columns_prefixed_string.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
-                    let _join_columns = if has_join_table {
-                        let (_m, r) = namespace.through_relation(relation);
-                        Some(r.references().iter().map(|k| model.field(k).unwrap().column_name()).collect::<Vec<&str>>())
-                    } else { None };
-                    let through_columns_string = if has_join_table {
-                        let (m, r) = namespace.through_relation(relation);
-                        r.fields().iter().map(|k| format!("t.{}", m.field(k).unwrap().column_name())).collect::<Vec<String>>()
-                    } else { vec![] };
-                    let through_columns = if has_join_table {
-                        through_columns_string.iter().map(|k| k.as_str()).collect::<Vec<&str>>()
-                    } else { vec![] };
-                    for (key, value) in value.as_dictionary().unwrap() {
-                        let escape = dialect.escape();
-                        let from = if !has_join_table {
-                            format!("{escape}{}{escape} AS t", model.table_name())
-                        } else {
-                            let through_table_name = namespace.model_at_path(&relation.through_path().unwrap()).unwrap().table_name();
-                            format!("{escape}{}{escape} AS t", through_table_name)
-                        };
-                        let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
-                        let relation_table_name = opposite_model.table_name();
-                        let on = if has_join_table {
+                    let outer = if let Some(alias) = table_alias {
+                        alias.to_owned()
+                    } else {
+                        model.table_name().escape(dialect)
+                    };
+                    // nested relation filters are prefixed so the correlated aliases don't shadow each other
+                    let alias_prefix = table_alias.map(|a| format!("{a}_")).unwrap_or_default();
+                    let j = format!("{alias_prefix}j");
+                    let t = format!("{alias_prefix}t");
+                    let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
+                    let relation_table_name = opposite_model.table_name();
+                    let relation_filters = Self::relation_filters(relation.is_vec(), key, value)?;
+                    for (key, value) in relation_filters {
+                        let (from, correlation) = if has_join_table {
+                            let (through_model, through_relation) = namespace.through_relation(relation);
                             let (_, opposite_relation) = namespace.opposite_relation(relation);
-                            let opposite_relation = opposite_relation.unwrap();
-                            let (join_model, join_relation) = namespace.through_relation(opposite_relation);
-                            join_relation.iter().map(|(f, r)| {
+                            let (join_model, join_relation) = namespace.through_relation(opposite_relation.unwrap());
+                            let on = join_relation.iter().map(|(f, r)| {
                                 let f = join_model.field(f).unwrap().column_name();
                                 let r = opposite_model.field(r).unwrap().column_name();
-                                format!("j.{} = t.{}", r.escape(dialect), f.escape(dialect))
-                            }).collect::<Vec<String>>().join(",")
+                                format!("{j}.{} = {t}.{}", r.escape(dialect), f.escape(dialect))
+                            }).collect::<Vec<String>>().join(" AND ");
+                            let from = format!("{} AS {t} INNER JOIN {} AS {j} ON {}", through_model.table_name().escape(dialect), relation_table_name.escape(dialect), on);
+                            let correlation = through_relation.iter().map(|(f, r)| {
+                                let f = through_model.field(f).unwrap().column_name();
+                                let r = model.field(r).unwrap().column_name();
+                                format!("{t}.{} = {outer}.{}", f.escape(dialect), r.escape(dialect))
+                            }).collect::<Vec<String>>();
+                            (from, correlation)
                         } else {
-                            relation.iter().map(|(f, r)| {
+                            let from = format!("{} AS {j}", relation_table_name.escape(dialect));
+                            let correlation = relation.iter().map(|(f, r)| {
                                 let f = model.field(f).unwrap().column_name();
                                 let r = opposite_model.field(r).unwrap().column_name();
-                                format!("j.{} = t.{}", r.escape(dialect), f.escape(dialect))
-                            }).collect::<Vec<String>>().join(",")
+                                format!("{j}.{} = {outer}.{}", r.escape(dialect), f.escape(dialect))
+                            }).collect::<Vec<String>>();
+                            (from, correlation)
                         };
-                        let addition_where = if has_join_table {
-                            let (m, r) = namespace.through_relation(relation);
-                            r.iter().map(|(f, _r)| {
-                                let f = m.field(f).unwrap().column_name();
-                                format!("t.{} IS NOT NULL", f.escape(dialect))
-                            }).collect::<Vec<String>>().join(" AND ")
-                        } else {
-                            relation.iter().map(|(f, _r)| {
-                                let f = model.field(f).unwrap().column_name();
-                                format!("t.{} IS NOT NULL", f.escape(dialect))
-                            }).collect::<Vec<String>>().join(" AND ")
-                        };
-                        let mut inner_where = Query::r#where(namespace, opposite_model, value, dialect, Some("j"));
-                        if key.as_str() == "every" {
-                            inner_where = Not(inner_where.to_wrapped()).to_string(dialect).to_wrapped();
-                        }
-                        if &inner_where == "" {
-                            inner_where = addition_where
-                        } else {
-                            inner_where = And(vec![inner_where, addition_where]).to_string(dialect);
-                        }
-                        let inner_stmt = SQL::select(Some(if has_join_table { &through_columns } else { &id_columns_prefixed }), &from)
-                            .inner_join(format!("{} AS j ON {}", relation_table_name.escape(dialect), on))
-                            .r#where(inner_where).to_string(dialect).to_wrapped();
-                        match key.as_str() {
-                            "some" | "is" => {
-                                retval.push(format!("{} IN {}", id_columns_string, inner_stmt))
-                            }
-                            "none" | "isNot" | "every" => {
-                                retval.push(format!("{} NOT IN {}", id_columns_string, inner_stmt))
-                            }
-                            _ => panic!("Unhandled key.")
+                        let inner_where = Query::r#where(namespace, opposite_model, value, dialect, Some(&j))?;
+                        if let Some(exists) = Self::relation_exists(key, &from, correlation, inner_where, dialect) {
+                            retval.push(exists);
                         }
                     }
-
+                } else {
+                    return Err(Error::new(format!("`{}' is not a field or relation of model `{}'", key, model.name())));
                 }
             }
         }
-        And(retval).to_string(dialect)
+        Ok(And(retval).to_string(dialect))
+    }
+
+    // a correlated subquery stops at the first related row and, unlike NOT IN, isn't
+    // emptied by a null key. none when the filter holds for every row anyway
+    fn relation_exists(operator: &str, from: &str, correlation: Vec<String>, inner_where: String, dialect: SQLDialect) -> Option<String> {
+        let mut inner_where = inner_where;
+        if operator == "every" {
+            if inner_where.is_empty() {
+                // every related record matches an empty filter
+                return None;
+            }
+            inner_where = Not(inner_where.to_wrapped()).to_string(dialect).to_wrapped();
+        }
+        let mut conditions = correlation;
+        if !inner_where.is_empty() {
+            conditions.push(inner_where);
+        }
+        let exists = format!("SELECT 1 FROM {} WHERE {}", from, And(conditions).to_string(dialect)).to_wrapped();
+        Some(match operator {
+            "some" | "is" => format!("EXISTS {}", exists),
+            _ => format!("NOT EXISTS {}", exists),
+        })
+    }
+
+    // a to-one relation may be filtered by its fields directly, which reads as `is`
+    fn relation_filters<'a>(is_vec: bool, relation_name: &str, value: &'a Value) -> Result<Vec<(&'a str, &'a Value)>> {
+        let map = value.as_dictionary().unwrap();
+        let operators = if is_vec { vec!["some", "none", "every"] } else { vec!["is", "isNot"] };
+        if map.keys().all(|k| operators.contains(&k.as_str())) {
+            return Ok(map.iter().map(|(k, v)| (k.as_str(), v)).collect());
+        }
+        if is_vec {
+            Err(Error::new(format!("filter on to-many relation `{}' should use some, none or every", relation_name)))
+        } else if map.keys().any(|k| operators.contains(&k.as_str())) {
+            Err(Error::new(format!("filter on relation `{}' mixes is or isNot with fields", relation_name)))
+        } else {
+            Ok(vec![("is", value)])
+        }
     }
 
     pub(crate) fn order_by(
+        namespace: &Namespace,
         model: &Model,
         order_by: &Value,
-        _dialect: SQLDialect,
+        dialect: SQLDialect,
         negative_take: bool,
-    ) -> String {
+        table_ref: &str,
+    ) -> Result<String> {
         let asc = if negative_take { "DESC" } else { "ASC" };
         let desc = if negative_take { "ASC" } else { "DESC" };
         let order_by = order_by.as_array().unwrap();
         let mut retval: Vec<String> = vec![];
-        for item in order_by.iter() {
-            let (key, value) = Input::key_value(item.as_dictionary().unwrap());
+        // a single item may carry several keys, each one is a separate term
+        for (key, value) in order_by.iter().map(|item| item.as_dictionary().unwrap()).flatten() {
             if let Some(field) = model.field(key) {
-                let column_name = field.column_name();
+                let column_name = format!("{table_ref}.{}", field.column_name().escape(dialect));
                 if let Some(str) = value.as_str() {
                     match str {
                         "asc" => retval.push(format!("{} {}", column_name, asc)),
                         "desc" => retval.push(format!("{} {}", column_name, desc)),
-                        _ => panic!("Unhandled."),
+                        _ => return Err(Error::new(format!("order of `{}' should be \"asc\" or \"desc\"", key))),
+                    }
+                } else if let Some(sort) = value.get("sort").map(|v| v.as_str()).flatten() {
+                    let nulls = value.get("nulls").map(|v| v.as_str()).flatten();
+                    retval.push(Self::order_by_nulls(&column_name, sort == "desc", nulls, dialect, negative_take)?);
+                }
+            } else if let Some(relation) = model.relation(key) {
+                if let Some(str) = value.get("_count").map(|v| v.as_str()).flatten() {
+                    let count = dialect.quote(&format!("_count.{key}"));
+                    match str {
+                        "asc" => retval.push(format!("{count} {}", asc)),
+                        "desc" => retval.push(format!("{count} {}", desc)),
+                        _ => return Err(Error::new(format!("order of `{}._count' should be \"asc\" or \"desc\"", key))),
+                    }
+                } else if relation.is_vec() || relation.has_join_table() {
+                    return Err(Error::new(format!("cannot order by fields of to-many relation `{}', order by its _count instead", key)));
+                } else {
+                    // the column of the single related row, null when there is none
+                    let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
+                    let alias = "_o".escape(dialect);
+                    let on = relation.iter().map(|(f, r)| {
+                        format!("{alias}.{} = {table_ref}.{}", opposite_model.field(r).unwrap().column_name().escape(dialect), model.field(f).unwrap().column_name().escape(dialect))
+                    }).join(" AND ");
+                    for (related_key, related_value) in value.as_dictionary().unwrap() {
+                        let Some(related_field) = opposite_model.field(related_key) else {
+                            return Err(Error::new(format!("cannot order by `{}.{}', only fields of a related model are supported", key, related_key)));
+                        };
+                        let term = format!("(SELECT {alias}.{} FROM {} AS {alias} WHERE {on})", related_field.column_name().escape(dialect), opposite_model.table_name().escape(dialect));
+                        if let Some(str) = related_value.as_str() {
+                            retval.push(Self::order_by_nulls(&term, str == "desc", None, dialect, negative_take)?);
+                        } else if let Some(sort) = related_value.get("sort").map(|v| v.as_str()).flatten() {
+                            let nulls = related_value.get("nulls").map(|v| v.as_str()).flatten();
+                            retval.push(Self::order_by_nulls(&term, sort == "desc", nulls, dialect, negative_take)?);
+                        }
                     }
                 }
             }
         }
-        retval.join(",")
+        Ok(retval.join(","))
+    }
+
+
+    // negative take reads the rows backwards and reverses them in memory,
+    // so both the direction and the null placement are flipped here
+    fn order_by_nulls(column_name: &str, desc: bool, nulls: Option<&str>, dialect: SQLDialect, negative_take: bool) -> Result<String> {
+        let desc = desc != negative_take;
+        let direction = if desc { "DESC" } else { "ASC" };
+        let nulls_first = match nulls {
+            Some("first") => Some(!negative_take),
+            Some("last") => Some(negative_take),
+            None => None,
+            Some(nulls) => return Err(Error::new(format!("nulls should be \"first\" or \"last\", not \"{}\"", nulls))),
+        };
+        Ok(match nulls_first {
+            None => format!("{} {}", column_name, direction),
+            Some(nulls_first) => if dialect.is_postgres() {
+                format!("{} {} NULLS {}", column_name, direction, if nulls_first { "FIRST" } else { "LAST" })
+            } else {
+                // mysql and sql server lack NULLS FIRST / LAST, sqlite only has it since 3.30
+                format!("CASE WHEN {} IS NULL THEN {} ELSE {} END,{} {}", column_name, if nulls_first { 0 } else { 1 }, if nulls_first { 1 } else { 0 }, column_name, direction)
+            }
+        })
     }
 
     pub(crate) fn build_for_count(
@@ -316,8 +680,33 @@ impl Query {
         additional_left_join: Option<String>,
         join_table_results: Option<Vec<String>>,
         force_negative_take: bool,
-    ) -> Result<String> {
-        Ok(format!("SELECT COUNT(*) FROM ({}) AS _", Self::build(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, force_negative_take)?))
+    ) -> Result<(String, Vec<QuaintValue<'static>>)> {
+        let stmt = format!("SELECT COUNT(*) FROM ({}) AS _", Self::select_sql(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, force_negative_take, false, None)?);
+        Ok(bind_params(&stmt, dialect))
+    }
+
+    // rows with nulls count as one distinct value, same as the in memory dedup of find many
+    pub(crate) fn build_for_count_distinct(
+        namespace: &Namespace,
+        model: &Model,
+        value: &Value,
+        dialect: SQLDialect,
+        distinct: &Vec<Value>,
+    ) -> Result<(String, Vec<QuaintValue<'static>>)> {
+        let columns = distinct.iter().map(|d| {
+            let Some(key) = d.as_str() else {
+                return Err(Error::new("distinct keys should be field names"));
+            };
+            if let Some(field) = model.field(key) {
+                Ok(field.column_name().escape(dialect))
+            } else if let Some(property) = model.property(key) {
+                Ok(property.column_name().escape(dialect))
+            } else {
+                Err(Error::new(format!("distinct key '{}' is not a field", key)))
+            }
+        }).collect::<Result<Vec<String>>>()?.join(",");
+        let stmt = format!("SELECT COUNT(*) FROM (SELECT DISTINCT {} FROM ({}) AS _) AS _d", columns, Self::select_sql(namespace, model, value, dialect, None, None, None, false, false, None)?);
+        Ok(bind_params(&stmt, dialect))
     }
 
     pub(crate) fn build_for_group_by(
@@ -325,20 +714,92 @@ impl Query {
         model: &Model,
         value: &Value,
         dialect: SQLDialect,
-    ) -> Result<String> {
-        let aggregate = Self::build_for_aggregate(namespace, model, value, dialect)?;
+    ) -> Result<(String, Vec<QuaintValue<'static>>)> {
+        let aggregate = Self::aggregate_sql(namespace, model, value, dialect)?;
         let map = value.as_dictionary().unwrap();
         let by = map.get("by").unwrap().as_array().unwrap().iter().map(|v| {
             let field_name = v.as_str().unwrap();
             model.field(field_name).unwrap().column_name()
-        }).collect::<Vec<&str>>().iter().map(|o| format!("{}{}{}", dialect.escape(), o, dialect.escape())).join(",");
+        }).collect::<Vec<&str>>().iter().map(|o| dialect.quote(o)).join(",");
         let having = if let Some(having) = map.get("having") {
-            let inner = Query::r#where(namespace, model, having, dialect, None);
-            " HAVING (".to_owned() + dialect.escape() + &inner + dialect.escape() + ")"
+            let inner = Query::r#where(namespace, model, having, dialect, None)?;
+            " HAVING (".to_owned() + &inner + ")"
         } else {
             "".to_owned()
         };
-        Ok(format!("{} GROUP BY {}{}", aggregate, by, having))
+        let order_by = if let Some(order_by) = map.get("orderBy") {
+            " ORDER BY ".to_owned() + &Self::group_by_order_by(model, order_by, dialect)?
+        } else {
+            "".to_owned()
+        };
+        Ok(bind_params(&format!("{} GROUP BY {}{}{}", aggregate, by, having, order_by), dialect))
+    }
+
+    // aggregates are ordered by their expression, not every database accepts
+    // the dotted select alias there
+    fn group_by_order_by(model: &Model, order_by: &Value, dialect: SQLDialect) -> Result<String> {
+        Self::group_by_order_terms(order_by, |key| model.field(key).map(|f| f.column_name().to_owned()), dialect)
+    }
+
+    fn group_by_order_terms(order_by: &Value, column: impl Fn(&str) -> Option<String>, dialect: SQLDialect) -> Result<String> {
+        let direction = |value: &Value| match value.as_str() {
+            Some("asc") => Ok("ASC"),
+            Some("desc") => Ok("DESC"),
+            _ => Err(Error::new("group by order should be \"asc\" or \"desc\"")),
+        };
+        let Some(order_by) = order_by.as_array() else {
+            return Err(Error::new("group by orderBy should be an array"));
+        };
+        let mut retval: Vec<String> = vec![];
+        for item in order_by {
+            let Some(item) = item.as_dictionary() else {
+                return Err(Error::new("group by orderBy items should be dictionaries"));
+            };
+            for (key, value) in item {
+                match key.as_str() {
+                    "_count" | "_sum" | "_avg" | "_min" | "_max" => {
+                        let Some(fields) = value.as_dictionary() else {
+                            return Err(Error::new(format!("group by order of `{}' should be a dictionary", key)));
+                        };
+                        for (k, v) in fields {
+                            let expression = if k == "_all" {
+                                "COUNT(*)".to_owned()
+                            } else if let Some(column_name) = column(k) {
+                                Self::aggregate_column(key, &column_name, dialect)
+                            } else {
+                                return Err(Error::new(format!("cannot order groups by `{}' of `{}', it is not a field", key, k)));
+                            };
+                            retval.push(format!("{} {}", expression, direction(v)?));
+                        }
+                    }
+                    _ => {
+                        let Some(column_name) = column(key) else {
+                            return Err(Error::new(format!("cannot order groups by `{}', it is not a field", key)));
+                        };
+                        retval.push(format!("{} {}", column_name.escape(dialect), direction(value)?));
+                    }
+                }
+            }
+        }
+        Ok(retval.join(","))
+    }
+
+    fn aggregate_expression(model: &Model, func_key: &str, field_name: &str, dialect: SQLDialect) -> String {
+        if field_name == "_all" {
+            return "COUNT(*)".to_owned();
+        }
+        Self::aggregate_column(func_key, model.field(field_name).unwrap().column_name(), dialect)
+    }
+
+    fn aggregate_column(func_key: &str, column_name: &str, dialect: SQLDialect) -> String {
+        let column_name = dialect.quote(column_name);
+        let func = SQL_AGGREGATE_MAP.get(func_key).unwrap();
+        // CAST(AVG(id) as DOUBLE)
+        let expression = format!("{}({})", func, column_name);
+        match func_key {
+            "_avg" | "_sum" => format!("CAST({} AS DOUBLE)", expression),
+            _ => expression,
+        }
     }
 
     pub(crate) fn build_for_aggregate(
@@ -346,9 +807,17 @@ impl Query {
         model: &Model,
         value: &Value,
         dialect: SQLDialect,
+    ) -> Result<(String, Vec<QuaintValue<'static>>)> {
+        Ok(bind_params(&Self::aggregate_sql(namespace, model, value, dialect)?, dialect))
+    }
+
+    fn aggregate_sql(
+        namespace: &Namespace,
+        model: &Model,
+        value: &Value,
+        dialect: SQLDialect,
     ) -> Result<String> {
         let map = value.as_dictionary().unwrap();
-        let escape = dialect.escape();
         let mut results: Vec<String> = vec![];
         for (key, value) in map {
             match key.as_str() {
@@ -356,20 +825,8 @@ impl Query {
                     for (k, v) in value.as_dictionary().unwrap() {
                         let k = k.as_str();
                         if v.as_bool().unwrap() {
-                            match k {
-                                "_all" => results.push(format!("COUNT(*) as {escape}_count._all{escape}")),
-                                _ => {
-                                    let column_name = model.field(k).unwrap().column_name();
-                                    let func = SQL_AGGREGATE_MAP.get(key.as_str()).unwrap();
-                                    // CAST(AVG(id) as DOUBLE)
-                                    let mut left = format!("{}({})", func, column_name);
-                                    match key.as_str() {
-                                        "_avg" | "_sum" => left = format!("CAST({} AS DOUBLE)", left),
-                                        _ => ()
-                                    }
-                                    results.push(format!("{} as {escape}{}.{}{escape}", left, key, k));
-                                }
-                            }
+                            let expression = Self::aggregate_expression(model, key, k, dialect);
+                            results.push(format!("{} as {}", expression, dialect.quote(&format!("{}.{}", key, k))));
                         }
                     }
                 }
@@ -379,13 +836,37 @@ impl Query {
         if let Some(by) = map.get("by") {
             for k in by.as_array().unwrap() {
                 let field_name = k.as_str().unwrap();
-                results.push(format!("{}{}{}", dialect.escape(), model.field(field_name).unwrap().column_name(), dialect.escape()));
+                results.push(dialect.quote(model.field(field_name).unwrap().column_name()));
             }
         }
-        Ok(format!("SELECT {} FROM ({}) AS _", results.join(","), Self::build(namespace, model, value, dialect, None, None, None, false)?))
+        let source = Self::select_sql(namespace, model, value, dialect, None, None, None, false, false, None)?;
+        let source = match map.get("distinct").map(|d| d.as_array().unwrap()).filter(|d| !d.is_empty()) {
+            Some(distinct) => Self::distinct_source(model, distinct, source, dialect),
+            None => source,
+        };
+        Ok(format!("SELECT {} FROM ({}) AS _", results.join(","), source))
     }
 
-    pub(crate) fn build(
+    // keeps the first row by primary key of every distinct key combination,
+    // each kept row is a real row of the table
+    fn distinct_source(model: &Model, distinct: &Vec<Value>, source: String, dialect: SQLDialect) -> String {
+        let column_name = |key: &str| model.field(key).map(|f| f.column_name().to_owned()).unwrap_or(key.to_owned());
+        let key_columns = distinct.iter().map(|d| column_name(d.as_str().unwrap()).escape(dialect)).join(",");
+        let primary_columns = model.primary_index().unwrap().items().iter().map(|item| column_name(&item.field).escape(dialect)).join(",");
+        Self::first_row_per_group(&key_columns, &primary_columns, &source, dialect)
+    }
+
+    fn first_row_per_group(key_columns: &str, primary_columns: &str, source: &str, dialect: SQLDialect) -> String {
+        if dialect.is_postgres() {
+            format!("SELECT DISTINCT ON ({key_columns}) * FROM ({source}) AS _d ORDER BY {key_columns},{primary_columns}")
+        } else {
+            let row_number = dialect.quote("_row_number");
+            format!("SELECT * FROM (SELECT _s.*, ROW_NUMBER() OVER (PARTITION BY {key_columns} ORDER BY {primary_columns}) AS {row_number} FROM ({source}) AS _s) AS _d WHERE {row_number} = 1")
+        }
+    }
+
+    // the select with its bound values still inline, see `params`
+    fn select_sql(
         namespace: &Namespace,
         model: &Model,
         value: &Value,
@@ -394,6 +875,8 @@ impl Query {
         additional_left_join: Option<String>,
         join_table_results: Option<Vec<String>>,
         force_negative_take: bool,
+        with_total_count: bool,
+        lock: Option<SQLLock>,
     ) -> Result<String> {
         let r#where = value.get("where");
         let order_by = value.get("orderBy");
@@ -402,6 +885,7 @@ impl Query {
         let skip = value.get("skip");
         let take = value.get("take");
         let cursor = value.get("cursor");
+        let after = value.get("after");
         let negative_take = if let Some(take) = take {
             take.as_int64().unwrap().is_negative()
         } else if force_negative_take {
@@ -409,7 +893,14 @@ impl Query {
         } else {
             false
         };
-        let table_name = if additional_left_join.is_some() {
+        // relation counts selected and ordered by share a single joined subquery
+        let relation_counts = Self::relation_counts(model, value);
+        let relation_scalars = Self::relation_scalars(model, value)?;
+        // the joined tables share column names with this one, every reference is qualified then
+        let aliased = additional_left_join.is_some() || !relation_counts.is_empty() || !relation_scalars.is_empty();
+        let source_alias = if aliased { Some("t") } else { None };
+        let table_ref = if aliased { "t".escape(dialect) } else { model.table_name().escape(dialect) };
+        let table_name = if aliased {
             model.table_name().to_string().escape(dialect) + " AS t"
         } else {
             model.table_name().to_string().escape(dialect)
@@ -423,23 +914,59 @@ impl Query {
                 columns.push(result_key);
             }
         }
+        let mut count_left_joins: Vec<String> = vec![];
+        if !relation_counts.is_empty() {
+            if columns.is_empty() {
+                columns.push(format!("{table_ref}.*"));
+            }
+            for relation_name in relation_counts {
+                let alias = format!("_c_{}", relation_name).escape(dialect);
+                columns.push(format!("COALESCE({alias}.{}, 0) AS {}", dialect.quote("_count"), dialect.quote(&format!("_count.{relation_name}"))));
+                count_left_joins.push(Self::relation_count_left_join(namespace, model, relation_name, &alias, &table_ref, dialect));
+            }
+        }
+        // scalars of to-one relations selected by `{ select: { author: { name: true } } }`
+        // come back flattened as `author.name`
+        for (relation_name, field_names) in relation_scalars {
+            let relation = model.relation(relation_name).unwrap();
+            if relation.is_vec() || relation.has_join_table() {
+                return Err(Error::new(format!("only scalars of to-one relations can be selected, `{}' is to-many", relation_name)));
+            }
+            if columns.is_empty() {
+                columns.push(format!("{table_ref}.*"));
+            }
+            let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
+            let alias = format!("_r_{}", relation_name).escape(dialect);
+            for field_name in field_names {
+                let Some(field) = opposite_model.field(field_name) else {
+                    return Err(Error::new(format!("`{}' is not a field of relation `{}'", field_name, relation_name)));
+                };
+                columns.push(format!("{alias}.{} AS {}", field.column_name().escape(dialect), dialect.quote(&format!("{}.{}", relation_name, field_name))));
+            }
+            let on = relation.iter().map(|(f, r)| {
+                format!("{alias}.{} = {table_ref}.{}", opposite_model.field(r).unwrap().column_name().escape(dialect), model.field(f).unwrap().column_name().escape(dialect))
+            }).join(" AND ");
+            count_left_joins.push(format!("{} AS {alias} ON {on}", opposite_model.table_name().escape(dialect)));
+        }
+        // total count of matched rows before paging, in the same round trip
+        if with_total_count {
+            if columns.is_empty() {
+                columns.push(format!("{table_ref}.*"));
+            }
+            columns.push(format!("COUNT(*) OVER () AS {}", dialect.quote("_total_count")));
+        }
         let column_refs = columns.iter().map(|c| c.as_str()).collect::<Vec<&str>>();
         let from = if let Some(cursor) = cursor {
             if order_by.is_none() {
                 return Err(Error::new("cursor is invalid without order by argument"));
             }
-            let order_by = order_by.unwrap().as_array().unwrap().get(0).unwrap().as_dictionary().unwrap();
-            let key = order_by.keys().next().unwrap();
-            let column_key = model.field(key).unwrap().column_name();
-            let columns = cursor.as_dictionary().unwrap().keys().map(|_k| {
-                if dialect == SQLDialect::PostgreSQL {
-                    format!("{} AS \"c.{}\"", column_key, column_key)
-                } else {
-                    format!("{} AS `c.{}`", column_key, column_key)
-                }
+            // the cursor row's values of the ordered columns, named apart from the table's own
+            let columns = Self::cursor_keys(model, order_by.unwrap()).iter().map(|(field_name, _)| {
+                let column_key = model.field(field_name).unwrap().column_name();
+                format!("{} AS {}", dialect.quote(column_key), dialect.quote(&format!("c.{}", column_key)))
             }).collect::<Vec<String>>();
             let column_refs: Vec<&str> = columns.iter().map(|k| k.as_str()).collect();
-            let sub_where = Query::r#where(namespace, model, cursor, dialect, None);
+            let sub_where = Query::r#where(namespace, model, cursor, dialect, None)?;
             let mut query = SQL::select(Some(&column_refs), &table_name);
             query.r#where(sub_where);
             format!("{}, ({}) AS c", &table_name, &query.to_string(dialect))
@@ -449,7 +976,7 @@ impl Query {
         let mut stmt = SQL::select(if columns.is_empty() { None } else { Some(&column_refs) }, from.as_ref());
         if let Some(r#where) = r#where {
             if !r#where.as_dictionary().unwrap().is_empty() {
-                stmt.r#where(Query::r#where(namespace, model, r#where, dialect, None));
+                stmt.r#where(Query::r#where(namespace, model, r#where, dialect, source_alias)?);
             }
         }
         if let Some(additional_where) = additional_where {
@@ -459,45 +986,329 @@ impl Query {
                 stmt.r#where(additional_where.to_string());
             }
         }
+        // keyset pagination always orders by a unique tail so the next page is well defined
+        let order_by: Option<Cow<Value>> = if after.is_some() {
+            Some(Cow::Owned(Self::keyset_order_by(model, order_by)))
+        } else {
+            order_by.map(Cow::Borrowed)
+        };
+        let order_by = order_by.as_ref().map(|o| o.as_ref());
+        if let Some(after) = after {
+            let keyset_where = Self::keyset_where(model, order_by.unwrap(), after, dialect, negative_take, &table_ref)?;
+            if stmt.r#where.is_some() {
+                stmt.r#where(And(vec![stmt.r#where.as_ref().unwrap().clone(), keyset_where]).to_string(dialect));
+            } else {
+                stmt.r#where(keyset_where);
+            }
+        }
         if cursor.is_some() {
-            let order_by = order_by.unwrap().as_array().unwrap().get(0).unwrap().as_dictionary().unwrap();
-            let key = order_by.keys().next().unwrap();
-            let order = if order_by.values().next().unwrap().as_str().unwrap() == if negative_take { "desc" } else { "asc" }
-                { ">=" } else { "<=" };
-            let cursor_where = Query::where_item(&key, order, &format!("`c.{}`", key));
+            let cursor_where = Self::cursor_where(model, order_by.unwrap(), dialect, negative_take, &table_ref);
             if stmt.r#where.is_some() {
                 stmt.r#where(And(vec![stmt.r#where.as_ref().unwrap().clone(), cursor_where]).to_string(dialect));
             } else {
                 stmt.r#where(cursor_where);
             }
         }
-        if let Some(additional_left_join) = additional_left_join {
-            stmt.left_join(additional_left_join);
+        let left_joins = additional_left_join.into_iter().chain(count_left_joins.into_iter()).collect::<Vec<String>>();
+        if !left_joins.is_empty() {
+            stmt.left_join(left_joins.join(" LEFT JOIN "));
         }
         if let Some(order_bys) = order_by {
-            stmt.order_by(Query::order_by(model, order_bys, dialect, negative_take));
+            stmt.order_by(Query::order_by(namespace, model, order_bys, dialect, negative_take, &table_ref)?);
         } else if negative_take {
             let val = Self::default_desc_order(model);
-            stmt.order_by(Query::order_by(model, &val, dialect, false));
+            stmt.order_by(Query::order_by(namespace, model, &val, dialect, false, &table_ref)?);
+        }
+        if let Some(field_name) = value.get("preserveInOrder").map(|v| v.as_str()).flatten() {
+            if let Some(in_order) = Self::in_order_by(model, field_name, r#where, dialect, source_alias)? {
+                stmt.order_by(match &stmt.order_by {
+                    Some(existing) => format!("{in_order},{existing}"),
+                    None => in_order,
+                });
+            }
+        }
+        let int = |value: Option<&Value>| value.map(|v| v.as_int64().unwrap());
+        match Self::paging(int(page_size), int(page_number), int(skip), int(take), dialect) {
+            Some((Some(limit), skip)) => { stmt.limit(limit, skip); }
+            Some((None, skip)) => { stmt.offset(skip); }
+            None => (),
+        }
+        // sql server only pages an ordered result, fall back to primary key order
+        if dialect.is_mssql() && stmt.limit.is_some() && stmt.order_by.is_none() {
+            let val = Self::default_desc_order(model);
+            stmt.order_by(Query::order_by(namespace, model, &val, dialect, !negative_take, &table_ref)?);
+        }
+        if let Some(lock) = lock {
+            stmt.lock(lock);
+        }
+        let result = stmt.to_string(dialect);
+        if let Some(comment) = value.get("comment").map(|c| c.as_str()).flatten() {
+            return Ok(sql_comment(comment) + &result);
         }
-        if page_size.is_some() && page_number.is_some() {
-            let skip: u64 = ((page_number.unwrap().as_int64().unwrap() - 1) * page_size.unwrap().as_int64().unwrap()) as u64;
-            let limit: u64 = page_size.unwrap().as_int64().unwrap() as u64;
-            stmt.limit(limit, skip);
+        Ok(result)
+    }
+
+    // the limit and offset of the requested page, any i64 input is clamped instead of overflowing
+    fn paging(page_size: Option<i64>, page_number: Option<i64>, skip: Option<i64>, take: Option<i64>, dialect: SQLDialect) -> Option<(Option<u64>, u64)> {
+        if let (Some(page_size), Some(page_number)) = (page_size, page_number) {
+            let page_number = page_number.saturating_sub(1).max(0);
+            let page_size = page_size.max(0);
+            Some((Some(page_size as u64), page_number.saturating_mul(page_size) as u64))
         } else if skip.is_some() || take.is_some() {
-            let skip: u64 = if skip.is_some() { skip.unwrap().as_int64().unwrap() as u64 } else { 0 };
-            if dialect == SQLDialect::MySQL {
-                let limit: u64 = if take.is_some() { take.unwrap().as_int64().unwrap().abs() as u64 } else { 18446744073709551615 };
-                stmt.limit(limit, skip);
+            let skip = skip.unwrap_or(0).max(0) as u64;
+            let limit = take.map(|take| if dialect == SQLDialect::MySQL {
+                take.unsigned_abs()
             } else {
-                let limit: u64 = if take.is_some() { take.unwrap().as_int64().unwrap().abs() as u64 } else { 9223372036854775806 };
-                stmt.limit(limit, skip);
+                // bigint limit, i64::MIN.unsigned_abs() doesn't fit
+                take.unsigned_abs().min(i64::MAX as u64)
+            });
+            Some((limit, skip))
+        } else {
+            None
+        }
+    }
+
+    // rows come back in the order of the `in` list the field is filtered by,
+    // the explicit order only breaks ties between rows sharing a value
+    fn in_order_by(model: &Model, field_name: &str, r#where: Option<&Value>, dialect: SQLDialect, table_alias: Option<&str>) -> Result<Option<String>> {
+        let Some(field) = model.field(field_name) else {
+            return Err(Error::new(format!("preserveInOrder: unknown field '{}'", field_name)));
+        };
+        let Some(list) = r#where.map(|w| w.get(field_name)).flatten().map(|f| f.get("in")).flatten().map(|l| l.as_array()).flatten() else {
+            return Err(Error::new(format!("preserveInOrder: '{}' is not filtered by an in list", field_name)));
+        };
+        let column = match table_alias {
+            Some(alias) => format!("{}.{}", alias, dialect.quote(field.column_name())),
+            None => dialect.quote(field.column_name()),
+        };
+        let values: Vec<String> = list.iter().map(|v| bind(v, field.r#type(), false, dialect)).collect::<Result<Vec<String>>>()?;
+        if values.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(match dialect {
+            SQLDialect::PostgreSQL => format!("array_position(ARRAY[{}], {column})", values.join(",")),
+            SQLDialect::MySQL => format!("FIELD({column}, {})", values.join(",")),
+            _ => format!("CASE {column} {} END", values.iter().enumerate().map(|(i, v)| format!("WHEN {v} THEN {i}")).join(" ")),
+        }))
+    }
+
+    pub(crate) fn build(
+        namespace: &Namespace,
+        model: &Model,
+        value: &Value,
+        dialect: SQLDialect,
+        additional_where: Option<String>,
+        additional_left_join: Option<String>,
+        join_table_results: Option<Vec<String>>,
+        force_negative_take: bool,
+        with_total_count: bool,
+        lock: Option<SQLLock>,
+    ) -> Result<(String, Vec<QuaintValue<'static>>)> {
+        let stmt = Self::select_sql(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, force_negative_take, with_total_count, lock)?;
+        Ok(bind_params(&stmt, dialect))
+    }
+
+    fn keyset_order_by(model: &Model, order_by: Option<&Value>) -> Value {
+        let mut items = order_by.map(|o| o.as_array().unwrap().clone()).unwrap_or(vec![]);
+        let last_desc = items.last().map(|item| item.as_dictionary().unwrap().values().last()).flatten().map(|value| Self::order_by_item_is_desc(value)).unwrap_or(false);
+        let direction = if last_desc { "desc" } else { "asc" };
+        for item in model.primary_index().unwrap().items() {
+            if !items.iter().any(|i| i.as_dictionary().unwrap().contains_key(&item.field)) {
+                items.push(Value::Dictionary(indexmap!{item.field.clone() => Value::String(direction.to_owned())}));
+            }
+        }
+        Value::Array(items)
+    }
+
+    fn order_by_item_is_desc(value: &Value) -> bool {
+        if let Some(str) = value.as_str() {
+            str == "desc"
+        } else {
+            value.get("sort").map(|v| v.as_str()).flatten() == Some("desc")
+        }
+    }
+
+    // (a, b) > (x, y) when every key sorts the same way, expanded into
+    // a > x OR (a = x AND b < y) otherwise. nullable keys are always expanded,
+    // comparing with NULL yields NULL, the IS NULL branches follow the null placement
+    fn keyset_where(model: &Model, order_by: &Value, after: &Value, dialect: SQLDialect, negative_take: bool, table_ref: &str) -> Result<String> {
+        // column, desc, nulls first, nullable, value or none for null
+        let mut keys: Vec<(String, bool, bool, bool, Option<String>)> = vec![];
+        for (key, value) in order_by.as_array().unwrap().iter().map(|item| item.as_dictionary().unwrap()).flatten() {
+            let Some(field) = model.field(key) else {
+                return Err(Error::new(format!("after only pages by fields, `{}' isn't one", key)));
+            };
+            let column_name = format!("{table_ref}.{}", dialect.quote(field.column_name()));
+            let Some(after_value) = after.get(key) else {
+                return Err(Error::new(format!("after is missing a value for `{}'", key)));
+            };
+            let item_desc = Self::order_by_item_is_desc(value);
+            let nulls_first = Self::sorts_nulls_first(value, item_desc, dialect);
+            let nullable = field.optionality().is_any_optional();
+            check_finite(after_value, dialect)?;
+            let sql_value = if after_value.is_null() { None } else { Some(bind(after_value, field.r#type(), nullable, dialect)?) };
+            keys.push((column_name, item_desc != negative_take, nulls_first != negative_take, nullable, sql_value));
+        }
+        Ok(Self::keyset_condition(&keys, dialect))
+    }
+
+    // where the nulls of an order by item end up, before reversing for a negative take
+    fn sorts_nulls_first(value: &Value, item_desc: bool, dialect: SQLDialect) -> bool {
+        match value.get("nulls").map(|v| v.as_str()).flatten() {
+            Some("first") => true,
+            Some("last") => false,
+            // postgres sorts nulls as the largest values, mysql and sqlite as the smallest
+            _ => if dialect.is_postgres() { item_desc } else { !item_desc },
+        }
+    }
+
+    fn cursor_keys<'a>(model: &Model, order_by: &'a Value) -> Vec<(&'a str, &'a Value)> {
+        order_by.as_array().unwrap().iter().map(|item| item.as_dictionary().unwrap()).flatten().filter(|(key, _)| {
+            model.field(key).is_some()
+        }).map(|(key, value)| (key.as_str(), value)).collect()
+    }
+
+    // the cursor row and every row after it, comparing the keys lexicographically
+    // against the cursor subquery's columns
+    fn cursor_where(model: &Model, order_by: &Value, dialect: SQLDialect, negative_take: bool, table_ref: &str) -> String {
+        let keys: Vec<(String, bool, bool, bool, Option<String>)> = Self::cursor_keys(model, order_by).iter().filter_map(|(key, value)| {
+            let field = model.field(key)?;
+            let item_desc = Self::order_by_item_is_desc(value);
+            let nulls_first = Self::sorts_nulls_first(value, item_desc, dialect);
+            let column = format!("{table_ref}.{}", dialect.quote(field.column_name()));
+            let cursor_column = dialect.quote(&format!("c.{}", field.column_name()));
+            Some((column, item_desc != negative_take, nulls_first != negative_take, field.optionality().is_any_optional(), Some(cursor_column)))
+        }).collect();
+        Self::cursor_condition(&keys, dialect)
+    }
+
+    // like `keyset_condition`, but whether the cursor's value is null is only known
+    // to the database, nullable keys check it in the sql
+    fn cursor_condition(keys: &Vec<(String, bool, bool, bool, Option<String>)>, dialect: SQLDialect) -> String {
+        let same = |(column, _, _, nullable, value): &(String, bool, bool, bool, Option<String>)| {
+            let value = value.as_ref().unwrap();
+            if *nullable {
+                format!("({column} = {value} OR ({column} IS NULL AND {value} IS NULL))")
+            } else {
+                Self::where_item(column, "=", value)
+            }
+        };
+        let same_row = And(keys.iter().map(same).collect()).to_wrapped_string(dialect);
+        if !keys.iter().any(|(_, _, _, nullable, _)| *nullable) {
+            return WhereClause::Or(vec![Self::keyset_condition(keys, dialect), same_row]).to_wrapped_string(dialect);
+        }
+        let mut alternatives = vec![];
+        for (index, (column, desc, nulls_first, nullable, value)) in keys.iter().enumerate() {
+            let value = value.as_ref().unwrap();
+            let after = Self::where_item(column, if *desc { "<" } else { ">" }, value);
+            let after_this = if !*nullable {
+                after
+            } else if *nulls_first {
+                WhereClause::Or(vec![after, format!("({value} IS NULL AND {column} IS NOT NULL)")]).to_wrapped_string(dialect)
+            } else {
+                WhereClause::Or(vec![after, format!("({column} IS NULL AND {value} IS NOT NULL)")]).to_wrapped_string(dialect)
+            };
+            let mut conditions: Vec<String> = keys[..index].iter().map(same).collect();
+            conditions.push(after_this);
+            alternatives.push(And(conditions).to_wrapped_string(dialect));
+        }
+        alternatives.push(same_row);
+        WhereClause::Or(alternatives).to_wrapped_string(dialect)
+    }
+
+    // rows strictly after the given values in the order the keys describe
+    fn keyset_condition(keys: &Vec<(String, bool, bool, bool, Option<String>)>, dialect: SQLDialect) -> String {
+        let op = |desc: bool| if desc { "<" } else { ">" };
+        // sql server has no row value comparison
+        if !dialect.is_mssql() && keys.iter().all(|(_, desc, _, nullable, value)| *desc == keys[0].1 && !*nullable && value.is_some()) {
+            let columns = keys.iter().map(|(c, _, _, _, _)| c.as_str()).join(",");
+            let values = keys.iter().map(|(_, _, _, _, v)| v.as_ref().unwrap().as_str()).join(",");
+            return Self::where_item(columns.to_wrapped(), op(keys[0].1), &values.to_wrapped());
+        }
+        let mut alternatives = vec![];
+        for (index, (column, desc, nulls_first, nullable, value)) in keys.iter().enumerate() {
+            let after_this = match value {
+                Some(value) => if *nullable && !*nulls_first {
+                    WhereClause::Or(vec![Self::where_item(column, op(*desc), value), format!("{} IS NULL", column)]).to_wrapped_string(dialect)
+                } else {
+                    Self::where_item(column, op(*desc), value)
+                },
+                None => if *nulls_first {
+                    format!("{} IS NOT NULL", column)
+                } else {
+                    // nothing sorts after a trailing null on this key
+                    continue
+                },
+            };
+            let mut conditions: Vec<String> = keys[..index].iter().map(|(c, _, _, _, v)| match v {
+                Some(v) => Self::where_item(c, "=", v),
+                None => format!("{} IS NULL", c),
+            }).collect();
+            conditions.push(after_this);
+            alternatives.push(And(conditions).to_wrapped_string(dialect));
+        }
+        if alternatives.is_empty() {
+            return "1 = 0".to_owned();
+        }
+        WhereClause::Or(alternatives).to_wrapped_string(dialect)
+    }
+
+    fn relation_scalars<'a>(model: &Model, value: &'a Value) -> Result<Vec<(&'a str, Vec<&'a str>)>> {
+        let mut result = vec![];
+        if let Some(select) = value.get("select").map(|s| s.as_dictionary()).flatten() {
+            for (key, selected) in select {
+                if key == "_count" || model.relation(key).is_none() {
+                    continue;
+                }
+                let Some(fields) = selected.as_dictionary() else {
+                    return Err(Error::new(format!("select relation `{}' with the fields to pull from it", key)));
+                };
+                let field_names = fields.iter().filter(|(_, v)| v.as_bool().unwrap_or(false)).map(|(k, _)| k.as_str()).collect();
+                result.push((key.as_str(), field_names));
             }
         }
-        let result = stmt.to_string(dialect);
         Ok(result)
     }
 
+    fn relation_counts<'a>(model: &Model, value: &'a Value) -> Vec<&'a str> {
+        let mut result: Vec<&str> = vec![];
+        if let Some(counts) = value.get("select").map(|s| s.get("_count")).flatten().map(|c| c.as_dictionary()).flatten() {
+            for (key, selected) in counts {
+                if selected.as_bool().unwrap_or(false) && model.relation(key).is_some() {
+                    result.push(key.as_str());
+                }
+            }
+        }
+        if let Some(order_by) = value.get("orderBy").map(|o| o.as_array()).flatten() {
+            for item in order_by {
+                for (key, value) in item.as_dictionary().unwrap() {
+                    if model.relation(key).is_some() && value.get("_count").is_some() && !result.contains(&key.as_str()) {
+                        result.push(key.as_str());
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn relation_count_left_join(namespace: &Namespace, model: &Model, relation_name: &str, alias: &str, table_ref: &str, dialect: SQLDialect) -> String {
+        let relation = model.relation(relation_name).unwrap();
+        let (count_table, pairs) = if relation.has_join_table() {
+            let (through_model, through_relation) = namespace.through_relation(relation);
+            (through_model.table_name(), through_relation.iter().map(|(f, r)| {
+                (through_model.field(f).unwrap().column_name(), model.field(r).unwrap().column_name())
+            }).collect::<Vec<(&str, &str)>>())
+        } else {
+            let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
+            (opposite_model.table_name(), relation.iter().map(|(f, r)| {
+                (opposite_model.field(r).unwrap().column_name(), model.field(f).unwrap().column_name())
+            }).collect::<Vec<(&str, &str)>>())
+        };
+        let group_columns = pairs.iter().map(|(c, _)| c.escape(dialect)).join(",");
+        let on = pairs.iter().map(|(c, r)| format!("{alias}.{} = {table_ref}.{}", c.escape(dialect), r.escape(dialect))).join(" AND ");
+        format!("(SELECT {group_columns}, COUNT(*) AS {} FROM {} GROUP BY {group_columns}) AS {alias} ON {on}", dialect.quote("_count"), count_table.escape(dialect))
+    }
+
     fn default_desc_order(model: &Model) -> Value {
         let mut vec: Vec<Value> = vec![];
         for item in model.primary_index().unwrap().items() {
@@ -519,10 +1330,331 @@ static SQL_AGGREGATE_MAP: Lazy<BTreeMap<&str, &str>> = Lazy::new(|| {
 
 pub(crate) fn escape_wisdom(s: impl AsRef<str>, dialect: SQLDialect) -> String {
     let s = s.as_ref();
-    let escape = dialect.escape();
-    if s.contains(escape) {
+    // already quoted by the caller
+    if s.contains(dialect.opening_quote()) {
         s.to_owned()
     } else {
-        s.split(".").map(|s| format!("{escape}{s}{escape}")).join(".")
+        s.split(".").map(|s| dialect.quote(s)).join(".")
+    }
+}
+
+// the comment text can't close the comment early
+pub(crate) fn sql_comment(comment: &str) -> String {
+    // the comment can be bound with the statement, keep the value markers out of it
+    format!("/* {} */ ", comment.replace("*/", "* /").replace("/*", "/ *").replace(MARK, ""))
+}
+
+// a small duration grammar, e.g. 30s, 15m, 12h, 7d, 2w or combined like 1d12h
+fn relative_seconds(spec: &Value) -> Result<i64> {
+    let Some(spec) = spec.as_str() else {
+        return Err(Error::new("relative time should be a string like 7d or 1d12h"));
+    };
+    let mut total = 0i64;
+    let mut number = String::new();
+    if spec.trim().is_empty() {
+        return Err(Error::new("relative time is empty"));
+    }
+    for ch in spec.trim().chars() {
+        if ch.is_ascii_digit() {
+            number.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(Error::new(format!("unknown unit '{}' in relative time '{}'", ch, spec))),
+        };
+        if number.is_empty() {
+            return Err(Error::new(format!("missing amount before '{}' in relative time '{}'", ch, spec)));
+        }
+        let seconds = number.parse::<i64>().ok().map(|n| n.checked_mul(unit)).flatten();
+        total = match seconds.map(|s| total.checked_add(s)).flatten() {
+            Some(total) => total,
+            None => return Err(Error::new(format!("relative time '{}' is too large", spec))),
+        };
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(Error::new(format!("missing unit after '{}' in relative time '{}'", number, spec)));
+    }
+    Ok(total)
+}
+
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    if prefix.is_empty() || prefix.contains('%') || prefix.contains('_') {
+        return None;
+    }
+    let mut chars: Vec<char> = prefix.chars().collect();
+    let last = chars.pop().unwrap();
+    chars.push(char::from_u32(last as u32 + 1)?);
+    Some(chars.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_runtime::teon;
+    use super::*;
+
+    #[test]
+    fn unknown_filter_is_an_error() {
+        let value = teon!({"almost": 1});
+        assert!(Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::PostgreSQL).is_err());
+    }
+
+    #[test]
+    fn known_filters_render() {
+        let value = teon!({"gte": 1, "lt": 5});
+        let sql = Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::MySQL).unwrap();
+        assert_eq!(sql, "(`age` >= 1 AND `age` < 5)");
+    }
+
+    #[test]
+    fn filters_bind_with_the_field_type() {
+        let value = teon!({"gte": 1, "lt": 5});
+        let sql = Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::PostgreSQL).unwrap();
+        let (sql, params) = bind_params(&sql, SQLDialect::PostgreSQL);
+        if cfg!(feature = "parameterized") {
+            assert_eq!(sql, "(\"age\" >= $1 AND \"age\" < $2)");
+            assert_eq!(params, vec![QuaintValue::int64(1), QuaintValue::int64(5)]);
+        } else {
+            assert_eq!(sql, "(\"age\" >= 1 AND \"age\" < 5)");
+            assert!(params.is_empty());
+        }
+    }
+
+    #[test]
+    fn on_date_requires_a_date_field() {
+        let value = teon!({"onDate": "2024-01-01"});
+        assert!(Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).is_err());
+        assert!(Query::where_entry_item("at", &Type::DateTime, false, &value, SQLDialect::PostgreSQL).is_err());
+    }
+
+    #[test]
+    fn on_date_truncates_per_dialect() {
+        let value = Value::Dictionary(indexmap!{"onDate".to_owned() => Value::Date(chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap())});
+        let pg = Query::where_entry_item("at", &Type::Date, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(pg, "(date_trunc('day', \"at\") = '2024-01-02'::date)");
+        let mysql = Query::where_entry_item("at", &Type::Date, false, &value, SQLDialect::MySQL).unwrap();
+        assert_eq!(mysql, "(DATE(`at`) = '2024-01-02')");
+    }
+
+    #[test]
+    fn relative_seconds_parses_combined_units() {
+        assert_eq!(relative_seconds(&teon!("30s")).unwrap(), 30);
+        assert_eq!(relative_seconds(&teon!("1d12h")).unwrap(), 129600);
+        assert_eq!(relative_seconds(&teon!("2w")).unwrap(), 1209600);
+    }
+
+    #[test]
+    fn malformed_relative_seconds_are_errors() {
+        for spec in ["", "7", "d", "7y", "1d 2h", "99999999999999999999s"] {
+            assert!(relative_seconds(&teon!(spec)).is_err(), "{}", spec);
+        }
+        assert!(relative_seconds(&teon!(7)).is_err());
+        let value = teon!({"gt": {"_now_minus": "7x"}});
+        assert!(Query::where_entry_item("at", &Type::DateTime, false, &value, SQLDialect::PostgreSQL).is_err());
+    }
+
+    #[test]
+    fn relative_time_uses_the_database_clock() {
+        let value = teon!({"gt": {"_now_minus": "1h"}});
+        let sql = Query::where_entry_item("at", &Type::DateTime, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "(\"at\" > (NOW() - INTERVAL '3600 seconds'))");
+    }
+
+    #[test]
+    fn keyset_condition_compares_qualified_row_values() {
+        let keys = vec![
+            ("\"t\".\"a\"".to_owned(), false, false, false, Some("1".to_owned())),
+            ("\"t\".\"id\"".to_owned(), false, false, false, Some("2".to_owned())),
+        ];
+        assert_eq!(Query::keyset_condition(&keys, SQLDialect::PostgreSQL), "(\"t\".\"a\",\"t\".\"id\") > (1,2)");
+        // no row values on sql server
+        let mssql_keys = vec![
+            ("[t].[a]".to_owned(), false, false, false, Some("1".to_owned())),
+            ("[t].[id]".to_owned(), true, false, false, Some("2".to_owned())),
+        ];
+        assert_eq!(Query::keyset_condition(&mssql_keys, SQLDialect::MSSQL), "(([t].[a] > 1) OR ([t].[a] = 1 AND [t].[id] < 2))");
+    }
+
+    #[test]
+    fn cursor_pages_by_a_nullable_column() {
+        // ascending on postgres puts the null scores last
+        let keys = vec![
+            ("\"t\".\"score\"".to_owned(), false, false, true, Some("\"c.score\"".to_owned())),
+            ("\"t\".\"id\"".to_owned(), false, false, false, Some("\"c.id\"".to_owned())),
+        ];
+        assert_eq!(Query::cursor_condition(&keys, SQLDialect::PostgreSQL), "(((\"t\".\"score\" > \"c.score\" OR (\"t\".\"score\" IS NULL AND \"c.score\" IS NOT NULL))) OR ((\"t\".\"score\" = \"c.score\" OR (\"t\".\"score\" IS NULL AND \"c.score\" IS NULL)) AND \"t\".\"id\" > \"c.id\") OR ((\"t\".\"score\" = \"c.score\" OR (\"t\".\"score\" IS NULL AND \"c.score\" IS NULL)) AND \"t\".\"id\" = \"c.id\"))");
+        // with the nulls first a null cursor is followed by every scored row
+        let keys = vec![
+            ("`t`.`score`".to_owned(), false, true, true, Some("`c.score`".to_owned())),
+        ];
+        let sql = Query::cursor_condition(&keys, SQLDialect::MySQL);
+        assert!(sql.contains("(`c.score` IS NULL AND `t`.`score` IS NOT NULL)"));
+        assert!(sql.contains("(`t`.`score` = `c.score` OR (`t`.`score` IS NULL AND `c.score` IS NULL))"));
+    }
+
+    #[test]
+    fn distinct_keeps_a_real_row_per_group() {
+        let pg = Query::first_row_per_group("\"a\"", "\"id\"", "SELECT * from \"t\"", SQLDialect::PostgreSQL);
+        assert_eq!(pg, "SELECT DISTINCT ON (\"a\") * FROM (SELECT * from \"t\") AS _d ORDER BY \"a\",\"id\"");
+        let mysql = Query::first_row_per_group("`a`", "`id`", "SELECT * from `t`", SQLDialect::MySQL);
+        assert_eq!(mysql, "SELECT * FROM (SELECT _s.*, ROW_NUMBER() OVER (PARTITION BY `a` ORDER BY `id`) AS `_row_number` FROM (SELECT * from `t`) AS _s) AS _d WHERE `_row_number` = 1");
+        assert!(!mysql.contains("MIN("));
+    }
+
+    #[test]
+    fn invalid_collation_is_an_error() {
+        let value = teon!({"gt": "a", "collation": "C\" OR 1=1 --"});
+        assert!(Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).is_err());
+        let value = teon!({"gt": "a", "collation": "C"});
+        let sql = Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "(\"name\" COLLATE \"C\" > 'a')");
+    }
+
+    #[test]
+    fn non_finite_filters_are_errors_outside_postgres() {
+        let value = Value::Dictionary(indexmap!{"gt".to_owned() => Value::Float(f64::NAN)});
+        for dialect in [SQLDialect::MySQL, SQLDialect::SQLite] {
+            assert!(Query::where_entry_item("score", &Type::Float, false, &value, dialect).is_err());
+        }
+        let sql = Query::where_entry_item("score", &Type::Float, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "(\"score\" > 'NaN'::float8)");
+    }
+
+    #[test]
+    fn overlaps_is_postgres_only() {
+        let value = teon!({"overlaps": 1});
+        for dialect in [SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL] {
+            assert!(Query::where_entry_item("during", &Type::Int64, false, &value, dialect).is_err());
+        }
+    }
+
+    #[test]
+    fn json_path_rejects_bad_operators_and_values() {
+        let unknown = teon!({"path": ["a"], "has": 1});
+        assert!(Query::where_entry_item("data", &Type::String, false, &unknown, SQLDialect::PostgreSQL).is_err());
+        let nested = teon!({"path": ["a"], "equals": {"b": 1}});
+        assert!(Query::where_entry_item("data", &Type::String, false, &nested, SQLDialect::MySQL).is_err());
+        let mixed = teon!({"path": ["a"], "in": [1, "b"]});
+        assert!(Query::where_entry_item("data", &Type::String, false, &mixed, SQLDialect::SQLite).is_err());
+    }
+
+    #[test]
+    fn json_path_empty_in_matches_nothing() {
+        let value = teon!({"path": ["a"], "in": []});
+        let sql = Query::where_entry_item("data", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "(1 = 0)");
+        let value = teon!({"path": ["a"], "notIn": []});
+        let sql = Query::where_entry_item("data", &Type::String, false, &value, SQLDialect::SQLite).unwrap();
+        assert_eq!(sql, "(1 = 1)");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn json_path_extracts_per_dialect() {
+        let value = teon!({"path": ["a", 0], "equals": 1});
+        let pg = Query::where_entry_item("data", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(pg, "((\"data\" -> 'a' ->> 0)::bigint = 1)");
+        let mysql = Query::where_entry_item("data", &Type::String, false, &value, SQLDialect::MySQL).unwrap();
+        assert_eq!(mysql, "(CAST(JSON_EXTRACT(`data`, '$.\"a\"[0]') AS SIGNED) = 1)");
+    }
+
+    #[test]
+    fn in_filters_handle_nulls_and_empty_lists() {
+        let value = teon!({"in": [1, null, 2]});
+        let sql = Query::where_entry_item("age", &Type::Int64, true, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "((\"age\" IN (1, 2) OR \"age\" IS NULL))");
+        let value = teon!({"in": []});
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::PostgreSQL).unwrap(), "(FALSE)");
+        let value = teon!({"notIn": []});
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::PostgreSQL).unwrap(), "(TRUE)");
+    }
+
+    #[test]
+    fn not_keeps_null_rows_of_optional_fields() {
+        let value = teon!({"not": 1});
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, true, &value, SQLDialect::PostgreSQL).unwrap(), "(\"age\" IS DISTINCT FROM 1)");
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, true, &value, SQLDialect::MySQL).unwrap(), "(NOT (`age` <=> 1))");
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, false, &value, SQLDialect::PostgreSQL).unwrap(), "(\"age\" <> 1)");
+        let value = teon!({"equals": null});
+        assert_eq!(Query::where_entry_item("age", &Type::Int64, true, &value, SQLDialect::PostgreSQL).unwrap(), "(\"age\" IS NULL)");
+    }
+
+    #[test]
+    fn between_and_contains_check_their_operands() {
+        let value = teon!({"between": 1});
+        assert!(Query::where_entry_item("age", &Type::Int, false, &value, SQLDialect::PostgreSQL).is_err());
+        // contains on a scalar column isn't a range lookup
+        let value = teon!({"contains": 1});
+        assert!(Query::where_entry_item("age", &Type::Int, false, &value, SQLDialect::PostgreSQL).is_err());
+        assert!(Query::where_entry_item("age", &Type::Int, false, &value, SQLDialect::MySQL).is_err());
+    }
+
+    #[test]
+    fn prefix_range_compares_bounds() {
+        let value = teon!({"startsWith": "abc", "prefixRange": true});
+        let sql = Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(sql, "(\"name\" >= 'abc' AND \"name\" < 'abd')");
+        assert_eq!(prefix_upper_bound(""), None);
+    }
+
+    #[test]
+    fn nulls_placement_follows_the_read_direction() {
+        assert_eq!(Query::order_by_nulls("\"a\"", false, Some("first"), SQLDialect::PostgreSQL, false).unwrap(), "\"a\" ASC NULLS FIRST");
+        assert_eq!(Query::order_by_nulls("\"a\"", false, Some("first"), SQLDialect::PostgreSQL, true).unwrap(), "\"a\" DESC NULLS LAST");
+        assert_eq!(Query::order_by_nulls("`a`", true, Some("last"), SQLDialect::MySQL, false).unwrap(), "CASE WHEN `a` IS NULL THEN 1 ELSE 0 END,`a` DESC");
+        assert_eq!(Query::order_by_nulls("`a`", true, None, SQLDialect::MySQL, false).unwrap(), "`a` DESC");
+        assert!(Query::order_by_nulls("`a`", true, Some("middle"), SQLDialect::MySQL, false).is_err());
+    }
+
+    #[test]
+    fn relation_filters_are_correlated_exists() {
+        let from = "\"posts\" AS \"j\"";
+        let correlation = || vec!["\"j\".\"authorId\" = \"users\".\"id\"".to_owned()];
+        let filter = "\"j\".\"draft\" = true".to_owned();
+        assert_eq!(Query::relation_exists("some", from, correlation(), filter.clone(), SQLDialect::PostgreSQL).unwrap(), "EXISTS (SELECT 1 FROM \"posts\" AS \"j\" WHERE \"j\".\"authorId\" = \"users\".\"id\" AND \"j\".\"draft\" = true)");
+        assert_eq!(Query::relation_exists("every", from, correlation(), filter.clone(), SQLDialect::PostgreSQL).unwrap(), "NOT EXISTS (SELECT 1 FROM \"posts\" AS \"j\" WHERE \"j\".\"authorId\" = \"users\".\"id\" AND (NOT (\"j\".\"draft\" = true)))");
+        assert_eq!(Query::relation_exists("every", from, correlation(), "".to_owned(), SQLDialect::PostgreSQL), None);
+        // a null foreign key can't empty the result the way NOT IN (..., NULL) does
+        let none = Query::relation_exists("none", from, correlation(), "".to_owned(), SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(none, "NOT EXISTS (SELECT 1 FROM \"posts\" AS \"j\" WHERE \"j\".\"authorId\" = \"users\".\"id\")");
+        assert!(!none.contains(" IN "));
+        assert!(Query::relation_exists("isNot", from, correlation(), filter, SQLDialect::PostgreSQL).unwrap().starts_with("NOT EXISTS "));
+    }
+
+    #[test]
+    fn extreme_paging_values_are_clamped() {
+        assert_eq!(Query::paging(None, None, None, Some(i64::MIN), SQLDialect::PostgreSQL), Some((Some(i64::MAX as u64), 0)));
+        assert_eq!(Query::paging(None, None, None, Some(i64::MIN), SQLDialect::MySQL), Some((Some(1 << 63), 0)));
+        assert_eq!(Query::paging(None, None, Some(i64::MIN), Some(-3), SQLDialect::SQLite), Some((Some(3), 0)));
+        assert_eq!(Query::paging(Some(i64::MAX), Some(i64::MAX), None, None, SQLDialect::PostgreSQL), Some((Some(i64::MAX as u64), i64::MAX as u64)));
+        assert_eq!(Query::paging(Some(10), Some(i64::MIN), None, None, SQLDialect::PostgreSQL), Some((Some(10), 0)));
+        assert_eq!(Query::paging(None, None, Some(5), None, SQLDialect::PostgreSQL), Some((None, 5)));
+        assert_eq!(Query::paging(None, None, None, None, SQLDialect::PostgreSQL), None);
+    }
+
+    #[test]
+    fn groups_order_by_aggregates_and_grouped_columns() {
+        let column = |key: &str| ["id", "name"].contains(&key).then(|| key.to_owned());
+        let order_by = teon!([{"_count": {"_all": "desc"}}, {"_count": {"id": "asc"}}, {"name": "asc"}]);
+        assert_eq!(Query::group_by_order_terms(&order_by, column, SQLDialect::PostgreSQL).unwrap(), "COUNT(*) DESC,COUNT(\"id\") ASC,\"name\" ASC");
+        assert!(Query::group_by_order_terms(&teon!([{"age": "asc"}]), column, SQLDialect::PostgreSQL).is_err());
+        assert!(Query::group_by_order_terms(&teon!([{"_count": {"age": "asc"}}]), column, SQLDialect::PostgreSQL).is_err());
+        assert!(Query::group_by_order_terms(&teon!([{"name": "up"}]), column, SQLDialect::PostgreSQL).is_err());
+    }
+
+    #[test]
+    fn like_filters_escape_wildcards() {
+        let value = teon!({"contains": "50%"});
+        let pg = Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(pg, "(\"name\" LIKE '%50\\%%' ESCAPE '\\')");
+        let mysql = Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::MySQL).unwrap();
+        assert_eq!(mysql, "(`name` LIKE '%50\\\\%%' ESCAPE '\\\\')");
+        let value = teon!({"iContains": "a"});
+        let pg = Query::where_entry_item("name", &Type::String, false, &value, SQLDialect::PostgreSQL).unwrap();
+        assert_eq!(pg, "(\"name\" ILIKE '%a%' ESCAPE '\\')");
+    }
+}
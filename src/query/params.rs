@@ -0,0 +1,217 @@
+use quaint_forked::Value as QuaintValue;
+use quaint_forked::ast::Query as QuaintQuery;
+use quaint_forked::prelude::{Queryable, ResultSet};
+use chrono::{DateTime, NaiveDate, Utc};
+use teo_parser::r#type::Type;
+use teo_runtime::value::Value;
+use crate::schema::dialect::SQLDialect;
+use teo_result::Result;
+use crate::schema::value::encode::{check_finite, ToLike, ToLikeInput, ToSQLInputDialect, ValueToSQLString};
+
+// a bound value travels inside the generated sql as a marker until the statement is
+// complete, so fragments can be built in any order and reused. `bind_params` turns
+// the markers into the dialect's placeholders, numbered in the order they appear.
+// marker: MARK, a type tag, the payload length in chars, ':' and the payload.
+// literals write a MARK of their own text as ESCAPED_MARK, payloads are never escaped
+pub(crate) const MARK: char = '\u{1}';
+pub(crate) const ESCAPED_MARK: &str = "\u{1}\u{1}";
+
+fn marker(tag: char, payload: &str) -> String {
+    format!("{MARK}{tag}{}:{payload}", payload.chars().count())
+}
+
+// the value as a placeholder when binding is enabled, otherwise as a literal.
+// nulls, ranges, arrays and decimals stay literals, they are no user text
+pub(crate) fn bind(value: &Value, r#type: &Type, optional: bool, dialect: SQLDialect) -> Result<String> {
+    check_finite(value, dialect)?;
+    match bound(value, r#type, dialect) {
+        Some(marker) => Ok(marker),
+        None => value.to_sql_string(r#type, optional, dialect),
+    }
+}
+
+// none when the value is written as a literal
+pub(crate) fn bound(value: &Value, r#type: &Type, dialect: SQLDialect) -> Option<String> {
+    if cfg!(feature = "parameterized") {
+        bound_marker(value, r#type.unwrap_optional(), dialect)
+    } else {
+        None
+    }
+}
+
+// text which isn't a field value, e.g. like patterns and json path keys
+pub(crate) fn bind_text(text: &str, dialect: SQLDialect) -> String {
+    if cfg!(feature = "parameterized") {
+        marker('t', text)
+    } else {
+        text.to_sql_input(dialect)
+    }
+}
+
+// like patterns escape their wildcards with a backslash, see `like_escape`
+pub(crate) fn bind_like(term: &str, left: bool, right: bool, dialect: SQLDialect) -> String {
+    if !cfg!(feature = "parameterized") {
+        return term.to_like_input(dialect).to_like(left, right);
+    }
+    let mut pattern = String::with_capacity(term.len() + 2);
+    if left {
+        pattern.push('%');
+    }
+    for ch in term.chars() {
+        if ch == '\\' || ch == '%' || ch == '_' {
+            pattern.push('\\');
+        }
+        pattern.push(ch);
+    }
+    if right {
+        pattern.push('%');
+    }
+    marker('t', &pattern)
+}
+
+// sqlite keeps dates as the text the literals write, they're bound as that text
+fn bound_marker(value: &Value, r#type: &Type, dialect: SQLDialect) -> Option<String> {
+    Some(match (r#type, value) {
+        (Type::Int, Value::Int(i)) => marker('i', &i.to_string()),
+        (Type::Int, Value::Int64(i)) if i32::try_from(*i).is_ok() => marker('i', &i.to_string()),
+        (Type::Int64, Value::Int(i)) => marker('l', &i.to_string()),
+        (Type::Int64, Value::Int64(i)) => marker('l', &i.to_string()),
+        (Type::Float32, Value::Float32(f)) => marker('f', &f.to_string()),
+        (Type::Float32, Value::Float(f)) => marker('f', &(*f as f32).to_string()),
+        (Type::Float, Value::Float(f)) => marker('d', &f.to_string()),
+        (Type::Float, Value::Float32(f)) => marker('d', &(*f as f64).to_string()),
+        (Type::Bool, Value::Bool(b)) => marker('b', &b.to_string()),
+        (Type::String | Type::EnumVariant(_), Value::String(s)) => marker('t', s),
+        (Type::Date, Value::Date(d)) if dialect.is_sqlite() => marker('t', &d.to_string()),
+        (Type::Date, Value::Date(d)) => marker('D', &d.format("%Y-%m-%d").to_string()),
+        (Type::DateTime, Value::DateTime(d)) if dialect.is_sqlite() => marker('t', &d.to_string()),
+        (Type::DateTime, Value::DateTime(d)) => marker('T', &d.to_rfc3339()),
+        _ => return None,
+    })
+}
+
+fn quaint_value(tag: char, payload: String) -> QuaintValue<'static> {
+    match tag {
+        'i' => QuaintValue::int32(payload.parse::<i32>().unwrap()),
+        'l' => QuaintValue::int64(payload.parse::<i64>().unwrap()),
+        'f' => QuaintValue::float(payload.parse::<f32>().unwrap()),
+        'd' => QuaintValue::double(payload.parse::<f64>().unwrap()),
+        'b' => QuaintValue::boolean(payload == "true"),
+        'D' => QuaintValue::date(NaiveDate::parse_from_str(&payload, "%Y-%m-%d").unwrap()),
+        'T' => QuaintValue::datetime(DateTime::parse_from_rfc3339(&payload).unwrap().with_timezone(&Utc)),
+        _ => QuaintValue::text(payload),
+    }
+}
+
+fn placeholder(index: usize, dialect: SQLDialect) -> String {
+    match dialect {
+        SQLDialect::PostgreSQL => format!("${index}"),
+        SQLDialect::MSSQL => format!("@P{index}"),
+        _ => "?".to_owned(),
+    }
+}
+
+// a fragment used twice binds its values twice
+pub(crate) fn bind_params(sql: &str, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+    let mut result = String::with_capacity(sql.len());
+    let mut params: Vec<QuaintValue<'static>> = vec![];
+    let mut chars = sql.chars();
+    while let Some(ch) = chars.next() {
+        if ch != MARK {
+            result.push(ch);
+            continue;
+        }
+        let tag = chars.next().unwrap_or(MARK);
+        if tag == MARK {
+            result.push(MARK);
+            continue;
+        }
+        let len: usize = chars.by_ref().take_while(|c| *c != ':').collect::<String>().parse().unwrap_or(0);
+        let payload: String = chars.by_ref().take(len).collect();
+        params.push(quaint_value(tag, payload));
+        result.push_str(&placeholder(params.len(), dialect));
+    }
+    (result, params)
+}
+
+// generated statements which never go through `bind_params`, e.g. migrations
+pub(crate) fn unescape_marks(sql: &str) -> String {
+    sql.replace(ESCAPED_MARK, &MARK.to_string())
+}
+
+// statements without bound values go through the text protocol as before
+pub(crate) async fn query_bound(conn: &dyn Queryable, sql: &str, params: &[QuaintValue<'static>]) -> quaint_forked::Result<ResultSet> {
+    if params.is_empty() {
+        conn.query(QuaintQuery::from(sql.to_owned())).await
+    } else {
+        conn.query_raw(sql, params).await
+    }
+}
+
+pub(crate) async fn execute_bound(conn: &dyn Queryable, sql: &str, params: &[QuaintValue<'static>]) -> quaint_forked::Result<u64> {
+    if params.is_empty() {
+        conn.execute(QuaintQuery::from(sql.to_owned())).await
+    } else {
+        conn.execute_raw(sql, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_become_numbered_placeholders() {
+        let sql = format!("SELECT * FROM \"users\" WHERE \"id\" = {} AND \"name\" = {}", marker('l', "7"), marker('t', "o'brien"));
+        let (sql, params) = bind_params(&sql, SQLDialect::PostgreSQL);
+        assert_eq!(sql, "SELECT * FROM \"users\" WHERE \"id\" = $1 AND \"name\" = $2");
+        assert_eq!(params, vec![QuaintValue::int64(7), QuaintValue::text("o'brien")]);
+    }
+
+    #[test]
+    fn placeholders_follow_the_dialect() {
+        let sql = format!("{} {}", marker('i', "1"), marker('b', "true"));
+        assert_eq!(bind_params(&sql, SQLDialect::MySQL).0, "? ?");
+        assert_eq!(bind_params(&sql, SQLDialect::SQLite).0, "? ?");
+        assert_eq!(bind_params(&sql, SQLDialect::MSSQL).0, "@P1 @P2");
+    }
+
+    #[test]
+    fn payloads_may_contain_anything() {
+        let text = "a:b 3:c é\n";
+        let (sql, params) = bind_params(&format!("x = {}", marker('t', text)), SQLDialect::MySQL);
+        assert_eq!(sql, "x = ?");
+        assert_eq!(params, vec![QuaintValue::text(text)]);
+    }
+
+    #[test]
+    fn literal_text_keeps_its_marks() {
+        let literal = "a\u{1}t1:b".to_sql_input(SQLDialect::PostgreSQL);
+        let (sql, params) = bind_params(&format!("x = {literal} AND y = {}", marker('t', "c")), SQLDialect::PostgreSQL);
+        assert_eq!(sql, "x = 'a\u{1}t1:b' AND y = $1");
+        assert_eq!(params, vec![QuaintValue::text("c")]);
+        assert_eq!(unescape_marks(&literal), "'a\u{1}t1:b'");
+        let (sql, params) = bind_params(&Value::Array(vec![Value::String("\u{1}".to_owned())]).to_sql_string_array_arg(&Type::Array(Box::new(Type::String)), false, SQLDialect::PostgreSQL), SQLDialect::PostgreSQL);
+        assert_eq!(sql, "'{\"\u{1}\"}'");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn reused_fragments_bind_again() {
+        let fragment = marker('t', "a");
+        let (_, params) = bind_params(&format!("{fragment} UNION {fragment}"), SQLDialect::PostgreSQL);
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn values_keep_their_types() {
+        assert_eq!(bound_marker(&Value::Int(3), &Type::Int64, SQLDialect::PostgreSQL), Some(marker('l', "3")));
+        assert_eq!(bound_marker(&Value::Bool(true), &Type::Bool, SQLDialect::MySQL), Some(marker('b', "true")));
+        let date = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(bound_marker(&Value::Date(date), &Type::Date, SQLDialect::PostgreSQL), Some(marker('D', "2024-02-29")));
+        assert_eq!(bound_marker(&Value::Date(date), &Type::Date, SQLDialect::SQLite), Some(marker('t', "2024-02-29")));
+        assert_eq!(bound_marker(&Value::Null, &Type::String, SQLDialect::PostgreSQL), None);
+        let (_, params) = bind_params(&marker('D', "2024-02-29"), SQLDialect::PostgreSQL);
+        assert_eq!(params, vec![QuaintValue::date(date)]);
+    }
+}
@@ -4,6 +4,7 @@ use itertools::Itertools;
 use maplit::{hashset};
 use quaint_forked::pooled::PooledConnection;
 use quaint_forked::prelude::{Query, Queryable, ResultRow, ResultSet};
+use crate::migration::plan::SQLMigrationOperation;
 use crate::migration::sql::psql_is_auto_increment;
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
@@ -14,6 +15,7 @@ use teo_runtime::model::field::indexable::Indexable;
 use teo_runtime::model::field::is_optional::IsOptional;
 use teo_runtime::traits::named::Named;
 use teo_runtime::value::Value;
+use teo_result::{Error, Result};
 
 #[derive(Debug)]
 pub(crate) enum ColumnManipulation<'a> {
@@ -47,6 +49,18 @@ impl<'a> ColumnManipulation<'a> {
         }
     }
 
+    pub(crate) fn to_operation(&self, table: &str) -> SQLMigrationOperation {
+        let table = table.to_owned();
+        match self {
+            ColumnManipulation::AddColumn(c, _) => SQLMigrationOperation::AddColumn { table, column: (*c).clone() },
+            ColumnManipulation::RemoveColumn(c) => SQLMigrationOperation::RemoveColumn { table, column: c.clone() },
+            ColumnManipulation::RenameColumn { old, new } => SQLMigrationOperation::RenameColumn { table, old: old.clone(), new: new.clone() },
+            ColumnManipulation::AlterColumn(old, new) => SQLMigrationOperation::AlterColumn { table, old: (*old).clone(), new: (*new).clone() },
+            ColumnManipulation::CreateIndex(index) => SQLMigrationOperation::CreateIndex { table, index: index.name().to_owned() },
+            ColumnManipulation::DropIndex(index) => SQLMigrationOperation::DropIndex { table, index: index.name().to_owned() },
+        }
+    }
+
     pub(crate) fn is_add_column_non_null(&self) -> bool {
         match self {
             ColumnManipulation::AddColumn(c, _) => c.not_null,
@@ -59,7 +73,7 @@ pub(crate) struct ColumnDecoder { }
 
 impl ColumnDecoder {
 
-    pub(crate) fn manipulations<'a>(db_columns: &'a HashSet<SQLColumn>, model_columns: &'a HashSet<SQLColumn>, db_indices: &'a HashSet<Index>, model_indices: &'a HashSet<Index>, model: &Model) -> Vec<ColumnManipulation<'a>> {
+    pub(crate) fn manipulations<'a>(db_columns: &'a HashSet<SQLColumn>, model_columns: &'a HashSet<SQLColumn>, db_indices: &'a HashSet<Index>, model_indices: &'a HashSet<Index>, index_options_changed: &HashSet<String>, model: &Model) -> Vec<ColumnManipulation<'a>> {
         let mut to_create: Vec<&Index> = vec![];
         let mut to_drop: Vec<&Index> = vec![];
         for index in db_indices {
@@ -83,7 +97,7 @@ impl ColumnDecoder {
                 let index = to_add.iter().position(|x| x.name() == c.name()).unwrap();
                 to_add.remove(index);
                 // maybe alter
-                if c != dc {
+                if !c.same_definition(dc) {
                     to_alter.push(dc);
                 }
             } else {
@@ -121,6 +135,15 @@ impl ColumnDecoder {
         for i in to_drop {
             result.push(ColumnManipulation::DropIndex(i));
         }
+        // same columns but different index options, recreate after dropping
+        for i in model_indices {
+            if index_options_changed.contains(i.name()) {
+                if let Some(db_index) = db_indices.get(i) {
+                    result.push(ColumnManipulation::DropIndex(db_index));
+                    result.push(ColumnManipulation::CreateIndex(i));
+                }
+            }
+        }
         for c in to_remove {
             result.push(ColumnManipulation::RemoveColumn(c.name().to_owned()));
         }
@@ -138,10 +161,12 @@ impl ColumnDecoder {
         result
     }
 
+    // columns are paired by name, their position in the table never counts as a
+    // change so sqlite isn't asked to rebuild a table that only differs in order
     pub(crate) fn need_to_alter_any_columns(db: &HashSet<SQLColumn>, def: &HashSet<SQLColumn>) -> bool {
         for column in db {
-            if let Some(def_column) = def.iter().find(|c| { &c.name == &column.name}) {
-                if def_column != column {
+            if let Some(def_column) = def.iter().find(|c| c.name == column.name) {
+                if !column.same_definition(def_column) {
                     return true;
                 }
             }
@@ -163,7 +188,7 @@ impl ColumnDecoder {
         result
     }
 
-    pub(crate) fn decode_sqlite_columns(columns: ResultSet, indices: ResultSet, auto_increment: ResultSet) -> HashSet<SQLColumn> {
+    pub(crate) fn decode_sqlite_columns(columns: ResultSet, indices: ResultSet, auto_increment: ResultSet) -> Result<HashSet<SQLColumn>> {
         let columns_iter: Vec<ResultRow> = columns.into_iter().collect();
         let indices_iter: Vec<ResultRow> = indices.into_iter().collect();
         let primary_is_single = columns_iter.iter().filter(|r| {
@@ -174,6 +199,7 @@ impl ColumnDecoder {
             let name = column.get("name").unwrap().as_str().unwrap();
             let r#type = column.get("type").unwrap().as_str().unwrap();
             let not_null = column.get("notnull").unwrap().as_bool().unwrap();
+            let default = column.get("dflt_value").map(|v| v.to_string()).flatten();
             let pk = if primary_is_single {
                 column.get("pk").unwrap().as_bool().unwrap_or(false)
             } else {
@@ -189,14 +215,15 @@ impl ColumnDecoder {
             };
             result.insert(SQLColumn {
                 name: name.to_string(),
-                r#type: SQLTypeDecoder::decode(&r#type, SQLDialect::SQLite),
+                r#type: SQLTypeDecoder::decode(&r#type, SQLDialect::SQLite)?,
                 not_null,
                 auto_increment: pk && !auto_increment.is_empty(),
-                default: None,
+                default,
                 primary_key: pk,
+                collation: None,
             });
         }
-        result
+        Ok(result)
     }
 
     async fn psql_primary_field_name(conn: &dyn Queryable, table_name: &str) -> Vec<String> {
@@ -228,45 +255,68 @@ AND    i.indisprimary", table_name);
         !conn.query(Query::from(psql_is_auto_increment(table_name, column_name))).await.unwrap().is_empty()
     }
 
-    pub(crate) async fn decode(row: ResultRow, dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> SQLColumn {
+    pub(crate) async fn decode(row: ResultRow, dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<SQLColumn> {
         if dialect == SQLDialect::MySQL {
             let field: String = row.get("Field").unwrap().to_string().unwrap();
             let field_type_in_string: String = row.get("Type").unwrap().to_string().unwrap();
             let null_in_string: String = row.get("Null").unwrap().to_string().unwrap();
-            let null = &null_in_string == "YES";
+            let null = null_in_string.trim().eq_ignore_ascii_case("YES");
             let key: String = row.get("Key").unwrap().to_string().unwrap();
-            let extra: String = row.get("Extra").unwrap().to_string().unwrap();
+            let extra: String = row.get("Extra").unwrap().to_string().unwrap().to_lowercase();
             let auto_increment = extra.contains("auto_increment");
             let primary = &key == "PRI";
-            SQLColumn {
+            // generated columns report their expression, it isn't a default
+            let generated = extra.contains("virtual generated") || extra.contains("stored generated");
+            let default = if generated { None } else { row.get("Default").map(|v| v.to_string()).flatten() };
+            Ok(SQLColumn {
                 name: field,
-                r#type: SQLTypeDecoder::decode(&field_type_in_string, dialect),
+                r#type: SQLTypeDecoder::decode(&field_type_in_string, dialect)?,
                 not_null: !null,
                 auto_increment,
-                default: None,
+                default,
                 primary_key: primary,
-            }
+                collation: None,
+            })
         } else if dialect == SQLDialect::PostgreSQL { // postgres
             let primary_names = Self::psql_primary_field_name(conn, table_name).await;
             let column_name: String = row.get("column_name").unwrap().to_string().unwrap();
             let nullable_text: String = row.get("is_nullable").unwrap().to_string().unwrap();
-            let nullable: bool = nullable_text == "YES";
+            let nullable: bool = nullable_text.trim().eq_ignore_ascii_case("YES");
             let mut data_type: String = row.get("data_type").unwrap().to_string().unwrap();
             let mut udt_name: String = row.get("udt_name").unwrap().to_string().unwrap();
             if data_type.as_str() == "ARRAY" {
                 udt_name.remove(0);
                 data_type = data_type + "|" + udt_name.as_str()
+            } else if ["character varying", "character", "bit"].contains(&data_type.as_str()) {
+                // information_schema keeps the length apart from the type name
+                let len = row.get("character_maximum_length").map(|v| v.as_i64().or(v.as_i32().map(|i| i as i64))).flatten();
+                if let Some(len) = len {
+                    data_type = format!("{}({})", data_type, len);
+                }
+            } else if data_type.as_str() == "numeric" {
+                let precision = row.get("numeric_precision").map(|v| v.as_i64().or(v.as_i32().map(|i| i as i64))).flatten();
+                let scale = row.get("numeric_scale").map(|v| v.as_i64().or(v.as_i32().map(|i| i as i64))).flatten();
+                if let (Some(precision), Some(scale)) = (precision, scale) {
+                    data_type = format!("numeric({},{})", precision, scale);
+                }
             }
-            SQLColumn {
+            let default = row.get("column_default").map(|v| v.to_string()).flatten();
+            // serial columns default to their sequence, identity columns have no default at all
+            let serial = default.as_ref().map(|d| d.starts_with("nextval(")).unwrap_or(false);
+            let identity = row.get("is_identity").map(|v| v.to_string()).flatten().map(|v| v.eq_ignore_ascii_case("YES")).unwrap_or(false);
+            let generated = row.get("is_generated").map(|v| v.to_string()).flatten().map(|v| v.eq_ignore_ascii_case("ALWAYS")).unwrap_or(false);
+            let auto_increment = serial || identity || Self::psql_is_auto_increment(conn, table_name, &column_name).await;
+            Ok(SQLColumn {
                 name: column_name.clone(),
-                r#type: SQLTypeDecoder::decode(&data_type, dialect),
+                r#type: SQLTypeDecoder::decode(&data_type, dialect)?,
                 not_null: !nullable,
-                default: None,
+                default: if serial || generated { None } else { default },
                 primary_key: primary_names.contains(&column_name),
-                auto_increment: Self::psql_is_auto_increment(conn, table_name, &column_name).await,
-            }
+                auto_increment,
+                collation: None,
+            })
         } else {
-            unreachable!()
+            Err(Error::new("cannot describe SQL Server columns yet"))
         }
     }
 }
@@ -294,3 +344,36 @@ impl From<&Arc<Property>> for SQLColumn {
         SQLColumn::from(property.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quaint_forked::Value as QuaintValue;
+
+    fn table_info(columns: &[(&str, &str, i64, i64)]) -> ResultSet {
+        let names = vec!["name", "type", "notnull", "dflt_value", "pk"].into_iter().map(|n| n.to_owned()).collect();
+        let rows = columns.iter().map(|(name, r#type, not_null, pk)| {
+            vec![QuaintValue::text(*name), QuaintValue::text(*r#type), QuaintValue::int64(*not_null), QuaintValue::Text(None), QuaintValue::int64(*pk)]
+        }).collect();
+        ResultSet::new(names, rows)
+    }
+
+    fn empty() -> ResultSet {
+        ResultSet::new(vec!["column_name".to_owned(), "unique".to_owned()], vec![])
+    }
+
+    #[test]
+    fn column_order_is_not_a_change() {
+        let db = ColumnDecoder::decode_sqlite_columns(table_info(&[("id", "INTEGER", 1, 1), ("name", "TEXT", 1, 0), ("age", "INTEGER", 0, 0)]), empty(), empty()).unwrap();
+        let model = ColumnDecoder::decode_sqlite_columns(table_info(&[("age", "INTEGER", 0, 0), ("name", "TEXT", 1, 0), ("id", "INTEGER", 1, 1)]), empty(), empty()).unwrap();
+        assert!(!ColumnDecoder::need_to_alter_any_columns(&db, &model));
+        assert!(!ColumnDecoder::need_to_alter_any_columns(&model, &db));
+    }
+
+    #[test]
+    fn changed_definition_is_a_change() {
+        let db = ColumnDecoder::decode_sqlite_columns(table_info(&[("id", "INTEGER", 1, 1), ("name", "TEXT", 1, 0)]), empty(), empty()).unwrap();
+        let model = ColumnDecoder::decode_sqlite_columns(table_info(&[("name", "TEXT", 0, 0), ("id", "INTEGER", 1, 1)]), empty(), empty()).unwrap();
+        assert!(ColumnDecoder::need_to_alter_any_columns(&db, &model));
+    }
+}
@@ -1,59 +1,118 @@
 use crate::schema::dialect::SQLDialect;
 use crate::schema::value::encode::ToSQLString;
 use teo_runtime::database::r#type::DatabaseType;
+use teo_runtime::database::mysql::r#type::MySQLType;
 use crate::exts::database_type::DatabaseTypeToSQLString;
 
 pub(crate) mod decoder;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
-pub(crate) struct SQLColumn {
+pub struct SQLColumn {
     pub(self) name: String,
     pub(self) r#type: DatabaseType,
     pub(self) not_null: bool,
     pub(self) auto_increment: bool,
     pub(self) default: Option<String>,
     pub(self) primary_key: bool,
+    pub(self) collation: Option<String>,
 }
 
 impl SQLColumn {
 
     pub(crate) fn new(name: String, r#type: DatabaseType, not_null: bool, auto_increment: bool, default: Option<String>, primary_key: bool) -> Self {
         Self {
-            name, r#type, not_null, auto_increment, default, primary_key
+            name, r#type, not_null, auto_increment, default, primary_key, collation: None
         }
     }
 
-    pub(crate) fn name(&self) -> &str {
+    pub fn builder(name: impl Into<String>, r#type: DatabaseType) -> SQLColumnBuilder {
+        SQLColumnBuilder {
+            column: Self::new(name.into(), r#type, false, false, None, false)
+        }
+    }
+
+    pub fn name(&self) -> &str {
         &self.name
     }
 
-    pub(crate) fn r#type(&self) -> &DatabaseType {
+    pub fn r#type(&self) -> &DatabaseType {
         &self.r#type
     }
 
-    pub(crate) fn not_null(&self) -> bool {
+    pub fn not_null(&self) -> bool {
         self.not_null
     }
 
-    pub(crate) fn null(&self) -> bool {
+    pub fn null(&self) -> bool {
         !self.not_null
     }
 
-    pub(crate) fn auto_increment(&self) -> bool {
+    pub fn auto_increment(&self) -> bool {
         self.auto_increment
     }
 
-    pub(crate) fn default(&self) -> Option<&str> {
+    pub fn default(&self) -> Option<&str> {
         self.default.as_deref()
     }
 
-    pub(crate) fn primary_key(&self) -> bool {
+    pub fn primary_key(&self) -> bool {
         self.primary_key
     }
 
+    pub fn collation(&self) -> Option<&str> {
+        self.collation.as_deref()
+    }
+
     pub(crate) fn set_default(&mut self, default: Option<String>) {
         self.default = default;
     }
+
+    // databases store defaults in their own form, e.g. 'x'::text or now() for CURRENT_TIMESTAMP
+    pub(crate) fn normalized_default(&self) -> Option<String> {
+        let default = self.default.as_ref()?;
+        let mut default = default.trim();
+        while default.starts_with('(') && default.ends_with(')') {
+            default = &default[1..default.len() - 1];
+        }
+        if (self.auto_increment && default.starts_with("nextval(")) || default.eq_ignore_ascii_case("null") {
+            return None;
+        }
+        // strip trailing casts outside of the quoted literal
+        let literal_end = if default.starts_with('\'') { default.rfind('\'').unwrap() } else { 0 };
+        let default = match default[literal_end..].find("::") {
+            Some(index) => &default[..literal_end + index],
+            None => default,
+        };
+        let default = default.trim_matches('\'').to_owned();
+        Some(match default.to_lowercase().as_str() {
+            "now()" | "current_timestamp" | "current_timestamp()" => "CURRENT_TIMESTAMP".to_owned(),
+            "true" | "false" => default.to_lowercase(),
+            _ => default,
+        })
+    }
+
+    pub(crate) fn same_definition(&self, model_column: &SQLColumn) -> bool {
+        self.name == model_column.name &&
+            comparable_type(&self.r#type) == comparable_type(&model_column.r#type) &&
+            self.not_null == model_column.not_null &&
+            self.auto_increment == model_column.auto_increment &&
+            self.primary_key == model_column.primary_key &&
+            (model_column.collation.is_none() || self.collation == model_column.collation) &&
+            self.normalized_default() == model_column.normalized_default()
+    }
+}
+
+// mysql stopped reporting integer display widths, they never change storage,
+// except tinyint(1) which marks a boolean
+fn comparable_type(r#type: &DatabaseType) -> DatabaseType {
+    match r#type {
+        DatabaseType::MySQLType(MySQLType::TinyInt(len, unsigned)) if *len != Some(1) => DatabaseType::MySQLType(MySQLType::TinyInt(None, *unsigned)),
+        DatabaseType::MySQLType(MySQLType::SmallInt(_, unsigned)) => DatabaseType::MySQLType(MySQLType::SmallInt(None, *unsigned)),
+        DatabaseType::MySQLType(MySQLType::MediumInt(_, unsigned)) => DatabaseType::MySQLType(MySQLType::MediumInt(None, *unsigned)),
+        DatabaseType::MySQLType(MySQLType::Int(_, unsigned)) => DatabaseType::MySQLType(MySQLType::Int(None, *unsigned)),
+        DatabaseType::MySQLType(MySQLType::BigInt(_, unsigned)) => DatabaseType::MySQLType(MySQLType::BigInt(None, *unsigned)),
+        _ => r#type.clone(),
+    }
 }
 
 impl ToSQLString for SQLColumn {
@@ -63,6 +122,9 @@ impl ToSQLString for SQLColumn {
         let not_null = if self.not_null { " NOT NULL" } else { " NULL" };
         let primary = if self.primary_key { " PRIMARY KEY" } else { "" };
         let default = if self.default.is_some() { " DEFAULT ".to_owned() + self.default.as_ref().unwrap().as_str() } else { "".to_owned() };
+        let collation = if let Some(collation) = &self.collation {
+            if dialect == SQLDialect::PostgreSQL { format!(" COLLATE \"{collation}\"") } else { format!(" COLLATE {collation}") }
+        } else { "".to_owned() };
         let auto_inc = if self.auto_increment {
             if dialect == SQLDialect::MySQL {
                 " AUTO_INCREMENT"
@@ -76,9 +138,75 @@ impl ToSQLString for SQLColumn {
             } else {
                 t
             };
-            format!("\"{name}\" {t_with_auto_inc}{default}{not_null}{primary}")
+            format!("\"{name}\" {t_with_auto_inc}{collation}{default}{not_null}{primary}")
         } else {
-            format!("`{name}` {t}{default}{not_null}{primary}{auto_inc}")
+            format!("`{name}` {t}{collation}{default}{not_null}{primary}{auto_inc}")
         }
     }
 }
+
+// for driving custom DDL with the statements migration uses
+pub struct SQLColumnBuilder {
+    column: SQLColumn,
+}
+
+impl SQLColumnBuilder {
+
+    pub fn not_null(&mut self, not_null: bool) -> &mut Self {
+        self.column.not_null = not_null;
+        self
+    }
+
+    pub fn auto_increment(&mut self, auto_increment: bool) -> &mut Self {
+        self.column.auto_increment = auto_increment;
+        self
+    }
+
+    // raw sql, e.g. '0' or CURRENT_TIMESTAMP
+    pub fn default(&mut self, default: impl Into<String>) -> &mut Self {
+        self.column.default = Some(default.into());
+        self
+    }
+
+    pub fn primary_key(&mut self, primary_key: bool) -> &mut Self {
+        self.column.primary_key = primary_key;
+        self
+    }
+
+    pub fn collation(&mut self, collation: impl Into<String>) -> &mut Self {
+        self.column.collation = Some(collation.into());
+        self
+    }
+
+    pub fn build(&self) -> SQLColumn {
+        self.column.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_runtime::database::postgres::r#type::PostgreSQLType;
+    use super::*;
+
+    fn column(default: Option<&str>) -> SQLColumn {
+        SQLColumn::new("at".to_owned(), DatabaseType::PostgreSQLType(PostgreSQLType::Text), false, false, default.map(|d| d.to_owned()), false)
+    }
+
+    #[test]
+    fn stored_defaults_normalize_to_the_declared_ones() {
+        assert_eq!(column(Some("'x'::text")).normalized_default(), column(Some("'x'")).normalized_default());
+        assert_eq!(column(Some("('x'::character varying)")).normalized_default(), Some("x".to_owned()));
+        assert_eq!(column(Some("now()")).normalized_default(), column(Some("CURRENT_TIMESTAMP")).normalized_default());
+        assert_eq!(column(Some("NULL")).normalized_default(), None);
+        let serial = SQLColumn::new("id".to_owned(), DatabaseType::PostgreSQLType(PostgreSQLType::Integer), true, true, Some("nextval('users_id_seq'::regclass)".to_owned()), true);
+        assert_eq!(serial.normalized_default(), None);
+    }
+
+    #[test]
+    fn equal_defaults_are_stable_and_removed_ones_are_not() {
+        assert!(column(Some("'x'::text")).same_definition(&column(Some("'x'"))));
+        assert!(column(Some("now()")).same_definition(&column(Some("CURRENT_TIMESTAMP"))));
+        assert!(!column(Some("'x'::text")).same_definition(&column(None)));
+        assert!(!column(None).same_definition(&column(Some("'x'"))));
+    }
+}
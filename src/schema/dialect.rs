@@ -2,6 +2,7 @@ use teo_runtime::database::mysql::r#type::MySQLType;
 use teo_runtime::database::postgres::r#type::PostgreSQLType;
 use teo_runtime::database::r#type::DatabaseType;
 use teo_runtime::database::sqlite::r#type::SQLiteType;
+use crate::exts::database_type::DatabaseTypeToSQLString;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum SQLDialect {
@@ -13,10 +14,20 @@ pub enum SQLDialect {
 
 impl SQLDialect {
 
-    pub(crate) fn escape(&self) -> &'static str {
+    pub(crate) fn opening_quote(&self) -> char {
         match self {
-            SQLDialect::PostgreSQL => "\"",
-            _ => "`",
+            SQLDialect::PostgreSQL => '"',
+            SQLDialect::MSSQL => '[',
+            _ => '`',
+        }
+    }
+
+    // a closing quote inside the identifier is doubled
+    pub(crate) fn quote(&self, identifier: &str) -> String {
+        match self {
+            SQLDialect::MSSQL => format!("[{}]", identifier.replace("]", "]]")),
+            SQLDialect::PostgreSQL => format!("\"{}\"", identifier.replace("\"", "\"\"")),
+            _ => format!("`{}`", identifier.replace("`", "``")),
         }
     }
 
@@ -41,21 +52,69 @@ impl SQLDialect {
         }
     }
 
-    pub(crate) fn float64_type(&self) -> DatabaseType {
+    pub(crate) fn is_mssql(&self) -> bool {
+        match self {
+            SQLDialect::MSSQL => true,
+            _ => false,
+        }
+    }
+
+    // in bytes, longer identifiers are cut silently by the database
+    pub(crate) fn max_identifier_length(&self) -> usize {
         match self {
-            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Double),
-            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::DoublePrecision),
-            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Real),
-            SQLDialect::MSSQL => panic!(),
+            SQLDialect::PostgreSQL => 63,
+            SQLDialect::MySQL => 64,
+            SQLDialect::MSSQL => 128,
+            SQLDialect::SQLite => usize::MAX,
         }
     }
 
-    pub(crate) fn int64_type(&self) -> DatabaseType {
+    // sql server types aren't modeled by the runtime, they're rendered directly
+    pub(crate) fn float32_type(&self) -> String {
         match self {
-            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Int(None, true)),
-            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::Integer),
-            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Integer),
-            SQLDialect::MSSQL => panic!(),
+            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Float).to_sql_string(),
+            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::Real).to_sql_string(),
+            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Real).to_sql_string(),
+            SQLDialect::MSSQL => "REAL".to_owned(),
         }
     }
+
+    pub(crate) fn float64_type(&self) -> String {
+        match self {
+            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Double).to_sql_string(),
+            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::DoublePrecision).to_sql_string(),
+            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Real).to_sql_string(),
+            SQLDialect::MSSQL => "FLOAT".to_owned(),
+        }
+    }
+
+    pub(crate) fn int64_type(&self) -> String {
+        match self {
+            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Int(None, true)).to_sql_string(),
+            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::Integer).to_sql_string(),
+            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Integer).to_sql_string(),
+            SQLDialect::MSSQL => "BIGINT".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_doubles_the_closing_symbol() {
+        assert_eq!(SQLDialect::PostgreSQL.quote("a\"b"), "\"a\"\"b\"");
+        assert_eq!(SQLDialect::MySQL.quote("a`b"), "`a``b`");
+        assert_eq!(SQLDialect::SQLite.quote("ab"), "`ab`");
+        assert_eq!(SQLDialect::MSSQL.quote("a]b"), "[a]]b]");
+    }
+
+    #[test]
+    fn sql_server_number_types() {
+        assert_eq!(SQLDialect::MSSQL.float32_type(), "REAL");
+        assert_eq!(SQLDialect::MSSQL.float64_type(), "FLOAT");
+        assert_eq!(SQLDialect::MSSQL.int64_type(), "BIGINT");
+        assert_eq!(SQLDialect::PostgreSQL.float64_type(), "DOUBLE PRECISION");
+    }
 }
@@ -1,5 +1,5 @@
-pub(crate) mod table;
-pub(crate) mod column;
+pub mod table;
+pub mod column;
 pub(crate) mod r#type;
 pub mod dialect;
-pub(crate) mod value;
+pub mod value;
@@ -1,6 +1,33 @@
-use crate::stmts::create::table::SQLCreateTableStatement;
+use crate::stmts::create::table::{SQLCreateTableStatement, SQLTableOptions};
 use crate::stmts::SQL;
-use teo_runtime::model::Model;
+use teo_runtime::model::{Index, Model};
+use crate::schema::column::SQLColumn;
+
+#[derive(Clone, Debug)]
+pub struct SQLTable {
+    name: String,
+    columns: Vec<SQLColumn>,
+    indices: Vec<Index>,
+}
+
+impl SQLTable {
+
+    pub(crate) fn new(name: String, columns: Vec<SQLColumn>, indices: Vec<Index>) -> Self {
+        Self { name, columns, indices }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn columns(&self) -> &Vec<SQLColumn> {
+        &self.columns
+    }
+
+    pub fn indices(&self) -> &Vec<Index> {
+        &self.indices
+    }
+}
 
 impl From<&Model> for SQLCreateTableStatement {
     fn from(model: &Model) -> Self {
@@ -19,6 +46,18 @@ impl From<&Model> for SQLCreateTableStatement {
         if model.primary_index().unwrap().keys().len() > 1 {
             stmt.primary(model.primary_index().unwrap().clone());
         }
+        if let Some(start) = model.migration().auto_increment_start {
+            stmt.auto_increment_start(start);
+        }
+        let migration = model.migration();
+        stmt.options(SQLTableOptions {
+            engine: migration.engine.clone(),
+            charset: migration.charset.clone(),
+            collation: migration.collation.clone(),
+            without_rowid: migration.without_rowid,
+            strict: migration.strict,
+            tablespace: migration.tablespace.clone(),
+        });
         stmt
     }
 }
@@ -5,17 +5,18 @@ use snailquote::unescape;
 use teo_runtime::database::mysql::r#type::{MySQLEnum, MySQLType};
 use teo_runtime::database::postgres::r#type::PostgreSQLType;
 use teo_runtime::database::sqlite::r#type::SQLiteType;
+use teo_result::{Error, Result};
 use crate::schema::dialect::SQLDialect;
 
 pub(crate) struct SQLTypeDecoder { }
 
 impl SQLTypeDecoder {
-    pub(crate) fn decode(r#type: &str, dialect: SQLDialect) -> DatabaseType {
+    pub(crate) fn decode(r#type: &str, dialect: SQLDialect) -> Result<DatabaseType> {
         match dialect {
-            SQLDialect::MySQL => DatabaseType::MySQLType(mysql_type_to_database_type(r#type)),
-            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(postgresql_type_to_database_type(r#type)),
-            SQLDialect::SQLite => DatabaseType::SQLiteType(sqlite_type_to_database_type(r#type)),
-            SQLDialect::MSSQL => panic!(),
+            SQLDialect::MySQL => Ok(DatabaseType::MySQLType(mysql_type_to_database_type(r#type))),
+            SQLDialect::PostgreSQL => Ok(DatabaseType::PostgreSQLType(postgresql_type_to_database_type(r#type))),
+            SQLDialect::SQLite => Ok(DatabaseType::SQLiteType(sqlite_type_to_database_type(r#type))),
+            SQLDialect::MSSQL => Err(Error::new(format!("cannot decode SQL Server column type `{}', the runtime doesn't define SQL Server types yet", r#type))),
         }
     }
 }
@@ -23,13 +24,14 @@ impl SQLTypeDecoder {
 fn mysql_type_to_database_type(r#type: &str) -> MySQLType {
     let r#type_string = r#type.to_lowercase();
     let r#type: &str = r#type_string.as_str();
-    let regex = Regex::new("([^ \\(\\)]+)( (.+))?(\\((.+)\\))?").unwrap();
+    // the arguments come before modifiers, e.g. int(11) unsigned
+    let regex = Regex::new("([^ \\(\\)]+)(\\((.+)\\))?( (.+))?").unwrap();
     match regex.captures(r#type) {
         None => panic!("Unhandled database type '{}' '{}'.", r#type, regex),
         Some(captures) => {
             let name = captures.get(1).unwrap().as_str();
-            let trailing1 = captures.get(3).map(|m| m.as_str());
-            let arg = captures.get(5).map(|m| m.as_str());
+            let trailing1 = captures.get(5).map(|m| m.as_str()).filter(|t| t.contains("unsigned"));
+            let arg = captures.get(3).map(|m| m.as_str());
             match name {
                 "bit" => MySQLType::Bit(arg.map(|a| i32::from_str(a).unwrap())),
                 "tinyint" => MySQLType::TinyInt(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
@@ -71,6 +73,13 @@ fn postgresql_type_to_database_type(r#type: &str) -> PostgreSQLType {
     let lower_str = lower.as_str();
     match lower_str {
         "integer" | "int4" => PostgreSQLType::Integer,
+        "smallint" | "int2" => PostgreSQLType::SmallInt,
+        "uuid" => PostgreSQLType::UUID,
+        "xml" => PostgreSQLType::Xml,
+        "inet" => PostgreSQLType::Inet,
+        "money" => PostgreSQLType::Money,
+        "oid" => PostgreSQLType::Oid,
+        "bit varying" | "varbit" => PostgreSQLType::VarBit,
         "text" => PostgreSQLType::Text,
         "timestamp with time zone" | "timestamptz" => PostgreSQLType::Timestamp(3, true),
         "timestamp without time zone" | "timestamp" => PostgreSQLType::Timestamp(3, false),
@@ -83,12 +92,26 @@ fn postgresql_type_to_database_type(r#type: &str) -> PostgreSQLType {
         _ => if lower_str.starts_with("array|") {
             let inner = &lower_str[6..];
             PostgreSQLType::Array(Box::new(postgresql_type_to_database_type(inner)))
+        } else if let Some(len) = pg_type_length(lower_str, &["character varying(", "varchar("]) {
+            PostgreSQLType::VarChar(len)
+        } else if let Some(len) = pg_type_length(lower_str, &["character(", "char("]) {
+            PostgreSQLType::Char(len)
+        } else if let Some(len) = pg_type_length(lower_str, &["bit("]) {
+            PostgreSQLType::Bit(len)
+        } else if lower_str.starts_with("numeric(") || lower_str.starts_with("decimal(") {
+            let args = lower_str.split_once("(").unwrap().1.trim_end_matches(")").split(",").map(|a| a.trim()).collect::<Vec<&str>>();
+            PostgreSQLType::Decimal(args.get(0).unwrap().parse().unwrap(), args.get(1).unwrap_or(&"0").parse().unwrap())
         } else {
             panic!("Unhandled database type {}", r#type)
         }
     }
 }
 
+fn pg_type_length(r#type: &str, prefixes: &[&str]) -> Option<i32> {
+    let prefix = prefixes.iter().find(|p| r#type.starts_with(**p))?;
+    i32::from_str(r#type[prefix.len()..].trim_end_matches(")").trim()).ok()
+}
+
 fn sqlite_type_to_database_type(r#type: &str) -> SQLiteType {
     let r#type_string = r#type.to_lowercase();
     let r#type: &str = r#type_string.as_str();
@@ -116,3 +139,14 @@ fn mssql_type_to_database_type(r#type: &str) -> DatabaseType {
         _ => panic!("Unhandled database type.")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sql_server_types_are_an_error() {
+        assert!(SQLTypeDecoder::decode("nvarchar(255)", SQLDialect::MSSQL).is_err());
+        assert!(SQLTypeDecoder::decode("integer", SQLDialect::SQLite).is_ok());
+    }
+}
@@ -1,5 +1,5 @@
 use std::str::FromStr;
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, ToPrimitive};
 use crate::schema::dialect::SQLDialect;
 use teo_runtime::value::Value;
 use chrono::{NaiveDate, DateTime, Utc};
@@ -67,9 +67,18 @@ impl RowDecoder {
                     None => Value::Null,
                 }
             }
-            // quaint_forked::Value::Array(values) => {
-            //
-            // }
+            quaint_forked::Value::Array(values) => {
+                match values {
+                    Some(values) => Value::Array(values.iter().map(Self::decode_raw).collect()),
+                    None => Value::Null,
+                }
+            }
+            quaint_forked::Value::Enum(e) => {
+                match e {
+                    Some(e) => Value::String(e.as_ref().to_owned()),
+                    None => Value::Null,
+                }
+            }
             _ => unreachable!()
         }
     }
@@ -105,6 +114,21 @@ impl RowDecoder {
         }
     }
 
+    // postgres money comes formatted by lc_monetary, e.g. $1,234.56 or -$1,234.56.
+    // a comma decimal separator can't be told from a grouping one, which is why
+    // decimal columns are preferable to money
+    fn decode_money(money: &str) -> Option<BigDecimal> {
+        let negative = money.contains('-') || money.starts_with('(');
+        let digits: String = money.chars().filter(|c| c.is_ascii_digit() || *c == '.').collect();
+        let decimal = BigDecimal::from_str(&digits).ok()?;
+        Some(if negative { -decimal } else { decimal })
+    }
+
+    // mysql sends BIT(n) as big endian bytes
+    fn decode_bits(bytes: &[u8]) -> i64 {
+        bytes.iter().fold(0i64, |acc, byte| (acc << 8) | *byte as i64)
+    }
+
     pub(crate) fn decode_value(r#type: &Type, optional: bool, value: Option<&quaint_forked::Value>, dialect: SQLDialect) -> Value {
         if optional {
             if value.is_none() {
@@ -116,6 +140,14 @@ impl RowDecoder {
         if r#type.is_bool() {
             if let Some(v) = value.as_bool() {
                 return Value::Bool(v)
+            } else if let Some(v) = value.as_i32() { // mysql tinyint(1)
+                return Value::Bool(v != 0)
+            } else if let Some(v) = value.as_i64() {
+                return Value::Bool(v != 0)
+            } else if let Some(v) = value.as_str() {
+                return Value::Bool(v == "t" || v == "true")
+            } else if let Some(v) = value.as_bytes() { // mysql bit
+                return Value::Bool(Self::decode_bits(v) != 0)
             } else {
                 return Value::Null;
             }
@@ -130,6 +162,19 @@ impl RowDecoder {
         if r#type.is_int() {
             if let Some(v) = value.as_i32() {
                 return Value::Int(v);
+            } else if let Some(v) = value.as_i64() { // mysql int unsigned
+                return match i32::try_from(v) {
+                    Ok(v) => Value::Int(v),
+                    Err(_) => Value::Int64(v),
+                };
+            } else if let Some(v) = value.as_str().map(|s| i32::from_str(s).ok()).flatten() {
+                return Value::Int(v);
+            } else if let Some(v) = value.as_bytes() { // mysql bit
+                let v = Self::decode_bits(v);
+                return match i32::try_from(v) {
+                    Ok(v) => Value::Int(v),
+                    Err(_) => Value::Int64(v),
+                };
             } else {
                 return Value::Null;
             }
@@ -139,6 +184,15 @@ impl RowDecoder {
                 return Value::Int64(v);
             } else if let Some(v) = value.as_i32() {
                 return Value::Int(v);
+            } else if let Some(v) = value.as_numeric() { // mysql bigint unsigned above i64::MAX
+                return match v.to_i64() {
+                    Some(i) => Value::Int64(i),
+                    None => Value::Decimal(v.clone()),
+                };
+            } else if let Some(v) = value.as_str().map(|s| i64::from_str(s).ok()).flatten() {
+                return Value::Int64(v);
+            } else if let Some(v) = value.as_bytes() { // mysql bit
+                return Value::Int64(Self::decode_bits(v));
             } else {
                 return Value::Null;
             }
@@ -154,12 +208,19 @@ impl RowDecoder {
                 };
             } else if let Some(f32_val) = value.as_f32() {
                 return if r#type.is_float() {
-                    Value::Float(f32_val as f64)
+                    // widen through the shortest decimal form so 0.1f32 reads back as 0.1
+                    Value::Float(f64::from_str(&f32_val.to_string()).unwrap())
                 } else if r#type.is_float32() {
                     Value::Float32(f32_val)
                 } else {
                     unreachable!()
                 };
+            } else if let Some(f64_val) = value.as_str().map(|s| f64::from_str(s).ok()).flatten() {
+                return if r#type.is_float() {
+                    Value::Float(f64_val)
+                } else {
+                    Value::Float32(f64_val as f32)
+                };
             } else {
                 return Value::Null;
             }
@@ -168,6 +229,8 @@ impl RowDecoder {
             if dialect == SQLDialect::PostgreSQL {
                 if let Some(naive_date) = value.as_date() {
                     return Value::Date(naive_date);
+                } else if let Some(naive_date) = value.as_str().map(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()).flatten() {
+                    return Value::Date(naive_date);
                 } else {
                     return Value::Null;
                 }
@@ -218,7 +281,10 @@ impl RowDecoder {
             if let Some(val) = value.as_numeric() {
                 return Value::Decimal(val.clone());
             } else if let Some(val) = value.as_str() {
-                return Value::Decimal(BigDecimal::from_str(val).unwrap());
+                return match BigDecimal::from_str(val).ok().or_else(|| Self::decode_money(val)) {
+                    Some(decimal) => Value::Decimal(decimal),
+                    None => Value::Null,
+                };
             } else if let Some(val) = value.as_f64() {
                 return Value::Decimal(BigDecimal::from_str(&val.to_string()).unwrap())
             } else if let Some(val) = value.as_f32() {
@@ -232,9 +298,15 @@ impl RowDecoder {
             }
         }
         if r#type.is_array() {
+            let inner = r#type.as_array().unwrap();
             if let Some(vals) = value.as_array() {
-                let inner = r#type.as_array().unwrap();
                 return Value::Array(vals.iter().map(|v| Self::decode_value(inner.unwrap_optional(), inner.is_optional(), Some(v), dialect)).collect());
+            } else if let Some(literal) = value.as_str() {
+                // element types the driver doesn't know come back as '{a,b,c}'
+                return Value::Array(parse_psql_array_literal(literal).into_iter().map(|e| match e {
+                    Some(e) => Self::decode_value(inner.unwrap_optional(), inner.is_optional(), Some(&QuaintValue::text(e)), dialect),
+                    None => Value::Null,
+                }).collect());
             } else {
                 return Value::Null;
             }
@@ -266,3 +338,122 @@ impl RowDecoder {
         Self::decode_value(r#type, optional, result.clone(), dialect)
     }
 }
+
+fn parse_psql_array_literal(literal: &str) -> Vec<Option<String>> {
+    let inner = literal.trim().trim_start_matches('{').trim_end_matches('}');
+    let mut result = vec![];
+    if inner.is_empty() {
+        return result;
+    }
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut was_quoted = false;
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => { quoted = !quoted; was_quoted = true; }
+            '\\' if quoted => if let Some(next) = chars.next() { current.push(next) },
+            ',' if !quoted => {
+                result.push(if !was_quoted && current == "NULL" { None } else { Some(current.clone()) });
+                current.clear();
+                was_quoted = false;
+            }
+            _ => current.push(ch),
+        }
+    }
+    result.push(if !was_quoted && current == "NULL" { None } else { Some(current) });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::value::encode::{ValueToSQLString, money_to_sql_input};
+    use super::*;
+
+    #[test]
+    fn float32_widens_through_its_shortest_form() {
+        assert_eq!(RowDecoder::decode_value(&Type::Float, false, Some(&QuaintValue::float(0.1f32)), SQLDialect::PostgreSQL), Value::Float(0.1));
+    }
+
+    #[test]
+    fn float32_survives_an_encode_decode_round_trip() {
+        for value in [0.1f32, 1.5, -7.25, 3.4028235e38, f32::MIN_POSITIVE] {
+            let literal = Value::Float32(value).to_sql_string(&Type::Float32, false, SQLDialect::PostgreSQL).unwrap();
+            let stored: f32 = literal.parse().unwrap();
+            let decoded = RowDecoder::decode_value(&Type::Float32, false, Some(&QuaintValue::float(stored)), SQLDialect::PostgreSQL);
+            assert_eq!(decoded, Value::Float32(value));
+            // mysql hands FLOAT columns back as doubles
+            let decoded = RowDecoder::decode_value(&Type::Float32, false, Some(&QuaintValue::double(stored as f64)), SQLDialect::MySQL);
+            assert_eq!(decoded, Value::Float32(value));
+        }
+    }
+
+    #[test]
+    fn mysql_integers_decode_as_bools() {
+        assert_eq!(RowDecoder::decode_value(&Type::Bool, false, Some(&QuaintValue::int32(1)), SQLDialect::MySQL), Value::Bool(true));
+        assert_eq!(RowDecoder::decode_value(&Type::Bool, false, Some(&QuaintValue::int32(0)), SQLDialect::MySQL), Value::Bool(false));
+        assert_eq!(RowDecoder::decode_value(&Type::Bool, false, Some(&QuaintValue::bytes(vec![1u8])), SQLDialect::MySQL), Value::Bool(true));
+    }
+
+    #[test]
+    fn psql_array_literals_split_on_unquoted_commas() {
+        assert_eq!(parse_psql_array_literal("{}"), Vec::<Option<String>>::new());
+        assert_eq!(parse_psql_array_literal("{a,NULL,\"NULL\"}"), vec![Some("a".to_owned()), None, Some("NULL".to_owned())]);
+        assert_eq!(parse_psql_array_literal("{\"a,b\",\"c\\\"d\"}"), vec![Some("a,b".to_owned()), Some("c\"d".to_owned())]);
+    }
+
+    #[test]
+    fn decimals_read_money_text() {
+        let value = RowDecoder::decode_value(&Type::Decimal, false, Some(&QuaintValue::text("$12.50")), SQLDialect::PostgreSQL);
+        assert_eq!(value, Value::Decimal(BigDecimal::from_str("12.50").unwrap()));
+        let value = RowDecoder::decode_value(&Type::Decimal, false, Some(&QuaintValue::text("n/a")), SQLDialect::PostgreSQL);
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn unknown_array_elements_come_from_the_literal() {
+        let r#type = Type::Array(Box::new(Type::Int));
+        let value = RowDecoder::decode_value(&r#type, false, Some(&QuaintValue::text("{1,2}")), SQLDialect::PostgreSQL);
+        assert_eq!(value, Value::Array(vec![Value::Int(1), Value::Int(2)]));
+    }
+
+    #[test]
+    fn unsigned_ints_widen_when_they_do_not_fit() {
+        assert_eq!(RowDecoder::decode_value(&Type::Int, false, Some(&QuaintValue::int64(5)), SQLDialect::MySQL), Value::Int(5));
+        assert_eq!(RowDecoder::decode_value(&Type::Int, false, Some(&QuaintValue::int64(u32::MAX as i64)), SQLDialect::MySQL), Value::Int64(u32::MAX as i64));
+    }
+
+    #[test]
+    fn money_keeps_sign_and_drops_formatting() {
+        assert_eq!(RowDecoder::decode_money("$1,234.56"), Some(BigDecimal::from_str("1234.56").unwrap()));
+        assert_eq!(RowDecoder::decode_money("-$1,234.56"), Some(BigDecimal::from_str("-1234.56").unwrap()));
+        assert_eq!(RowDecoder::decode_money("($7.00)"), Some(BigDecimal::from_str("-7.00").unwrap()));
+        assert_eq!(RowDecoder::decode_money("$"), None);
+    }
+
+    #[test]
+    fn money_survives_an_encode_decode_round_trip() {
+        // what postgres sends back for the written literal with the default lc_monetary
+        let cases = [
+            (Value::Decimal(BigDecimal::from_str("1234.56").unwrap()), "1234.56::money", "$1,234.56"),
+            (Value::Decimal(BigDecimal::from_str("-0.5").unwrap()), "-0.5::money", "-$0.50"),
+            (Value::Float(19.99), "19.99::money", "$19.99"),
+            (Value::String("7".to_owned()), "7::money", "$7.00"),
+            (Value::Int64(3), "3::money", "$3.00"),
+        ];
+        for (value, encoded, stored) in cases {
+            assert_eq!(money_to_sql_input(&value).unwrap(), encoded);
+            let decoded = RowDecoder::decode_value(&Type::Decimal, false, Some(&QuaintValue::text(stored)), SQLDialect::PostgreSQL);
+            assert_eq!(decoded, Value::Decimal(BigDecimal::from_str(encoded.trim_end_matches("::money")).unwrap()));
+        }
+        assert!(money_to_sql_input(&Value::String("a lot".to_owned())).is_err());
+        assert!(money_to_sql_input(&Value::Float(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn bits_are_big_endian() {
+        assert_eq!(RowDecoder::decode_bits(&[]), 0);
+        assert_eq!(RowDecoder::decode_bits(&[1]), 1);
+        assert_eq!(RowDecoder::decode_bits(&[1, 0]), 256);
+    }
+}
@@ -1,8 +1,11 @@
+use std::str::FromStr;
 use bigdecimal::BigDecimal;
 use chrono::{NaiveDate, Utc, DateTime, SecondsFormat};
 use itertools::Itertools;
 use teo_parser::r#type::Type;
+use teo_result::{Error, Result};
 use teo_runtime::value::Value;
+use crate::query::params::{ESCAPED_MARK, MARK};
 use crate::schema::dialect::SQLDialect;
 
 pub trait ToSQLString {
@@ -20,47 +23,65 @@ impl TypeOrNull for &str {
 }
 
 pub(crate) trait ValueToSQLString {
-    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> String;
+    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> Result<String>;
     fn to_sql_string_array_arg<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> String;
 }
 
 impl ValueToSQLString for Value {
 
-    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> String {
+    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> Result<String> {
         if optional {
             if self.is_null() {
-                return "NULL".to_owned()
+                return Ok("NULL".to_owned())
             }
         }
-        match r#type.unwrap_optional() {
-            Type::String => ToSQLInputDialect::to_sql_input(&self.as_str().unwrap(), dialect),
-            Type::Bool => self.as_bool().unwrap().to_sql_input(),
-            Type::Int | Type::Int64 |
-            Type::Float32 | Type::Float => if let Some(val) = self.as_float() {
+        if let Some(range) = self.as_range() {
+            return range_to_sql_input(range.start.to_sql_string(r#type, false, dialect)?, range.end.to_sql_string(r#type, false, dialect)?, range.closed, dialect);
+        }
+        Ok(match r#type.unwrap_optional() {
+            // a number given for a string column is still compared as text, "007" never equals 7
+            Type::String => ToSQLInputDialect::to_sql_input(&scalar_text(self).as_str(), dialect),
+            Type::Bool => bool_to_sql_input(self.as_bool().unwrap(), dialect),
+            Type::Float32 => if let Some(val) = self.as_float32() {
+                float32_to_sql_input(val, dialect)
+            } else if let Some(val) = self.as_float() {
+                float32_to_sql_input(val as f32, dialect)
+            } else if let Some(val) = self.as_int64() {
                 val.to_string()
-            } else if let Some(val) = self.as_float32() {
+            } else if let Some(val) = self.as_int() {
                 val.to_string()
+            } else {
+                return Err(Error::new(format!("cannot encode {:?} as a number", self)))
+            }
+            Type::Int | Type::Int64 | Type::Float => if let Some(val) = self.as_float() {
+                float_to_sql_input(val, dialect)
+            } else if let Some(val) = self.as_float32() {
+                float32_to_sql_input(val, dialect)
             } else if let Some(val) = self.as_int64() {
                 val.to_string()
             } else if let Some(val) = self.as_int() {
                 val.to_string()
+            } else if let Some(val) = self.as_decimal() { // unsigned bigint beyond i64
+                val.to_string()
+            } else if let Some(val) = self.as_str().filter(|s| is_integer_literal(s)) { // snowflake ids beyond i64
+                val.to_owned()
             } else {
-                panic!("cannot encode number")
+                return Err(Error::new(format!("cannot encode {:?} as a number", self)))
             }
             Type::EnumVariant(_) => ToSQLInputDialect::to_sql_input(&self.as_str().unwrap(), dialect),
             Type::Array(element_field) => {
                 let val = self.as_array().unwrap();
                 let mut result: Vec<String> = vec![];
                 for (_i, v) in val.iter().enumerate() {
-                    result.push(v.to_sql_string(element_field.unwrap_optional(), element_field.is_optional(), dialect));
+                    result.push(v.to_sql_string_array_arg(element_field.unwrap_optional(), element_field.is_optional(), dialect));
                 }
                 result.join(", ").wrap_in_array()
             }
             Type::Date => self.as_date().unwrap().to_string().to_sql_input(dialect),
             Type::DateTime => self.as_datetime().unwrap().to_string().to_sql_input(dialect),
-            Type::Decimal => self.as_decimal().unwrap().to_string().to_sql_input(dialect),
-            _ => { panic!() }
-        }
+            Type::Decimal => plain_decimal(self.as_decimal().unwrap()).to_sql_input(dialect),
+            _ => return Err(Error::new(format!("cannot encode {:?} as {}", self, r#type))),
+        })
     }
 
     fn to_sql_string_array_arg<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> String {
@@ -70,17 +91,28 @@ impl ValueToSQLString for Value {
             }
         }
         match r#type.unwrap_optional() {
-            Type::String => ToSQLInputDialect::to_sql_input(&self.as_str().unwrap(), dialect),
+            Type::String | Type::EnumVariant(_) => psql_array_element(self.as_str().unwrap()),
             Type::Bool => self.as_bool().unwrap().to_sql_input(),
-            Type::Int | Type::Int64 |
-            Type::Float32 | Type::Float => if let Some(val) = self.as_float() {
+            Type::Float32 => if let Some(val) = self.as_float32() {
+                float32_array_element(val)
+            } else if let Some(val) = self.as_float() {
+                float32_array_element(val as f32)
+            } else if let Some(val) = self.as_int64() {
                 val.to_string()
+            } else {
+                panic!("Uncoded number.")
+            }
+            Type::Int | Type::Int64 | Type::Float => if let Some(val) = self.as_float() {
+                float_array_element(val)
+            } else if let Some(val) = self.as_float32() {
+                float32_array_element(val)
             } else if let Some(val) = self.as_int64() {
                 val.to_string()
+            } else if let Some(val) = self.as_str().filter(|s| is_integer_literal(s)) {
+                val.to_owned()
             } else {
                 panic!("Uncoded number.")
             }
-            Type::EnumVariant(_) => ToSQLInputDialect::to_sql_input(&self.as_str().unwrap(), dialect),
             Type::Array(element_field) => {
                 let val = self.as_array().unwrap();
                 let mut result: Vec<String> = vec![];
@@ -91,14 +123,14 @@ impl ValueToSQLString for Value {
             }
             Type::Date => self.as_date().unwrap().to_string(),
             Type::DateTime => self.as_datetime().unwrap().to_string(),
-            Type::Decimal => self.as_decimal().unwrap().to_string(),
+            Type::Decimal => plain_decimal(self.as_decimal().unwrap()),
             _ => { panic!() }
         }
     }
 }
 
 impl ValueToSQLString for &Value {
-    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> String {
+    fn to_sql_string<'a>(&self, r#type: &Type, optional: bool, dialect: SQLDialect) -> Result<String> {
         (*self).to_sql_string(r#type, optional, dialect)
     }
 
@@ -114,27 +146,92 @@ impl ToSQLString for &Value {
             Value::String(string) => string.to_sql_input(dialect),
             Value::Int(i) => i.to_string(),
             Value::Int64(i) => i.to_string(),
-            Value::Float32(i) => i.to_string(),
-            Value::Float(i) => i.to_string(),
-            Value::Bool(b) => b.to_sql_input(),
+            Value::Float32(i) => float32_to_sql_input(*i, dialect),
+            Value::Float(i) => float_to_sql_input(*i, dialect),
+            Value::Bool(b) => bool_to_sql_input(*b, dialect),
             Value::Date(d) => d.to_sql_input(dialect),
             Value::DateTime(d) => d.to_sql_input(dialect),
             Value::Decimal(d) => d.to_sql_input(dialect),
             Value::Array(values) => format!("array[{}]", values.iter().map(|v| ToSQLString::to_string(&v, dialect)).join(",")),
+            // the callers only get here for postgres, `to_string_with_ft` checks the dialect
+            Value::Range(range) => psql_range_literal(ToSQLString::to_string(&range.start.as_ref(), dialect), ToSQLString::to_string(&range.end.as_ref(), dialect), range.closed),
             _ => panic!("unhandled value: {:?}", self),
         }
     }
 }
 
+// mysql BIT columns take binary literals, e.g. b'101'
+pub(crate) fn bit_to_sql_input(value: &Value) -> String {
+    if value.is_null() {
+        return "NULL".to_owned();
+    }
+    let bits = if let Some(b) = value.as_bool() {
+        b as i64
+    } else if let Some(i) = value.as_int() {
+        i as i64
+    } else {
+        value.as_int64().unwrap()
+    };
+    format!("b'{:b}'", bits)
+}
+
+// numeric casts to money without going through the locale
+pub(crate) fn money_to_sql_input(value: &Value) -> Result<String> {
+    let decimal = match value {
+        Value::Null => return Ok("NULL".to_owned()),
+        Value::Decimal(d) => d.clone(),
+        Value::Int(i) => BigDecimal::from(*i),
+        Value::Int64(i) => BigDecimal::from(*i),
+        Value::Float(f) if f.is_finite() => BigDecimal::from_str(&f.to_string()).map_err(|_| Error::new(format!("cannot store {} as money", f)))?,
+        Value::Float32(f) if f.is_finite() => BigDecimal::from_str(&f.to_string()).map_err(|_| Error::new(format!("cannot store {} as money", f)))?,
+        Value::String(s) => BigDecimal::from_str(s.trim()).map_err(|_| Error::new(format!("cannot store {:?} as money", s)))?,
+        _ => return Err(Error::new(format!("money columns take decimals, not {:?}", value))),
+    };
+    Ok(plain_decimal(&decimal) + "::money")
+}
+
+// only postgres has range types, elsewhere range filters are split into bounds by the query
+pub(crate) fn range_to_sql_input(start: String, end: String, closed: bool, dialect: SQLDialect) -> Result<String> {
+    if !dialect.is_postgres() {
+        return Err(Error::new(format!("range values are only supported on PostgreSQL, not on {:?}", dialect)));
+    }
+    Ok(psql_range_literal(start, end, closed))
+}
+
+fn psql_range_literal(start: String, end: String, closed: bool) -> String {
+    let bound = |encoded: String| {
+        let inner = if encoded.starts_with('\'') { encoded[1..encoded.len() - 1].to_owned() } else { encoded };
+        format!("\"{}\"", inner.replace('\\', "\\\\").replace('"', "\\\""))
+    };
+    format!("'[{},{}{}'", bound(start), bound(end), if closed { "]" } else { ")" })
+}
+
+// an element inside a '{...}' array literal, enum variants are stored as text
+fn psql_array_element(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' | '\\' => { result.push('\\'); result.push(ch); }
+            '\'' => result.push_str("''"),
+            MARK => result.push_str(ESCAPED_MARK),
+            _ => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
 pub(crate) trait PSQLArrayToSQLString {
-    fn to_string_with_ft(&self, dialect: SQLDialect, field_type: &Type) -> String;
+    fn to_string_with_ft(&self, dialect: SQLDialect, field_type: &Type) -> Result<String>;
 }
 
 fn field_type_to_psql(field_type: &Type) -> &'static str {
     match field_type {
         Type::Decimal => "decimal",
         Type::Int | Type::Int64 => "integer",
-        Type::Float32 | Type::Float => "double precision",
+        Type::Float32 => "real",
+        Type::Float => "double precision",
         Type::String => "text",
         Type::Bool => "boolean",
         Type::Date => "date",
@@ -146,8 +243,12 @@ fn field_type_to_psql(field_type: &Type) -> &'static str {
 }
 
 impl PSQLArrayToSQLString for Value {
-    fn to_string_with_ft(&self, dialect: SQLDialect, field_type: &Type) -> String {
-        match self {
+    fn to_string_with_ft(&self, dialect: SQLDialect, field_type: &Type) -> Result<String> {
+        check_finite(self, dialect)?;
+        if let Value::Range(range) = self {
+            return range_to_sql_input(ToSQLString::to_string(&range.start.as_ref(), dialect), ToSQLString::to_string(&range.end.as_ref(), dialect), range.closed, dialect);
+        }
+        Ok(match self {
             Value::Array(values) => if values.is_empty() {
                 format!("array[]::{}[]", field_type_to_psql(field_type.as_array().unwrap().unwrap_optional()))
             } else {
@@ -156,7 +257,7 @@ impl PSQLArrayToSQLString for Value {
                 }).join(","))
             },
             _ => ToSQLString::to_string(&self, dialect),
-        }
+        })
     }
 }
 
@@ -198,6 +299,10 @@ impl ToSQLInputWithoutQuotes for &str {
                 } else {
                     result.push_str("''");
                 },
+                // mysql reads backslash escapes in literals unless NO_BACKSLASH_ESCAPES is set,
+                // which the quote escaping above assumes isn't either
+                '\\' if dialect.is_mysql() => result.push_str("\\\\"),
+                MARK => result.push_str(ESCAPED_MARK),
                 _ => result.push(ch)
             }
         }
@@ -216,6 +321,8 @@ impl ToSQLInputDialect for String {
                 } else {
                     result.push_str("''");
                 },
+                '\\' if dialect.is_mysql() => result.push_str("\\\\"),
+                MARK => result.push_str(ESCAPED_MARK),
                 _ => result.push(ch)
             }
         }
@@ -236,6 +343,8 @@ impl ToSQLInputDialect for &str {
                 } else {
                     result.push_str("''");
                 },
+                '\\' if dialect.is_mysql() => result.push_str("\\\\"),
+                MARK => result.push_str(ESCAPED_MARK),
                 _ => result.push(ch)
             }
         }
@@ -251,9 +360,109 @@ impl ToSQLInput for bool {
     }
 }
 
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Int(i) => i.to_string(),
+        Value::Int64(i) => i.to_string(),
+        Value::Decimal(d) => plain_decimal(d),
+        _ => panic!("cannot encode {:?} as string", value),
+    }
+}
+
+// only digits are ever emitted unquoted
+fn is_integer_literal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// the encoders run this before writing a value, see `float_to_sql_input`
+pub(crate) fn check_finite(value: &Value, dialect: SQLDialect) -> Result<()> {
+    if dialect.is_postgres() {
+        return Ok(());
+    }
+    let non_finite = match value {
+        Value::Float(f) => !f.is_finite(),
+        Value::Float32(f) => !f.is_finite(),
+        Value::Array(values) => return values.iter().try_for_each(|v| check_finite(v, dialect)),
+        Value::Dictionary(map) => return map.values().try_for_each(|v| check_finite(v, dialect)),
+        Value::Range(range) => return check_finite(&range.start, dialect).and_then(|_| check_finite(&range.end, dialect)),
+        _ => false,
+    };
+    if non_finite {
+        Err(Error::new(format!("{:?} can't be stored in {:?}, only PostgreSQL supports NaN and infinity", value, dialect)))
+    } else {
+        Ok(())
+    }
+}
+
+// only postgres stores NaN and infinity. `check_finite` rejects them elsewhere before
+// encoding, a value slipping past it becomes NULL rather than invalid sql
+fn float_to_sql_input(value: f64, dialect: SQLDialect) -> String {
+    if value.is_finite() {
+        return value.to_string();
+    }
+    if !dialect.is_postgres() {
+        return "NULL".to_owned();
+    }
+    let literal = if value.is_nan() { "NaN" } else if value.is_sign_positive() { "Infinity" } else { "-Infinity" };
+    format!("'{}'::float8", literal)
+}
+
+fn float32_to_sql_input(value: f32, dialect: SQLDialect) -> String {
+    if value.is_finite() { value.to_string() } else { float_to_sql_input(value as f64, dialect) }
+}
+
+// array literals only exist on postgres and take the bare words
+fn float_array_element(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value.is_sign_positive() { "Infinity".to_owned() } else { "-Infinity".to_owned() }
+    } else {
+        value.to_string()
+    }
+}
+
+fn float32_array_element(value: f32) -> String {
+    if value.is_finite() { value.to_string() } else { float_array_element(value as f64) }
+}
+
+// only postgres has a boolean type, the others store booleans as integers
+fn bool_to_sql_input(value: bool, dialect: SQLDialect) -> String {
+    if dialect.is_postgres() {
+        value.to_sql_input()
+    } else {
+        if value { "1".to_owned() } else { "0".to_owned() }
+    }
+}
+
+// never in scientific notation, 1E+2 isn't a valid numeric literal everywhere
+pub(crate) fn plain_decimal(decimal: &BigDecimal) -> String {
+    let (int, scale) = decimal.normalized().as_bigint_and_exponent();
+    let int = int.to_string();
+    let (sign, digits) = match int.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", int.as_str()),
+    };
+    if digits == "0" {
+        return "0".to_owned();
+    }
+    let scale = scale as isize;
+    let plain = if scale <= 0 {
+        format!("{}{}", digits, "0".repeat(-scale as usize))
+    } else if digits.len() as isize > scale {
+        let point = digits.len() - scale as usize;
+        format!("{}.{}", &digits[..point], &digits[point..])
+    } else {
+        format!("0.{}{}", "0".repeat(scale as usize - digits.len()), digits)
+    };
+    format!("{}{}", sign, plain)
+}
+
 impl ToSQLInputDialect for BigDecimal {
     fn to_sql_input(&self, dialect: SQLDialect) -> String {
-        let result = self.normalized().to_string();
+        let result = plain_decimal(self);
         if dialect == SQLDialect::PostgreSQL {
             result + "::numeric"
         } else {
@@ -308,6 +517,30 @@ impl IfIMode for String {
     }
 }
 
+// the search term of a like pattern, wildcards and the backslash escape are
+// matched literally. mysql reads the backslashes once more in the literal
+pub trait ToLikeInput {
+    fn to_like_input(&self, dialect: SQLDialect) -> String;
+}
+
+impl ToLikeInput for &str {
+    fn to_like_input(&self, dialect: SQLDialect) -> String {
+        let mut escaped = String::with_capacity(self.len());
+        for ch in self.chars() {
+            if ch == '\\' || ch == '%' || ch == '_' {
+                escaped.push('\\');
+            }
+            escaped.push(ch);
+        }
+        escaped.as_str().to_sql_input_without_quotes(dialect)
+    }
+}
+
+// the ESCAPE operand matching `to_like_input`
+pub(crate) fn like_escape(dialect: SQLDialect) -> &'static str {
+    if dialect.is_mysql() { "'\\\\'" } else { "'\\'" }
+}
+
 pub trait ToLike {
     fn to_like(&self, left: bool, right: bool) -> String;
 }
@@ -357,28 +590,131 @@ pub trait SQLEscape {
 
 impl SQLEscape for &str {
     fn is_escaped(&self) -> bool {
-        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`")
+        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`") || self.starts_with("[")
     }
     fn escape(&self, dialect: SQLDialect) -> String {
-        match dialect {
-            SQLDialect::MySQL => format!("`{}`", self),
-            SQLDialect::PostgreSQL => format!("\"{}\"", self),
-            _ => format!("`{}`", self),
-        }
+        dialect.quote(self)
     }
 }
 
 impl SQLEscape for String {
 
     fn is_escaped(&self) -> bool {
-        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`")
+        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`") || self.starts_with("[")
     }
 
     fn escape(&self, dialect: SQLDialect) -> String {
-        match dialect {
-            SQLDialect::MySQL => format!("`{}`", self),
-            SQLDialect::PostgreSQL => format!("\"{}\"", self),
-            _ => format!("`{}`", self),
+        dialect.quote(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_floats_are_postgres_only() {
+        for value in [Value::Float(f64::NAN), Value::Float(f64::INFINITY), Value::Float32(f32::NEG_INFINITY)] {
+            assert!(check_finite(&value, SQLDialect::PostgreSQL).is_ok());
+            for dialect in [SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL] {
+                assert!(check_finite(&value, dialect).is_err());
+            }
         }
+        assert!(check_finite(&Value::Array(vec![Value::Float(1.5), Value::Float(f64::NAN)]), SQLDialect::MySQL).is_err());
+        assert!(check_finite(&Value::Float(1.5), SQLDialect::SQLite).is_ok());
+    }
+
+    #[test]
+    fn non_finite_floats_encode_on_postgres() {
+        assert_eq!(float_to_sql_input(f64::NAN, SQLDialect::PostgreSQL), "'NaN'::float8");
+        assert_eq!(float_to_sql_input(f64::NEG_INFINITY, SQLDialect::PostgreSQL), "'-Infinity'::float8");
+        assert_eq!(float32_to_sql_input(f32::INFINITY, SQLDialect::PostgreSQL), "'Infinity'::float8");
+        assert_eq!(float_to_sql_input(1.5, SQLDialect::MySQL), "1.5");
+    }
+
+    #[test]
+    fn non_finite_floats_fail_to_encode_outside_postgres() {
+        let r#type = Type::Array(Box::new(Type::Float));
+        let value = Value::Array(vec![Value::Float(1.5), Value::Float(f64::INFINITY)]);
+        assert!(value.to_string_with_ft(SQLDialect::MySQL, &r#type).is_err());
+        assert_eq!(value.to_string_with_ft(SQLDialect::PostgreSQL, &r#type).unwrap(), "array[1.5,'Infinity'::float8]");
+        assert_eq!(float_to_sql_input(f64::NAN, SQLDialect::SQLite), "NULL");
+    }
+
+    #[test]
+    fn to_like_wraps_empty_and_short_terms() {
+        assert_eq!("".to_like(false, false), "''");
+        assert_eq!("".to_like(true, true), "'%%'");
+        assert_eq!("x".to_like(true, false), "'%x'");
+        assert_eq!("x".to_like(false, true), "'x%'");
+        assert_eq!("é".to_like(true, true), "'%é%'");
+        assert_eq!("é".to_owned().to_like(false, true), "'é%'");
+    }
+
+    #[test]
+    fn like_input_escapes_wildcards() {
+        assert_eq!("50%_off".to_like_input(SQLDialect::PostgreSQL), "50\\%\\_off");
+        assert_eq!("é".to_like_input(SQLDialect::SQLite), "é");
+    }
+
+    #[test]
+    fn array_elements_escape_quotes_and_backslashes() {
+        assert_eq!(psql_array_element("a\"b\\c'd"), "\"a\\\"b\\\\c''d\"");
+        let r#type = Type::Array(Box::new(Type::String));
+        let value = Value::Array(vec![Value::String("x".to_owned()), Value::String("y".to_owned())]);
+        assert_eq!(value.to_sql_string_array_arg(&r#type, false, SQLDialect::PostgreSQL), "'{\"x\",\"y\"}'");
+    }
+
+    #[test]
+    fn postgres_arrays_keep_their_element_type() {
+        let r#type = Type::Array(Box::new(Type::Int64));
+        assert_eq!(Value::Array(vec![]).to_string_with_ft(SQLDialect::PostgreSQL, &r#type).unwrap(), "array[]::integer[]");
+        assert_eq!(Value::Array(vec![Value::Int64(1), Value::Int64(2)]).to_string_with_ft(SQLDialect::PostgreSQL, &r#type).unwrap(), "array[1,2]");
+        assert_eq!(Value::Int64(1).to_string_with_ft(SQLDialect::PostgreSQL, &Type::Int64).unwrap(), "1");
+    }
+
+    #[test]
+    fn ranges_quote_their_bounds() {
+        assert_eq!(range_to_sql_input("1".to_owned(), "5".to_owned(), false, SQLDialect::PostgreSQL).unwrap(), "'[\"1\",\"5\")'");
+        assert_eq!(range_to_sql_input("'a\"b'".to_owned(), "'c'".to_owned(), true, SQLDialect::PostgreSQL).unwrap(), "'[\"a\\\"b\",\"c\"]'");
+        assert!(range_to_sql_input("1".to_owned(), "5".to_owned(), false, SQLDialect::MySQL).is_err());
+    }
+
+    #[test]
+    fn decimals_are_never_scientific() {
+        let decimal = |s: &str| s.parse::<BigDecimal>().unwrap();
+        assert_eq!(plain_decimal(&decimal("1E+2")), "100");
+        assert_eq!(plain_decimal(&decimal("-0.00120")), "-0.0012");
+        assert_eq!(plain_decimal(&decimal("0.000")), "0");
+        assert_eq!(decimal("12.5").to_sql_input(SQLDialect::PostgreSQL), "12.5::numeric");
+    }
+
+    #[test]
+    fn strings_escape_quotes_per_dialect() {
+        assert_eq!("it's".to_sql_input(SQLDialect::PostgreSQL), "'it''s'");
+        assert_eq!("it's".to_sql_input(SQLDialect::MySQL), "'it\\'s'");
+        assert_eq!("a\\b".to_sql_input(SQLDialect::MySQL), "'a\\\\b'");
+        assert_eq!("a\\b".to_sql_input(SQLDialect::SQLite), "'a\\b'");
+    }
+
+    #[test]
+    fn numbers_for_string_columns_stay_text() {
+        assert_eq!(Value::Int(7).to_sql_string(&Type::String, false, SQLDialect::PostgreSQL), "'7'");
+        assert_eq!(Value::Null.to_sql_string(&Type::String, true, SQLDialect::PostgreSQL), "NULL");
+        assert_eq!(Value::String("123456789012345678901".to_owned()).to_sql_string(&Type::Int64, false, SQLDialect::MySQL), "123456789012345678901");
+    }
+
+    #[test]
+    fn bools_are_integers_outside_postgres() {
+        assert_eq!(ToSQLString::to_string(&&Value::Bool(true), SQLDialect::PostgreSQL), "TRUE");
+        assert_eq!(ToSQLString::to_string(&&Value::Bool(true), SQLDialect::MySQL), "1");
+        assert_eq!(ToSQLString::to_string(&&Value::Bool(false), SQLDialect::SQLite), "0");
+    }
+
+    #[test]
+    fn bits_are_binary_literals() {
+        assert_eq!(bit_to_sql_input(&Value::Int(5)), "b'101'");
+        assert_eq!(bit_to_sql_input(&Value::Bool(true)), "b'1'");
+        assert_eq!(bit_to_sql_input(&Value::Null), "NULL");
     }
 }
@@ -1,2 +1,2 @@
 pub(crate) mod decode;
-pub(crate) mod encode;
+pub mod encode;
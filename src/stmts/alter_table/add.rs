@@ -9,9 +9,8 @@ pub struct SQLAlterTableAddStatement {
 
 impl ToSQLString for SQLAlterTableAddStatement {
     fn to_string(&self, dialect: SQLDialect) -> String {
-        let table = &self.table;
+        let table = dialect.quote(&self.table);
         let def = self.column_def.to_string(dialect);
-        let escape = if dialect == SQLDialect::PostgreSQL { "\"" } else { "`" };
-        format!("ALTER TABLE {escape}{table}{escape} ADD {def}")
+        format!("ALTER TABLE {table} ADD {def}")
     }
 }
@@ -8,9 +8,8 @@ pub struct SQLAlterTableDropColumnStatement {
 
 impl ToSQLString for SQLAlterTableDropColumnStatement {
     fn to_string(&self, dialect: SQLDialect) -> String {
-        let table = &self.table;
-        let column = &self.column;
-        let escape = dialect.escape();
-        format!("ALTER TABLE {escape}{table}{escape} DROP COLUMN {escape}{column}{escape}")
+        let table = dialect.quote(&self.table);
+        let column = dialect.quote(&self.column);
+        format!("ALTER TABLE {table} DROP COLUMN {column}")
     }
 }
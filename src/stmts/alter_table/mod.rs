@@ -24,3 +24,21 @@ impl SQLAlterTableStatement {
         SQLAlterTableAddStatement { table: self.table.clone(), column_def }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use teo_runtime::database::r#type::DatabaseType;
+    use teo_runtime::database::mysql::r#type::MySQLType;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::ToSQLString;
+    use crate::stmts::SQL;
+    use super::*;
+
+    #[test]
+    fn add_and_drop_column_quote_per_dialect() {
+        let column = SQLColumn::builder("bio", DatabaseType::MySQLType(MySQLType::Text)).not_null(true).build();
+        assert_eq!(SQL::alter_table("users").add(column.clone()).to_string(SQLDialect::MySQL), "ALTER TABLE `users` ADD `bio` TEXT NOT NULL");
+        assert_eq!(SQL::alter_table("users").modify(column).to_string(SQLDialect::MySQL), "ALTER TABLE `users` MODIFY `bio` TEXT NOT NULL");
+        assert_eq!(SQL::alter_table("users").drop_column("bio").to_string(SQLDialect::PostgreSQL), "ALTER TABLE \"users\" DROP COLUMN \"bio\"");
+    }
+}
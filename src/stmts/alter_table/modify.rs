@@ -9,16 +9,15 @@ pub struct SQLAlterTableModifyStatement {
 
 impl ToSQLString for SQLAlterTableModifyStatement {
     fn to_string(&self, dialect: SQLDialect) -> String {
-        let table = &self.table;
+        let table = dialect.quote(&self.table);
         let def = self.column.to_string(dialect);
-        let escape = dialect.escape();
         if dialect == SQLDialect::SQLite {
-            format!("ALTER TABLE {escape}{table}{escape} ({def})")
+            format!("ALTER TABLE {table} ({def})")
         } else if dialect == SQLDialect::PostgreSQL {
-            let c_name = self.column.name();
-            format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{c_name}{escape} TYPE column_definition;")
+            let c_name = dialect.quote(self.column.name());
+            format!("ALTER TABLE {table} ALTER COLUMN {c_name} TYPE column_definition;")
         } else {
-            format!("ALTER TABLE {escape}{table}{escape} MODIFY {def}")
+            format!("ALTER TABLE {table} MODIFY {def}")
         }
     }
 }
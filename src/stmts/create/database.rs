@@ -18,7 +18,7 @@ impl ToSQLString for SQLCreateDatabaseStatement {
         let database = &self.database;
         let if_not_exists = if self.if_not_exists { " IF NOT EXISTS" } else { "" };
         if dialect == SQLDialect::PostgreSQL {
-            format!("CREATE DATABASE{if_not_exists} {database};")
+            format!("CREATE DATABASE{if_not_exists} \"{database}\";")
         } else {
             format!("CREATE DATABASE{if_not_exists} `{database}`;")
         }
@@ -1,12 +1,12 @@
 use crate::stmts::create::database::SQLCreateDatabaseStatement;
 use crate::stmts::create::index::SQLCreateIndexStatement;
-use crate::stmts::create::table::SQLCreateTableStatement;
+use crate::stmts::create::table::{SQLCreateTableStatement, SQLTableOptions};
 
 pub mod database;
 pub mod table;
 pub mod index;
 
-pub(crate) struct SQLCreateStatement { }
+pub struct SQLCreateStatement { }
 
 impl SQLCreateStatement {
 
@@ -14,8 +14,8 @@ impl SQLCreateStatement {
         SQLCreateDatabaseStatement { database: database.into(), if_not_exists: false }
     }
 
-    pub(crate) fn table(&self, table: impl Into<String>) -> SQLCreateTableStatement {
-        SQLCreateTableStatement { table: table.into(), if_not_exists: false, columns: vec![], primary: None }
+    pub fn table(&self, table: impl Into<String>) -> SQLCreateTableStatement {
+        SQLCreateTableStatement { table: table.into(), if_not_exists: false, columns: vec![], primary: None, auto_increment_start: None, options: SQLTableOptions::default() }
     }
 
     pub(crate) fn index(&self, index: impl Into<String>) -> SQLCreateIndexStatement {
@@ -1,37 +1,114 @@
 use array_tool::vec::Join;
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{ToSQLInputDialect, ToSQLString};
 use teo_runtime::model::Index;
 use crate::exts::index::IndexExt;
 
-pub(crate) struct SQLCreateTableStatement {
+// each option only applies to its own database and is ignored by the others
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SQLTableOptions {
+    pub engine: Option<String>,
+    pub charset: Option<String>,
+    pub collation: Option<String>,
+    pub without_rowid: bool,
+    pub strict: bool,
+    pub tablespace: Option<String>,
+}
+
+impl SQLTableOptions {
+
+    fn to_sql_string(&self, dialect: SQLDialect) -> String {
+        let mut options: Vec<String> = vec![];
+        match dialect {
+            SQLDialect::MySQL => {
+                if let Some(engine) = &self.engine {
+                    options.push(format!(" ENGINE={engine}"));
+                }
+                if let Some(charset) = &self.charset {
+                    options.push(format!(" DEFAULT CHARSET={charset}"));
+                }
+                if let Some(collation) = &self.collation {
+                    options.push(format!(" COLLATE={collation}"));
+                }
+                options.join("")
+            }
+            SQLDialect::SQLite => {
+                if self.without_rowid {
+                    options.push("WITHOUT ROWID".to_owned());
+                }
+                if self.strict {
+                    options.push("STRICT".to_owned());
+                }
+                if options.is_empty() { "".to_owned() } else { " ".to_owned() + &options.join(", ") }
+            }
+            SQLDialect::PostgreSQL => {
+                self.tablespace.as_ref().map(|t| format!(" TABLESPACE \"{t}\"")).unwrap_or_default()
+            }
+            _ => "".to_owned(),
+        }
+    }
+}
+
+pub struct SQLCreateTableStatement {
     pub(crate) table: String,
     pub(crate) if_not_exists: bool,
     pub(crate) columns: Vec<SQLColumn>,
     pub(crate) primary: Option<Index>,
+    pub(crate) auto_increment_start: Option<i64>,
+    pub(crate) options: SQLTableOptions,
 }
 
 impl SQLCreateTableStatement {
-    pub(crate) fn if_not_exists(&mut self) -> &mut Self {
+    pub fn if_not_exists(&mut self) -> &mut Self {
         self.if_not_exists = true;
         self
     }
 
-    pub(crate) fn column(&mut self, def: SQLColumn) -> &mut Self {
+    pub fn column(&mut self, def: SQLColumn) -> &mut Self {
         self.columns.push(def);
         self
     }
 
-    pub(crate) fn columns(&mut self, defs: Vec<SQLColumn>) -> &mut Self {
+    pub fn columns(&mut self, defs: Vec<SQLColumn>) -> &mut Self {
         self.columns.extend(defs);
         self
     }
 
-    pub(crate) fn primary(&mut self, index: Index) -> &mut Self {
+    pub fn primary(&mut self, index: Index) -> &mut Self {
         self.primary = Some(index);
         self
     }
+
+    pub fn auto_increment_start(&mut self, start: i64) -> &mut Self {
+        self.auto_increment_start = Some(start);
+        self
+    }
+
+    pub fn options(&mut self, options: SQLTableOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    // mysql sets the start value inline in create table
+    pub(crate) fn auto_increment_start_stmts(&self, dialect: SQLDialect) -> Vec<String> {
+        let Some(start) = self.auto_increment_start else { return vec![] };
+        let Some(column) = self.columns.iter().find(|c| c.auto_increment()) else { return vec![] };
+        let table_name = self.table.as_str();
+        match dialect {
+            SQLDialect::PostgreSQL => {
+                // the sequence name is truncated and deduplicated by postgres, ask for it
+                let table = dialect.quote(table_name);
+                vec![format!("SELECT setval(pg_get_serial_sequence({}, {}), {start}, false);", table.as_str().to_sql_input(dialect), column.name().to_sql_input(dialect))]
+            }
+            // only an AUTOINCREMENT primary key has a row in sqlite_sequence, other
+            // rowid tables continue after their largest key
+            SQLDialect::SQLite if column.primary_key() => {
+                vec![format!("INSERT INTO sqlite_sequence (name, seq) VALUES ({}, {});", table_name.to_sql_input(dialect), start.saturating_sub(1))]
+            }
+            _ => vec![],
+        }
+    }
 }
 
 impl ToSQLString for SQLCreateTableStatement {
@@ -47,10 +124,68 @@ impl ToSQLString for SQLCreateTableStatement {
             }).collect();
             columns += &format!(", PRIMARY KEY ({})", fields.join(","));
         }
+        let options = self.options.to_sql_string(dialect);
         if dialect == SQLDialect::PostgreSQL {
-            format!("CREATE TABLE{if_not_exists} \"{table_name}\"( {columns} );")
+            format!("CREATE TABLE{if_not_exists} \"{table_name}\"( {columns} ){options};")
+        } else if dialect == SQLDialect::MySQL && self.auto_increment_start.is_some() {
+            let start = self.auto_increment_start.unwrap();
+            format!("CREATE TABLE{if_not_exists} `{table_name}`( {columns} ) AUTO_INCREMENT = {start}{options};")
         } else {
-            format!("CREATE TABLE{if_not_exists} `{table_name}`( {columns} );")
+            format!("CREATE TABLE{if_not_exists} `{table_name}`( {columns} ){options};")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use teo_runtime::database::r#type::DatabaseType;
+    use teo_runtime::database::mysql::r#type::MySQLType;
+    use teo_runtime::database::postgres::r#type::PostgreSQLType;
+    use crate::stmts::SQL;
+    use super::*;
+
+    #[test]
+    fn create_table_renders_columns() {
+        let mut stmt = SQL::create().table("users");
+        stmt.if_not_exists()
+            .column(SQLColumn::builder("id", DatabaseType::PostgreSQLType(PostgreSQLType::Integer)).auto_increment(true).not_null(true).primary_key(true).build())
+            .column(SQLColumn::builder("name", DatabaseType::PostgreSQLType(PostgreSQLType::Text)).collation("C").default("'x'").build());
+        assert_eq!(stmt.to_string(SQLDialect::PostgreSQL), "CREATE TABLE IF NOT EXISTS \"users\"( \"id\" SERIAL NOT NULL PRIMARY KEY, \"name\" TEXT COLLATE \"C\" DEFAULT 'x' NULL );");
+    }
+
+    #[test]
+    fn mysql_starts_auto_increment_inline() {
+        let mut stmt = SQL::create().table("posts");
+        stmt.column(SQLColumn::builder("body", DatabaseType::MySQLType(MySQLType::Text)).build()).auto_increment_start(100);
+        assert_eq!(stmt.to_string(SQLDialect::MySQL), "CREATE TABLE `posts`( `body` TEXT NULL ) AUTO_INCREMENT = 100;");
+        assert!(stmt.auto_increment_start_stmts(SQLDialect::MySQL).is_empty());
+    }
+
+    #[test]
+    fn auto_increment_starts_after_create_table() {
+        let mut stmt = SQL::create().table("o'neil");
+        stmt.column(SQLColumn::builder("id", DatabaseType::PostgreSQLType(PostgreSQLType::Integer)).auto_increment(true).not_null(true).primary_key(true).build()).auto_increment_start(100);
+        assert_eq!(stmt.auto_increment_start_stmts(SQLDialect::PostgreSQL), vec!["SELECT setval(pg_get_serial_sequence('\"o''neil\"', 'id'), 100, false);".to_owned()]);
+        assert_eq!(stmt.auto_increment_start_stmts(SQLDialect::SQLite), vec!["INSERT INTO sqlite_sequence (name, seq) VALUES ('o''neil', 99);".to_owned()]);
+        // without an inline primary key the column isn't AUTOINCREMENT, there's no sequence row
+        let mut stmt = SQL::create().table("posts");
+        stmt.column(SQLColumn::builder("id", DatabaseType::PostgreSQLType(PostgreSQLType::Integer)).auto_increment(true).not_null(true).build()).auto_increment_start(100);
+        assert!(stmt.auto_increment_start_stmts(SQLDialect::SQLite).is_empty());
+    }
+
+    #[test]
+    fn options_only_apply_to_their_database() {
+        let options = SQLTableOptions {
+            engine: Some("InnoDB".to_owned()),
+            charset: Some("utf8mb4".to_owned()),
+            without_rowid: true,
+            strict: true,
+            tablespace: Some("fast".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(options.to_sql_string(SQLDialect::MySQL), " ENGINE=InnoDB DEFAULT CHARSET=utf8mb4");
+        assert_eq!(options.to_sql_string(SQLDialect::SQLite), " WITHOUT ROWID, STRICT");
+        assert_eq!(options.to_sql_string(SQLDialect::PostgreSQL), " TABLESPACE \"fast\"");
+        assert_eq!(options.to_sql_string(SQLDialect::MSSQL), "");
+    }
+}
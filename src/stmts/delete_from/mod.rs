@@ -4,6 +4,7 @@ use crate::schema::value::encode::ToSQLString;
 pub struct SQLDeleteFromStatement<'a> {
     pub(crate) from: &'a str,
     pub(crate) r#where: Option<String>,
+    pub(crate) returning: Vec<String>,
 }
 
 impl<'a> SQLDeleteFromStatement<'a> {
@@ -12,6 +13,11 @@ impl<'a> SQLDeleteFromStatement<'a> {
         self.r#where = Some(r#where);
         self
     }
+
+    pub fn returning<S>(&mut self, keys: &Vec<S>) -> &mut Self where S: Clone + Into<String> {
+        self.returning = keys.iter().map(|k| k.clone().into()).collect();
+        self
+    }
 }
 
 impl<'a> ToSQLString for SQLDeleteFromStatement<'a> {
@@ -25,7 +31,24 @@ impl<'a> ToSQLString for SQLDeleteFromStatement<'a> {
         } else {
             "".to_owned()
         };
-        let escape = dialect.escape();
-        format!("DELETE FROM {}{}{}{}", escape, self.from, escape, r#where)
+        let returning = if self.returning.is_empty() {
+            "".to_owned()
+        } else {
+            " RETURNING ".to_owned() + &self.returning.join(",")
+        };
+        format!("DELETE FROM {}{}{}", dialect.quote(self.from), r#where, returning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stmts::SQL;
+    use super::*;
+
+    #[test]
+    fn table_is_quoted_per_dialect() {
+        assert_eq!(SQL::delete_from("users").to_string(SQLDialect::PostgreSQL), "DELETE FROM \"users\"");
+        assert_eq!(SQL::delete_from("users").to_string(SQLDialect::MSSQL), "DELETE FROM [users]");
+        assert_eq!(SQL::delete_from("users").r#where("`id` = 1".to_owned()).to_string(SQLDialect::MySQL), "DELETE FROM `users` WHERE `id` = 1");
     }
 }
@@ -14,9 +14,21 @@ impl SQLDropDatabaseStatement {
 }
 
 impl ToSQLString for SQLDropDatabaseStatement {
-    fn to_string(&self, _dialect: SQLDialect) -> String {
-        let database = &self.database;
+    fn to_string(&self, dialect: SQLDialect) -> String {
+        let database = dialect.quote(&self.database);
         let if_exists = if self.if_exists { " IF EXISTS" } else { "" };
-        format!("DROP DATABASE{if_exists} `{database}`;")
+        format!("DROP DATABASE{if_exists} {database};")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_is_quoted_per_dialect() {
+        let mut stmt = SQLDropDatabaseStatement { database: "app".to_owned(), if_exists: false };
+        assert_eq!(stmt.to_string(SQLDialect::MSSQL), "DROP DATABASE [app];");
+        assert_eq!(stmt.if_exists().to_string(SQLDialect::PostgreSQL), "DROP DATABASE IF EXISTS \"app\";");
     }
 }
@@ -4,7 +4,9 @@ use crate::schema::value::encode::ToSQLString;
 pub(crate) struct SQLInsertIntoStatement<'a> {
     pub(crate) table: &'a str,
     pub(crate) values: Vec<(&'a str, &'a str)>,
+    pub(crate) rows: Vec<Vec<&'a str>>,
     pub(crate) returning: Vec<String>,
+    pub(crate) on_conflict: Option<(Vec<String>, Vec<(String, String)>)>,
 }
 
 impl<'a> SQLInsertIntoStatement<'a> {
@@ -18,10 +20,30 @@ impl<'a> SQLInsertIntoStatement<'a> {
         self
     }
 
+    // further value tuples in the column order of `values`
+    pub(crate) fn row(&mut self, row: Vec<&'a str>) -> &mut Self {
+        self.rows.push(row);
+        self
+    }
+
     pub(crate) fn returning<S>(&mut self, keys: &Vec<S>) -> &mut Self where S: Clone + Into<String> {
         self.returning = keys.iter().map(|k| k.clone().into()).collect();
         self
     }
+
+    pub(crate) fn on_conflict(&mut self, columns: Vec<String>, updates: Vec<(String, String)>) -> &mut Self {
+        self.on_conflict = Some((columns, updates));
+        self
+    }
+
+    // the value the conflicting insert tried to write, usable in the update list
+    pub(crate) fn excluded(column: &str, dialect: SQLDialect) -> String {
+        if dialect.is_mysql() {
+            format!("VALUES({})", dialect.quote(column))
+        } else {
+            format!("EXCLUDED.{}", dialect.quote(column))
+        }
+    }
 }
 
 impl<'a> ToSQLString for SQLInsertIntoStatement<'a> {
@@ -32,14 +54,65 @@ impl<'a> ToSQLString for SQLInsertIntoStatement<'a> {
             keys.push(k);
             values.push(v);
         }
-        if dialect == SQLDialect::PostgreSQL {
-            format!("INSERT INTO \"{}\"({}) VALUES({}){};", self.table, keys.iter().map(|k| format!("\"{}\"", k)).collect::<Vec<String>>().join(","), values.join(","), if self.returning.is_empty() {
-                "".to_owned()
+        let values = std::iter::once(values.join(",")).chain(self.rows.iter().map(|r| r.join(","))).collect::<Vec<String>>().join("),(");
+        let on_conflict = if let Some((columns, updates)) = &self.on_conflict {
+            let updates = updates.iter().map(|(k, v)| format!("{} = {v}", dialect.quote(k))).collect::<Vec<String>>().join(",");
+            if dialect == SQLDialect::MySQL {
+                if updates.is_empty() {
+                    // mysql doesn't have do nothing, assign a column to itself instead
+                    let column = dialect.quote(columns.first().unwrap());
+                    format!(" ON DUPLICATE KEY UPDATE {column} = {column}")
+                } else {
+                    format!(" ON DUPLICATE KEY UPDATE {updates}")
+                }
             } else {
-                "  RETURNING ".to_owned() + &self.returning.join(",")
-            })
+                let columns = columns.iter().map(|c| dialect.quote(c)).collect::<Vec<String>>().join(",");
+                if updates.is_empty() {
+                    format!(" ON CONFLICT ({columns}) DO NOTHING")
+                } else {
+                    format!(" ON CONFLICT ({columns}) DO UPDATE SET {updates}")
+                }
+            }
         } else {
-            format!("INSERT INTO `{}`({}) VALUES({});", self.table, keys.iter().map(|k| format!("`{k}`")).collect::<Vec<String>>().join(","), values.join(","))
-        }
+            "".to_owned()
+        };
+        let keys = keys.iter().map(|k| dialect.quote(k)).collect::<Vec<String>>().join(",");
+        // sql server has no RETURNING, the other databases which lack it aren't asked for it
+        let returning = if self.returning.is_empty() || dialect.is_mssql() {
+            "".to_owned()
+        } else {
+            " RETURNING ".to_owned() + &self.returning.join(",")
+        };
+        format!("INSERT INTO {}({}) VALUES({}){}{};", dialect.quote(self.table), keys, values, on_conflict, returning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stmts::SQL;
+    use super::*;
+
+    #[test]
+    fn excluded_follows_the_dialect() {
+        assert_eq!(SQLInsertIntoStatement::excluded("n", SQLDialect::MySQL), "VALUES(`n`)");
+        assert_eq!(SQLInsertIntoStatement::excluded("n", SQLDialect::PostgreSQL), "EXCLUDED.\"n\"");
+    }
+
+    #[test]
+    fn on_conflict_quotes_columns() {
+        let sql = SQL::insert_into("users").values(vec![("id", "1"), ("n", "2")]).on_conflict(vec!["id".to_owned()], vec![("n".to_owned(), "3".to_owned())]).to_string(SQLDialect::SQLite);
+        assert_eq!(sql, "INSERT INTO `users`(`id`,`n`) VALUES(1,2) ON CONFLICT (`id`) DO UPDATE SET `n` = 3;");
+        let sql = SQL::insert_into("users").values(vec![("id", "1")]).on_conflict(vec!["id".to_owned()], vec![]).to_string(SQLDialect::MySQL);
+        assert_eq!(sql, "INSERT INTO `users`(`id`) VALUES(1) ON DUPLICATE KEY UPDATE `id` = `id`;");
+    }
+
+    #[test]
+    fn returning_is_rendered_where_asked() {
+        let sql = SQL::insert_into("users").values(vec![("n", "2")]).returning(&vec!["\"id\""]).to_string(SQLDialect::PostgreSQL);
+        assert_eq!(sql, "INSERT INTO \"users\"(\"n\") VALUES(2) RETURNING \"id\";");
+        let sql = SQL::insert_into("users").values(vec![("n", "2")]).returning(&vec!["`id`"]).to_string(SQLDialect::SQLite);
+        assert_eq!(sql, "INSERT INTO `users`(`n`) VALUES(2) RETURNING `id`;");
+        let sql = SQL::insert_into("users").values(vec![("n", "2")]).returning(&vec!["[id]"]).to_string(SQLDialect::MSSQL);
+        assert_eq!(sql, "INSERT INTO [users]([n]) VALUES(2);");
     }
 }
@@ -8,6 +8,10 @@ use crate::stmts::r#use::SQLUseStatement;
 use crate::stmts::select::SQLSelectStatement;
 use crate::stmts::show::SQLShowStatement;
 use crate::stmts::update::SQLUpdateStatement;
+use quaint_forked::Value as QuaintValue;
+use crate::query::params::bind_params;
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::ToSQLString;
 
 pub mod create;
 pub mod drop;
@@ -20,10 +24,10 @@ pub mod select;
 pub mod update;
 pub mod delete_from;
 
-pub(crate) struct SQL { }
+pub struct SQL { }
 
 impl SQL {
-    pub(crate) fn create() -> SQLCreateStatement {
+    pub fn create() -> SQLCreateStatement {
         SQLCreateStatement { }
     }
 
@@ -48,7 +52,7 @@ impl SQL {
     }
 
     pub(crate) fn insert_into(table: &str) -> SQLInsertIntoStatement {
-        SQLInsertIntoStatement { table, values: vec![], returning: vec![] }
+        SQLInsertIntoStatement { table, values: vec![], rows: vec![], returning: vec![], on_conflict: None }
     }
 
     pub(crate) fn update(table: &str) -> SQLUpdateStatement {
@@ -56,10 +60,48 @@ impl SQL {
     }
 
     pub(crate) fn delete_from(from: &str) -> SQLDeleteFromStatement {
-        SQLDeleteFromStatement { from, r#where: None }
+        SQLDeleteFromStatement { from, r#where: None, returning: vec![] }
     }
 
     pub(crate) fn select<'a>(columns: Option<&'a Vec<&'a str>>, from: &'a str) -> SQLSelectStatement<'a> {
-        SQLSelectStatement { columns, from, r#where: None, order_by: None, limit: None, left_join: None, inner_join: None }
+        SQLSelectStatement { columns, from, r#where: None, order_by: None, limit: None, left_join: None, inner_join: None, lock: None }
+    }
+}
+
+// the statement with the values bound through `query::params` as parameters
+pub(crate) trait ToBoundSQL: ToSQLString {
+    fn to_bound_sql(&self, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+        bind_params(&self.to_string(dialect), dialect)
+    }
+}
+
+impl ToBoundSQL for SQLInsertIntoStatement<'_> { }
+
+impl ToBoundSQL for SQLUpdateStatement<'_> { }
+
+impl ToBoundSQL for SQLDeleteFromStatement<'_> { }
+
+impl ToBoundSQL for SQLSelectStatement<'_> { }
+
+#[cfg(test)]
+mod tests {
+    use teo_parser::r#type::Type;
+    use teo_runtime::value::Value;
+    use crate::query::params::{bind, bind_text};
+    use super::*;
+
+    #[test]
+    fn update_binds_values_in_statement_order() {
+        let dialect = SQLDialect::PostgreSQL;
+        let name = bind_text("o'brien", dialect);
+        let r#where = format!("\"id\" = {}", bind(&Value::Int64(7), &Type::Int64, false, dialect).unwrap());
+        let (sql, params) = SQL::update("users").value(("name", name.as_str())).r#where(&r#where).to_bound_sql(dialect);
+        if cfg!(feature = "parameterized") {
+            assert_eq!(sql, "UPDATE \"users\" SET \"name\" = $1 WHERE \"id\" = $2;");
+            assert_eq!(params, vec![QuaintValue::text("o'brien"), QuaintValue::int64(7)]);
+        } else {
+            assert_eq!(sql, "UPDATE \"users\" SET \"name\" = 'o''brien' WHERE \"id\" = 7;");
+            assert!(params.is_empty());
+        }
     }
 }
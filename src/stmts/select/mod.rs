@@ -5,6 +5,47 @@ use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub mod r#where;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SQLLockMode {
+    Update,
+    Share,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SQLLockWait {
+    Wait,
+    NoWait,
+    SkipLocked,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SQLLock {
+    pub mode: SQLLockMode,
+    pub wait: SQLLockWait,
+}
+
+// sqlite and sql server have no locking clause, locked reads are rejected
+// before a statement is built there
+impl ToSQLString for SQLLock {
+    fn to_string(&self, dialect: SQLDialect) -> String {
+        if dialect.is_sqlite() || dialect.is_mssql() {
+            return "".to_owned();
+        }
+        let mode = match self.mode {
+            SQLLockMode::Update => " FOR UPDATE",
+            // FOR SHARE only exists since mysql 8, the old form is understood by every version
+            SQLLockMode::Share if dialect.is_mysql() && self.wait == SQLLockWait::Wait => " LOCK IN SHARE MODE",
+            SQLLockMode::Share => " FOR SHARE",
+        };
+        let wait = match self.wait {
+            SQLLockWait::Wait => "",
+            SQLLockWait::NoWait => " NOWAIT",
+            SQLLockWait::SkipLocked => " SKIP LOCKED",
+        };
+        format!("{mode}{wait}")
+    }
+}
+
 pub struct SQLSelectStatement<'a> {
     pub(crate) columns: Option<&'a Vec<&'a str>>,
     pub(crate) from: &'a str,
@@ -12,7 +53,8 @@ pub struct SQLSelectStatement<'a> {
     pub(crate) left_join: Option<String>,
     pub(crate) inner_join: Option<String>,
     pub(crate) order_by: Option<String>,
-    pub(crate) limit: Option<(u64, u64)>,
+    pub(crate) limit: Option<(Option<u64>, u64)>,
+    pub(crate) lock: Option<SQLLock>,
 }
 
 impl<'a> SQLSelectStatement<'a> {
@@ -42,7 +84,18 @@ impl<'a> SQLSelectStatement<'a> {
     }
 
     pub fn limit(&mut self, limit: u64, skip: u64) -> &mut Self {
-        self.limit = Some((limit, skip));
+        self.limit = Some((Some(limit), skip));
+        self
+    }
+
+    // skips rows without limiting how many follow
+    pub fn offset(&mut self, skip: u64) -> &mut Self {
+        self.limit = Some((None, skip));
+        self
+    }
+
+    pub fn lock(&mut self, lock: SQLLock) -> &mut Self {
+        self.lock = Some(lock);
         self
     }
 }
@@ -81,20 +134,59 @@ impl<'a> ToSQLString for SQLSelectStatement<'a> {
             "".to_owned()
         };
         let limit = if let Some(limit) = &self.limit {
-            if dialect == SQLDialect::PostgreSQL {
-                format!(" LIMIT {} OFFSET {}", limit.0, limit.1)
-            } else {
-                format!(" LIMIT {},{}", limit.1, limit.0)
+            match (dialect, limit.0) {
+                (SQLDialect::PostgreSQL, Some(take)) => format!(" LIMIT {} OFFSET {}", take, limit.1),
+                (SQLDialect::PostgreSQL, None) => format!(" OFFSET {}", limit.1),
+                (SQLDialect::MSSQL, Some(take)) => format!(" OFFSET {} ROWS FETCH NEXT {} ROWS ONLY", limit.1, take),
+                (SQLDialect::MSSQL, None) => format!(" OFFSET {} ROWS", limit.1),
+                // a negative limit is no limit in sqlite
+                (SQLDialect::SQLite, None) => format!(" LIMIT -1 OFFSET {}", limit.1),
+                // mysql has no offset without a limit, the largest one stands for all rows
+                (_, None) => format!(" LIMIT {},18446744073709551615", limit.1),
+                (_, Some(take)) => format!(" LIMIT {},{}", limit.1, take),
             }
         } else {
             "".to_owned()
         };
-        let escape = dialect.escape();
-        let from_escaped = if self.from.starts_with("'") || self.from.starts_with("\"") || self.from.starts_with("`") {
+        let lock = if let Some(lock) = &self.lock {
+            lock.to_string(dialect)
+        } else {
+            "".to_owned()
+        };
+        let from_escaped = if self.from.is_escaped() {
             Cow::Borrowed(self.from)
         } else {
-            Cow::Owned(format!("{}{}{}", escape, self.from, escape))
+            Cow::Owned(dialect.quote(self.from))
         };
-        format!("SELECT {columns} from {}{}{}{}{}{}", from_escaped, left_join, inner_join, r#where, order_by, limit)
+        format!("SELECT {columns} from {}{}{}{}{}{}{}", from_escaped, left_join, inner_join, r#where, order_by, limit, lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stmts::SQL;
+    use super::*;
+
+    #[test]
+    fn lock_follows_the_dialect() {
+        let share = SQLLock { mode: SQLLockMode::Share, wait: SQLLockWait::Wait };
+        assert_eq!(share.to_string(SQLDialect::PostgreSQL), " FOR SHARE");
+        assert_eq!(share.to_string(SQLDialect::MySQL), " LOCK IN SHARE MODE");
+        let skip = SQLLock { mode: SQLLockMode::Update, wait: SQLLockWait::SkipLocked };
+        assert_eq!(skip.to_string(SQLDialect::MySQL), " FOR UPDATE SKIP LOCKED");
+        assert_eq!(skip.to_string(SQLDialect::SQLite), "");
+        assert_eq!(skip.to_string(SQLDialect::MSSQL), "");
+    }
+
+    #[test]
+    fn select_renders_limit_per_dialect() {
+        let columns = vec!["id"];
+        let mut stmt = SQL::select(Some(&columns), "users");
+        stmt.limit(10, 20);
+        assert_eq!(stmt.to_string(SQLDialect::PostgreSQL), "SELECT \"id\" from \"users\" LIMIT 10 OFFSET 20");
+        assert_eq!(stmt.to_string(SQLDialect::MySQL), "SELECT `id` from `users` LIMIT 20,10");
+        assert_eq!(stmt.to_string(SQLDialect::MSSQL), "SELECT [id] from [users] OFFSET 20 ROWS FETCH NEXT 10 ROWS ONLY");
+        stmt.offset(5);
+        assert_eq!(stmt.to_string(SQLDialect::SQLite), "SELECT `id` from `users` LIMIT -1 OFFSET 5");
     }
 }
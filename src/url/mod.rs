@@ -67,6 +67,10 @@ pub(crate) mod url_utils {
         url
     }
 
+    pub(crate) fn psql_schema(url: &Url) -> String {
+        url.query_pairs().find(|(k, _)| k == "schema").map(|(_, v)| v.to_string()).unwrap_or("public".to_owned())
+    }
+
     pub(crate) fn remove_db_path(dialect: SQLDialect, url: &Url) -> Url {
         let mut retval = url.clone();
         if dialect == SQLDialect::PostgreSQL {